@@ -3,18 +3,32 @@ use crate::netbox::{
     api::ApiClient,
     models::{
         Contact, Device, DeviceRole, DeviceType, Manufacturer, NetBoxIp4, NetBoxModel, Platform,
-        PostDevice, Site, Tag, VirtualMachine,
+        Site, Tag, VirtualMachine,
     },
 };
-use anyhow::{anyhow, Result};
+use crate::{
+    metrics,
+    naming::NamingPolicy,
+    report::{Outcome, RunReport},
+    utils::error_chain,
+};
+use anyhow::{anyhow, Context, Result};
 use dashmap::DashMap;
 use futures::{
     future::{join_all, BoxFuture},
     FutureExt,
 };
-use std::{any::type_name, sync::Arc};
-use tokio::task;
+use serde_json::Value;
+use std::{
+    any::type_name,
+    sync::{Arc, Mutex},
+};
+use tracing::{debug, error, info, instrument, trace, warn};
 
+/// NetBox objects by cache key (see [`NetBoxModel::get_cache_key`]).
+///
+/// Cloning is cheap and clones share the maps, so one cache can serve
+/// concurrent pushes. Objects created through `ensure_*` are added as they are made.
 #[derive(Debug, Clone)]
 pub struct LocalCache {
     pub devices: Arc<DashMap<String, Device>>,
@@ -27,9 +41,18 @@ pub struct LocalCache {
     pub platforms: Arc<DashMap<String, Platform>>,
     pub virtual_machines: Arc<DashMap<String, VirtualMachine>>,
     pub ipv4: Arc<DashMap<String, NetBoxIp4>>,
+    // keys devices, see [`LocalCache::with_naming`]
+    naming: Arc<NamingPolicy>,
+}
+
+impl Default for LocalCache {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl LocalCache {
+    /// An empty cache, see [`LocalCache::preload`] to fill it from NetBox
     pub fn new() -> Self {
         Self {
             devices: Arc::new(DashMap::new()),
@@ -42,14 +65,45 @@ impl LocalCache {
             platforms: Arc::new(DashMap::new()),
             virtual_machines: Arc::new(DashMap::new()),
             ipv4: Arc::new(DashMap::new()),
+            naming: Arc::new(NamingPolicy::default()),
+        }
+    }
+
+    /// Keys devices by `naming`, the way consolidated devices are keyed, so a
+    /// NetBox device named `pc01.corp.local` is found as `pc01` once the policy
+    /// strips `corp.local`. Devices already cached are keyed again; of two
+    /// ending up under one key, the one already named canonically wins.
+    pub fn with_naming(mut self, naming: NamingPolicy) -> Self {
+        self.naming = Arc::new(naming);
+        let mut devices: Vec<Device> = self.devices.iter().map(|d| d.value().clone()).collect();
+        devices.sort_by_key(|d| d.id);
+        self.devices.clear();
+        for device in devices {
+            let key = self.device_key(&device.name);
+            let canonical = device.name.to_lowercase() == key;
+            if let Some(kept) = self.devices.get(&key).map(|d| d.name.clone()) {
+                warn!(%key, kept = %kept, other = %device.name, "two NetBox devices share a name");
+                if !canonical {
+                    continue;
+                }
+            }
+            self.devices.insert(key, device);
         }
+        self
+    }
+
+    /// The key a device named `name` is cached under
+    pub fn device_key(&self, name: &str) -> String {
+        self.naming.key(name)
     }
 
-    pub async fn ensure_cached<T: NetBoxModel>(
+    /// Sets the id of `item` from `cache`, creating it in NetBox first when missing
+    pub async fn ensure_cached<T>(
         &self,
         item: &mut T,
         api: &ApiClient,
         cache: &Arc<DashMap<String, T>>,
+        report: &Mutex<RunReport>,
     ) -> Result<()>
     where
         T: NetBoxModel + 'static,
@@ -57,37 +111,41 @@ impl LocalCache {
         let slug = item.get_slug();
         let key = item.get_cache_key();
         let typename = type_name::<T>();
-        println!(
-            "🔍 [Cache] {} lookup slug=`{}`, key=`{}`",
-            typename, slug, key
-        );
+        trace!(model = typename, %slug, %key, "cache lookup");
 
-        if typename == "netbox_ingester::netbox::models::Platform" {
-            println!("Searching cache for Platform using `{}`", key)
-        }
         if let Some(cached) = cache.get(&key) {
             if let Some(id) = cached.get_id() {
-                println!("✅ [Cache] HIT  `{}` => id={}", key, id.to_string());
+                debug!(model = typename, %key, id = %id.to_string(), "cache hit");
+                metrics::cache_lookup(T::get_endpoint(), true);
                 item.set_id(id);
                 return Ok(());
             } else {
-                println!("⚠️ [Cache] HIT `{}` but no id set", key);
+                warn!(model = typename, %key, "cache hit without id");
             }
         } else {
-            println!("❌ [Cache] MISS `{}`", key);
+            debug!(model = typename, %key, "cache miss");
         }
+        metrics::cache_lookup(T::get_endpoint(), false);
 
         // CREATE in NetBox
-        let endpoint = format!("{}/", T::get_endpoint());
-        let created: T = api.post::<T, T>(&endpoint, item).await?;
-        let id = created.get_id().ok_or_else(|| {
-            anyhow!(
-                "Created item has no ID ({}): {:?}",
-                std::any::type_name::<T>(),
-                slug
-            )
-        })?;
-        println!("✨ [Cache] CREATED `{}` => id={}", key, id.to_string());
+        let created: T = match api.post::<T, T>(T::get_endpoint(), item).await {
+            Ok(created) => created,
+            Err(e) => {
+                report
+                    .lock()
+                    .unwrap()
+                    .record(T::get_endpoint(), Outcome::Failed);
+                return Err(e);
+            }
+        };
+        let id = created
+            .get_id()
+            .ok_or_else(|| anyhow!("Created item has no ID ({}): {:?}", typename, slug))?;
+        info!(model = typename, %key, id = %id.to_string(), "created");
+        report
+            .lock()
+            .unwrap()
+            .record(T::get_endpoint(), Outcome::Created);
 
         item.set_id(id.clone());
         cache.insert(key, created);
@@ -95,48 +153,84 @@ impl LocalCache {
     }
 
     // All the `ensure_*` methods
-    pub async fn ensure_tag(&self, tag: &mut Tag, api: &ApiClient) -> Result<()> {
-        self.ensure_cached(tag, api, &self.tags).await
+    pub async fn ensure_tag(
+        &self,
+        tag: &mut Tag,
+        api: &ApiClient,
+        report: &Mutex<RunReport>,
+    ) -> Result<()> {
+        self.ensure_cached(tag, api, &self.tags, report).await
     }
-    pub async fn ensure_manufacturer(&self, m: &mut Manufacturer, api: &ApiClient) -> Result<()> {
-        self.ensure_cached(m, api, &self.manufacturers).await
+    pub async fn ensure_manufacturer(
+        &self,
+        m: &mut Manufacturer,
+        api: &ApiClient,
+        report: &Mutex<RunReport>,
+    ) -> Result<()> {
+        self.ensure_cached(m, api, &self.manufacturers, report)
+            .await
     }
-    pub async fn ensure_device_type(&self, d: &mut DeviceType, api: &ApiClient) -> Result<()> {
-        self.ensure_manufacturer(&mut d.manufacturer, api).await?;
-        self.ensure_cached(d, api, &self.device_types).await
+    pub async fn ensure_device_type(
+        &self,
+        d: &mut DeviceType,
+        api: &ApiClient,
+        report: &Mutex<RunReport>,
+    ) -> Result<()> {
+        self.ensure_manufacturer(&mut d.manufacturer, api, report)
+            .await?;
+        self.ensure_cached(d, api, &self.device_types, report).await
     }
-    pub async fn ensure_platform(&self, p: &mut Platform, api: &ApiClient) -> Result<()> {
-        self.ensure_cached(p, api, &self.platforms).await
+    pub async fn ensure_platform(
+        &self,
+        p: &mut Platform,
+        api: &ApiClient,
+        report: &Mutex<RunReport>,
+    ) -> Result<()> {
+        if let Some(m) = &mut p.manufacturer {
+            self.ensure_manufacturer(m, api, report).await?;
+        }
+        self.ensure_cached(p, api, &self.platforms, report).await
     }
-    pub async fn ensure_role(&self, r: &mut DeviceRole, api: &ApiClient) -> Result<()> {
-        self.ensure_cached(r, api, &self.roles).await
+    pub async fn ensure_role(
+        &self,
+        r: &mut DeviceRole,
+        api: &ApiClient,
+        report: &Mutex<RunReport>,
+    ) -> Result<()> {
+        self.ensure_cached(r, api, &self.roles, report).await
     }
-    pub async fn ensure_site(&self, s: &mut Site, api: &ApiClient) -> Result<()> {
-        self.ensure_cached(s, api, &self.sites).await
+    pub async fn ensure_site(
+        &self,
+        s: &mut Site,
+        api: &ApiClient,
+        report: &Mutex<RunReport>,
+    ) -> Result<()> {
+        self.ensure_cached(s, api, &self.sites, report).await
     }
 
     pub async fn ensure_device_components(
         &self,
         device: &mut Device,
         api: &ApiClient,
+        report: &Mutex<RunReport>,
     ) -> Result<()> {
         let mut tasks: Vec<BoxFuture<'_, Result<()>>> = Vec::new();
 
         if let Some(ref mut dt) = device.device_type {
-            tasks.push(self.ensure_device_type(dt, api).boxed());
+            tasks.push(self.ensure_device_type(dt, api, report).boxed());
         }
         if let Some(ref mut role) = device.role {
-            tasks.push(self.ensure_role(role, api).boxed());
+            tasks.push(self.ensure_role(role, api, report).boxed());
         }
         if let Some(ref mut site) = device.site {
-            tasks.push(self.ensure_site(site, api).boxed());
+            tasks.push(self.ensure_site(site, api, report).boxed());
         }
         if let Some(ref mut platform) = device.platform {
-            tasks.push(self.ensure_platform(platform, api).boxed());
+            tasks.push(self.ensure_platform(platform, api, report).boxed());
         }
         if let Some(ref mut tags) = device.tags {
             for tag in tags.iter_mut() {
-                tasks.push(self.ensure_tag(tag, api).boxed());
+                tasks.push(self.ensure_tag(tag, api, report).boxed());
             }
         }
 
@@ -147,8 +241,41 @@ impl LocalCache {
         Ok(())
     }
 
-    pub async fn preload(client: Arc<ApiClient>) -> Result<Self, Box<dyn std::error::Error>> {
-        println!("Loading cache...");
+    /// Fills in ids of components the cache already knows, without creating anything.
+    /// Returns `endpoint:key` for every component NetBox doesn't have yet.
+    pub fn resolve_device_components(&self, device: &mut Device) -> Vec<String> {
+        let mut missing = Vec::new();
+
+        if let Some(ref mut dt) = device.device_type {
+            resolve(&mut dt.manufacturer, &self.manufacturers, &mut missing);
+            resolve(dt, &self.device_types, &mut missing);
+        }
+        if let Some(ref mut role) = device.role {
+            resolve(role, &self.roles, &mut missing);
+        }
+        if let Some(ref mut site) = device.site {
+            resolve(site, &self.sites, &mut missing);
+        }
+        if let Some(ref mut platform) = device.platform {
+            if let Some(ref mut m) = platform.manufacturer {
+                resolve(m, &self.manufacturers, &mut missing);
+            }
+            resolve(platform, &self.platforms, &mut missing);
+        }
+        if let Some(ref mut tags) = device.tags {
+            for tag in tags.iter_mut() {
+                resolve(tag, &self.tags, &mut missing);
+            }
+        }
+
+        missing
+    }
+
+    /// Loads every supported object type from NetBox. A type that fails to load
+    /// is logged and left empty instead of failing the whole preload.
+    #[instrument(name = "preload", skip_all)]
+    pub async fn preload(client: Arc<ApiClient>) -> Result<Self> {
+        info!("loading cache");
         let cache = Self::new();
         let mut tasks = Vec::new();
 
@@ -160,23 +287,20 @@ impl LocalCache {
                 tasks.push(tokio::spawn(async move {
                     match client.get::<$type>(ep, None).await {
                         Ok(list) => {
+                            let count = list.len();
                             for item in list {
                                 let key = item.get_cache_key();
                                 map.insert(key.clone(), item.clone());
                             }
-                            println!("✅ Cached {}s from {}", $label, ep);
+                            info!(model = $label, endpoint = ep, count, "cached");
                         }
                         Err(e) => {
-                            // Top‐level error
-                            eprintln!("❌ [Cache:{}] error loading `{}`: {}", $label, ep, e);
-                            // Full Debug dump (will include serde_json parse error with line/col)
-                            eprintln!("❌ Full error debug:\n{:#?}", e);
-                            // If you really want to walk the std::error::Error chain:
-                            let mut cause = std::error::Error::source(&e);
-                            while let Some(inner) = cause {
-                                eprintln!("    └─ caused by: {}", inner);
-                                cause = std::error::Error::source(inner);
-                            }
+                            error!(
+                                model = $label,
+                                endpoint = ep,
+                                error = %error_chain(e.as_ref()),
+                                "failed to preload"
+                            );
                         }
                     }
                 }));
@@ -219,12 +343,67 @@ impl LocalCache {
 
         let results = join_all(tasks).await;
         for (i, result) in results.into_iter().enumerate() {
-            if result.is_err() {
-                eprintln!("Error loading preload task {}: {:?}", i, result.err());
+            if let Err(e) = result {
+                error!(task = i, error = %e, "preload task panicked");
             }
         }
 
-        println!("Cache preload complete.");
+        info!("cache preload complete");
         Ok(cache)
     }
+
+    /// Applies a NetBox webhook: `object`, NetBox's representation of a
+    /// `model` (e.g. `device`), was created or updated, or deleted when
+    /// `deleted` is set. False for models the cache doesn't hold.
+    pub fn refresh(&self, model: &str, object: Value, deleted: bool) -> Result<bool> {
+        match model {
+            "device" => refresh(&self.devices, object, deleted, |d: &Device| {
+                self.device_key(&d.name)
+            }),
+            "devicetype" => refresh(&self.device_types, object, deleted, key),
+            "manufacturer" => refresh(&self.manufacturers, object, deleted, key),
+            "devicerole" => refresh(&self.roles, object, deleted, key),
+            "site" => refresh(&self.sites, object, deleted, key),
+            "tag" => refresh(&self.tags, object, deleted, key),
+            "platform" => refresh(&self.platforms, object, deleted, key),
+            "contact" => refresh(&self.contacts, object, deleted, key),
+            "virtualmachine" => refresh(&self.virtual_machines, object, deleted, key),
+            _ => return Ok(false),
+        }
+        .context(format!("Failed to parse the {} in the webhook", model))?;
+        Ok(true)
+    }
+}
+
+// Drops the cached object with the same id, renamed ones are cached under a new key
+fn refresh<T: NetBoxModel>(
+    cache: &DashMap<String, T>,
+    object: Value,
+    deleted: bool,
+    key: impl Fn(&T) -> String,
+) -> Result<()> {
+    let object: T = serde_json::from_value(object)?;
+    let id = object
+        .get_id()
+        .map(|id| id.to_string())
+        .ok_or_else(|| anyhow!("{} `{}` has no id", T::get_endpoint(), object.get_slug()))?;
+    cache.retain(|_, cached| cached.get_id().map(|id| id.to_string()).as_ref() != Some(&id));
+    if !deleted {
+        let key = key(&object);
+        debug!(endpoint = T::get_endpoint(), %key, "refreshed from webhook");
+        cache.insert(key, object);
+    }
+    Ok(())
+}
+
+fn key<T: NetBoxModel>(object: &T) -> String {
+    object.get_cache_key()
+}
+
+fn resolve<T: NetBoxModel>(item: &mut T, cache: &DashMap<String, T>, missing: &mut Vec<String>) {
+    let key = item.get_cache_key();
+    match cache.get(&key).and_then(|cached| cached.get_id()) {
+        Some(id) => item.set_id(id),
+        None => missing.push(format!("{}:{}", T::get_endpoint(), key)),
+    }
 }
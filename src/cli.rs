@@ -0,0 +1,529 @@
+use crate::{
+    cache::LocalCache,
+    config::{self, Settings},
+    consolidate, daemon,
+    netbox::{api::ApiClient, changes::RunChanges, delete::Deletions, models::Device},
+    ownership::Ownership,
+    reconcile,
+    report::{Conflict, RunReport},
+    sync::{self, ObjectKind, PlanAction, SourceKind, SyncOptions},
+    tags::TagPolicy,
+    undo::{self, UndoAction},
+};
+use ::config::Value;
+use anyhow::{Context, Result};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use serde::Serialize;
+use std::{collections::BTreeMap, path::PathBuf, sync::Arc};
+use tracing::{info_span, Instrument};
+
+#[derive(Debug, Parser)]
+#[command(
+    version,
+    about = "Collects inventory from Azure, FortiGate and NagiosXI and pushes it to NetBox"
+)]
+pub struct Cli {
+    /// Config file, defaults to $CONFIG_DIR/config.toml
+    #[arg(long, global = true)]
+    pub config: Option<PathBuf>,
+
+    /// Show what would change in NetBox without writing anything
+    #[arg(long, global = true)]
+    pub dry_run: bool,
+
+    /// Concurrent NetBox requests, overrides `netbox.api_limit`
+    #[arg(long, global = true)]
+    pub concurrency: Option<usize>,
+
+    /// Read source data from a snapshot directory instead of fetching it
+    #[arg(long, global = true, value_name = "DIR")]
+    pub from_snapshot: Option<PathBuf>,
+
+    /// Format of command output on stdout
+    #[arg(long, global = true, value_enum, default_value_t = OutputFormat::Text)]
+    pub output: OutputFormat,
+
+    /// Defaults to `sync` with all sources
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    Text,
+    Json,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Fetch sources, consolidate and push to NetBox
+    Sync(SyncArgs),
+    /// Fetch and consolidate, then list what a sync would change
+    Plan(SourceArgs),
+    /// Load the NetBox cache and show what it holds
+    Preload,
+    /// Show a consolidated device next to its NetBox counterpart
+    ShowDevice {
+        name: String,
+        #[command(flatten)]
+        sources: SourceArgs,
+    },
+    /// Load and check the configuration
+    ValidateConfig,
+    /// Mark devices no source reports anymore as offline
+    Reconcile(ReconcileArgs),
+    /// List what a run changed, from the NetBox changelog
+    Changes { run_id: String },
+    /// List how to revert what a run changed, and revert it with --apply
+    Undo(UndoArgs),
+    /// Keep running and sync every source on its own interval
+    Daemon,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct UndoArgs {
+    /// The run to revert, see `changes`
+    pub run_id: String,
+    /// Revert the changes instead of listing the steps
+    #[arg(long)]
+    pub apply: bool,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct SourceArgs {
+    /// Sources to fetch, all when omitted
+    #[arg(long, value_enum, value_delimiter = ',')]
+    pub source: Vec<SourceKind>,
+}
+
+impl SourceArgs {
+    fn sources(&self, settings: &Settings) -> Result<Vec<SourceKind>> {
+        if self.source.is_empty() {
+            return Ok(settings.sources());
+        }
+        if let Some(kind) = self.source.iter().find(|k| !settings.is_configured(**k)) {
+            anyhow::bail!(
+                "--source {}: [{}] is not configured or disabled",
+                kind,
+                kind
+            );
+        }
+        Ok(self.source.clone())
+    }
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct SyncArgs {
+    #[command(flatten)]
+    pub sources: SourceArgs,
+
+    /// Object kinds to sync, devices only when omitted
+    #[arg(long, value_enum, value_delimiter = ',')]
+    pub only: Vec<ObjectKind>,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct ReconcileArgs {
+    #[command(flatten)]
+    pub sources: SourceArgs,
+
+    /// Delete stale devices instead, within the `[delete]` limits
+    #[arg(long)]
+    pub delete: bool,
+}
+
+impl Cli {
+    // CLI flags are the top configuration layer
+    pub fn load_settings(&self) -> Result<Settings> {
+        let mut overrides = Vec::new();
+        if let Some(concurrency) = self.concurrency {
+            overrides.push(("netbox.api_limit", Value::from(concurrency as u64)));
+        }
+        if let Some(dir) = &self.from_snapshot {
+            overrides.push(("snapshot.replay", Value::from(dir.display().to_string())));
+        }
+        config::load(self.config.as_deref(), &overrides)
+    }
+}
+
+pub async fn run(cli: Cli, settings: Settings) -> Result<()> {
+    let command = cli.command.unwrap_or(Command::Sync(SyncArgs {
+        sources: SourceArgs { source: Vec::new() },
+        only: Vec::new(),
+    }));
+
+    // replayed data is stale by definition, so it never writes to NetBox
+    if let Some(dir) = &settings.snapshot.replay {
+        let writes = match &command {
+            Command::Sync(_) => !cli.dry_run,
+            Command::Reconcile(_) => !cli.dry_run,
+            Command::Daemon => !cli.dry_run,
+            _ => false,
+        };
+        if writes {
+            anyhow::bail!(
+                "--from-snapshot {} only works with plan, show-device and --dry-run",
+                dir.display()
+            );
+        }
+    }
+
+    match command {
+        Command::Sync(args) if cli.dry_run => {
+            // contacts are created without a plan, so a dry run can't preview them
+            if args.only.contains(&ObjectKind::Contacts) {
+                anyhow::bail!("--dry-run only plans devices, drop contacts from --only");
+            }
+            plan(&settings, &args.sources, cli.output).await
+        }
+        Command::Sync(args) => {
+            let mut options = SyncOptions {
+                sources: args.sources.sources(&settings)?,
+                ..SyncOptions::new(&settings)
+            };
+            if !args.only.is_empty() {
+                options.objects = args.only;
+            }
+            let report = sync::run_once(&settings, &options)
+                .instrument(info_span!("run"))
+                .await?;
+            match cli.output {
+                OutputFormat::Json => print_json(&report)?,
+                OutputFormat::Text => println!("run {}", report.run_id),
+            }
+            Ok(())
+        }
+        Command::Plan(args) => plan(&settings, &args, cli.output).await,
+        Command::Preload => preload(&settings, cli.output).await,
+        Command::ShowDevice { name, sources } => {
+            show_device(&settings, &name, &sources, cli.output).await
+        }
+        Command::ValidateConfig => {
+            match cli.output {
+                OutputFormat::Text => println!("configuration ok"),
+                OutputFormat::Json => print_json(&serde_json::json!({ "valid": true }))?,
+            }
+            Ok(())
+        }
+        Command::Reconcile(args) => reconcile(&settings, &args, cli.dry_run, cli.output).await,
+        Command::Changes { run_id } => changes(&settings, &run_id, cli.output).await,
+        Command::Undo(args) => undo(&settings, &args, cli.dry_run, cli.output).await,
+        Command::Daemon => daemon::run(settings, cli.dry_run).await,
+    }
+}
+
+async fn plan(settings: &Settings, args: &SourceArgs, output: OutputFormat) -> Result<()> {
+    let prepared = sync::prepare(settings, &args.sources(settings)?)
+        .instrument(info_span!("plan"))
+        .await?;
+    let devices = consolidate::devices(
+        &prepared.data,
+        &prepared.registry,
+        &prepared.normalizer,
+        &prepared.report,
+    );
+    let ownership = Ownership::new(&settings.ownership);
+    let tags = TagPolicy::new(
+        &settings.tags,
+        &prepared.registry,
+        &prepared.data.kinds(),
+        &ownership,
+    );
+    let plans = sync::plan(&devices, &prepared.local_cache, &ownership, &tags);
+
+    match output {
+        OutputFormat::Json => print_json(&plans)?,
+        OutputFormat::Text => {
+            let mut totals = BTreeMap::new();
+            for p in &plans {
+                *totals.entry(format!("{:?}", p.action)).or_insert(0) += 1;
+                if p.action == PlanAction::Unchanged {
+                    continue;
+                }
+                let action = format!("{:?}", p.action).to_lowercase();
+                println!("{:<9} {}", action, p.device);
+                for create in &p.creates {
+                    println!("{:<9}   + {}", "", create);
+                }
+            }
+            for (key, skipped) in &prepared.report.lock().unwrap().skipped {
+                *totals.entry("Skip".to_string()).or_insert(0) += 1;
+                println!("{:<9} {} ({})", "skip", key, skipped.reason);
+            }
+            println!();
+            for (action, count) in totals {
+                println!("{}: {}", action.to_lowercase(), count);
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn preload(settings: &Settings, output: OutputFormat) -> Result<()> {
+    let api = Arc::new(ApiClient::new(&settings.netbox));
+    let cache = LocalCache::preload(api).await?;
+    let counts = BTreeMap::from([
+        ("contacts", cache.contacts.len()),
+        ("device_types", cache.device_types.len()),
+        ("devices", cache.devices.len()),
+        ("manufacturers", cache.manufacturers.len()),
+        ("platforms", cache.platforms.len()),
+        ("roles", cache.roles.len()),
+        ("sites", cache.sites.len()),
+        ("tags", cache.tags.len()),
+        ("virtual_machines", cache.virtual_machines.len()),
+    ]);
+    match output {
+        OutputFormat::Json => print_json(&counts)?,
+        OutputFormat::Text => {
+            for (model, count) in counts {
+                println!("{:<17} {}", model, count);
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn show_device(
+    settings: &Settings,
+    name: &str,
+    args: &SourceArgs,
+    output: OutputFormat,
+) -> Result<()> {
+    let prepared = sync::prepare(settings, &args.sources(settings)?).await?;
+    let devices = consolidate::devices(
+        &prepared.data,
+        &prepared.registry,
+        &prepared.normalizer,
+        &prepared.report,
+    );
+    // keyed like the consolidated devices
+    let key = prepared.normalizer.naming.key(name);
+
+    let consolidated = devices.get(&key).map(|d| d.clone());
+    let netbox = prepared.local_cache.devices.get(&key).map(|d| d.clone());
+    if consolidated.is_none() && netbox.is_none() {
+        anyhow::bail!("No device named `{}` in the sources or NetBox", name);
+    }
+    let ownership = Ownership::new(&settings.ownership);
+    let tags = TagPolicy::new(
+        &settings.tags,
+        &prepared.registry,
+        &prepared.data.kinds(),
+        &ownership,
+    );
+    let plan = consolidated
+        .as_ref()
+        .map(|d| sync::plan_device(&key, d, &prepared.local_cache, &ownership, &tags));
+    let report = prepared.report.lock().unwrap();
+    let provenance = report.provenance.get(&key).cloned().unwrap_or_default();
+    let conflicts: Vec<Conflict> = report
+        .conflicts
+        .iter()
+        .filter(|c| c.device == key)
+        .cloned()
+        .collect();
+
+    #[derive(Serialize)]
+    struct Shown {
+        consolidated: Option<Device>,
+        // field -> source
+        provenance: BTreeMap<String, String>,
+        conflicts: Vec<Conflict>,
+        netbox: Option<Device>,
+        plan: Option<sync::DevicePlan>,
+    }
+    let shown = Shown {
+        consolidated,
+        provenance,
+        conflicts,
+        netbox,
+        plan,
+    };
+
+    match output {
+        OutputFormat::Json => print_json(&shown)?,
+        OutputFormat::Text => {
+            println!("== consolidated ==\n{:#?}", shown.consolidated);
+            println!("== provenance ==");
+            for (field, source) in &shown.provenance {
+                println!("{:<12} {}", field, source);
+            }
+            for c in &shown.conflicts {
+                println!(
+                    "{:<12} {} ({}) over {:?}",
+                    c.field, c.value, c.source, c.overridden
+                );
+            }
+            println!("== netbox ==\n{:#?}", shown.netbox);
+            println!("== plan ==\n{:#?}", shown.plan);
+        }
+    }
+    Ok(())
+}
+
+async fn reconcile(
+    settings: &Settings,
+    args: &ReconcileArgs,
+    dry_run: bool,
+    output: OutputFormat,
+) -> Result<()> {
+    let prepared = sync::prepare(settings, &args.sources.sources(settings)?)
+        .instrument(info_span!("reconcile"))
+        .await?;
+    let devices = consolidate::devices(
+        &prepared.data,
+        &prepared.registry,
+        &prepared.normalizer,
+        &prepared.report,
+    );
+    // devices of unavailable sources are left alone
+    let stale = reconcile::find_stale(
+        &prepared.local_cache,
+        &devices,
+        &prepared.available,
+        &Ownership::new(&settings.ownership),
+    );
+
+    let deletions = Deletions::new(&settings.delete);
+
+    if !dry_run && args.delete {
+        let deleted = reconcile::delete(
+            &stale,
+            &prepared.local_cache,
+            &prepared.netbox_client,
+            &deletions,
+            &prepared.report,
+        )
+        .await;
+        // the report shows what was refused or failed too
+        sync::finish(settings, &prepared.report)?;
+        deleted?;
+    } else if !dry_run {
+        reconcile::apply(&stale, &prepared.netbox_client, &prepared.report).await;
+        sync::finish(settings, &prepared.report)?;
+    }
+
+    match output {
+        OutputFormat::Json => print_json(&stale)?,
+        OutputFormat::Text => {
+            for device in &stale {
+                let id = device
+                    .netbox_id
+                    .map(|id| id.to_string())
+                    .unwrap_or_default();
+                let protected = args.delete
+                    && prepared
+                        .local_cache
+                        .devices
+                        .get(&device.device)
+                        .is_some_and(|d| deletions.is_protected(d.value()));
+                let action = match (args.delete, protected) {
+                    (true, true) => "protected",
+                    (true, false) => "delete",
+                    (false, _) => "stale",
+                };
+                println!("{:<9} {:<40} id={}", action, device.device, id);
+            }
+            println!("{} stale devices", stale.len());
+        }
+    }
+    Ok(())
+}
+
+async fn changes(settings: &Settings, run_id: &str, output: OutputFormat) -> Result<()> {
+    let api = ApiClient::new(&settings.netbox);
+    let run = RunChanges::fetch(&api, run_id).await?;
+
+    match output {
+        OutputFormat::Json => print_json(&run)?,
+        OutputFormat::Text => {
+            if run.changes.is_empty() {
+                println!("no changes recorded for run {}", run_id);
+                return Ok(());
+            }
+            for change in &run.changes {
+                let action = format!("{:?}", change.action()).to_lowercase();
+                println!(
+                    "{:<6} {:<30} {:<40} id={}",
+                    action,
+                    change.changed_object_type,
+                    change.object_repr,
+                    change.changed_object_id
+                );
+            }
+            println!();
+            for (object_type, counts) in &run.counts {
+                println!(
+                    "{}: {} created, {} updated, {} deleted",
+                    object_type, counts.created, counts.updated, counts.deleted
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn undo(
+    settings: &Settings,
+    args: &UndoArgs,
+    dry_run: bool,
+    output: OutputFormat,
+) -> Result<()> {
+    // the undo is a run of its own, so it can be undone too
+    let report = RunReport::shared();
+    let run_id = report.lock().unwrap().run_id.clone();
+    let api = ApiClient::new(&settings.netbox).for_run(&run_id);
+    let run = RunChanges::fetch(&api, &args.run_id).await?;
+    if run.changes.is_empty() {
+        anyhow::bail!("No changes recorded for run {}", args.run_id);
+    }
+    let deletions = Deletions::new(&settings.delete);
+    let steps = undo::plan(&api, &run, &deletions).await?;
+
+    let apply = args.apply && !dry_run;
+    if apply {
+        let applied = undo::apply(&steps, &api, &deletions, &report).await;
+        sync::finish(settings, &report)?;
+        applied?;
+    }
+
+    match output {
+        OutputFormat::Json => print_json(&steps)?,
+        OutputFormat::Text => {
+            for step in &steps {
+                let action = format!("{:?}", step.action).to_lowercase();
+                let fields = match (&step.action, &step.body) {
+                    (UndoAction::Restore, Some(serde_json::Value::Object(body))) => {
+                        format!(" {}", body.keys().cloned().collect::<Vec<_>>().join(","))
+                    }
+                    _ => String::new(),
+                };
+                let line = format!(
+                    "{:<30} {:<40} id={}{}",
+                    step.object_type, step.object, step.id, fields
+                );
+                match &step.skipped {
+                    Some(reason) => println!("{:<8} {} ({})", "skip", line, reason),
+                    None => println!("{:<8} {}", action, line),
+                }
+            }
+            println!();
+            if apply {
+                println!("undone by run {}", run_id);
+            } else {
+                println!(
+                    "nothing changed, pass --apply to revert run {}",
+                    args.run_id
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+fn print_json<T: Serialize>(value: &T) -> Result<()> {
+    let json = serde_json::to_string_pretty(value).context("Failed to serialize output")?;
+    println!("{}", json);
+    Ok(())
+}
@@ -1,50 +1,802 @@
-use config::{Config, ConfigError, Environment, File};
+use crate::{
+    normalize, ownership::DeviceField, source::Field, sync::SourceKind, utils::sanitize_slug,
+};
+use config::{Config, Environment, File, Value};
 use serde::Deserialize;
-use std::env;
-use std::path::Path;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    env, fmt, fs,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+};
+use tracing_subscriber::EnvFilter;
 
+// Prefix of environment overrides, e.g. NB_INGEST_NETBOX__API_KEY -> netbox.api_key
+pub const ENV_PREFIX: &str = "NB_INGEST";
+
+// Missing values are left empty here and reported by `validate`, so one run
+// lists every problem instead of stopping at the first missing field.
 #[derive(Debug, Deserialize)]
 pub struct Settings {
+    #[serde(default)]
     pub netbox: NetBoxConfig,
-    pub azure: AzureConfig,
-    pub fortigate: FortiGateConfig,
-    pub nagiosxi: NagiosxiConfig,
+    // a source without a section, or with `enabled = false`, is not fetched
+    pub azure: Option<AzureConfig>,
+    pub fortigate: Option<FortiGateConfig>,
+    pub nagiosxi: Option<NagiosxiConfig>,
+    // field -> sources in the order they win, overrides each source's default
+    #[serde(default)]
+    pub precedence: BTreeMap<Field, Vec<SourceKind>>,
+    #[serde(default)]
+    pub platforms: PlatformConfig,
+    #[serde(default)]
+    pub manufacturers: ManufacturerConfig,
+    #[serde(default)]
+    pub device_types: DeviceTypeConfig,
+    #[serde(default)]
+    pub fallback: FallbackConfig,
+    #[serde(default)]
+    pub naming: NamingConfig,
+    #[serde(default)]
+    pub logging: LoggingConfig,
+    #[serde(default)]
+    pub report: ReportConfig,
+    #[serde(default)]
+    pub snapshot: SnapshotConfig,
+    #[serde(default)]
+    pub delete: DeleteConfig,
+    #[serde(default)]
+    pub ownership: OwnershipConfig,
+    // tag name -> how the ingester creates the tag
+    #[serde(default)]
+    pub tags: BTreeMap<String, TagConfig>,
+    #[serde(default)]
+    pub metrics: MetricsConfig,
+    #[serde(default)]
+    pub daemon: DaemonConfig,
+    #[serde(default)]
+    pub webhooks: WebhooksConfig,
     // add eset, azure, foritgate, ...
 }
 
+#[derive(Debug, Deserialize, Default)]
+pub struct PlatformConfig {
+    // text custom field on dcim.device receiving the exact OS build
+    pub build_field: Option<String>,
+    // tried before the built-in rules
+    #[serde(default)]
+    pub rules: Vec<PlatformRuleConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PlatformRuleConfig {
+    // case-insensitive regex matched against "<os> <version>"
+    pub pattern: String,
+    // platform name, may refer to capture groups as $1
+    pub name: String,
+    pub manufacturer: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Default)]
+pub struct ManufacturerConfig {
+    // added to the built-in alias table
+    #[serde(default)]
+    pub aliases: Vec<ManufacturerAlias>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ManufacturerAlias {
+    pub name: String,
+    #[serde(default)]
+    pub aliases: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, Default)]
+pub struct DeviceTypeConfig {
+    // `device-types` directory of a devicetype-library checkout
+    pub library: Option<PathBuf>,
+    #[serde(default)]
+    pub aliases: Vec<DeviceTypeAlias>,
+    // values naming a kind of device rather than a model, e.g. "Windows PC",
+    // added to the built-in list
+    #[serde(default)]
+    pub ignore: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeviceTypeAlias {
+    // canonical manufacturer name
+    pub manufacturer: String,
+    pub model: String,
+    #[serde(default)]
+    pub aliases: Vec<String>,
+}
+
+// What happens to a consolidated device no source gave a device type
+#[derive(Debug, Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MissingTypeStrategy {
+    // leave it out of NetBox, listed in the report
+    #[default]
+    Skip,
+    // push it with the "Unknown <role>" device type
+    Unknown,
+    // like `unknown`, with status inventory
+    Inventory,
+    // leave it out and write it to the review queue
+    Review,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FallbackConfig {
+    #[serde(default)]
+    pub device_type: MissingTypeStrategy,
+    #[serde(default = "default_unknown_manufacturer")]
+    pub unknown_manufacturer: String,
+    // JSON, or CSV when the name ends in .csv
+    #[serde(default = "default_review_file")]
+    pub review_file: PathBuf,
+}
+
+impl Default for FallbackConfig {
+    fn default() -> Self {
+        Self {
+            device_type: MissingTypeStrategy::default(),
+            unknown_manufacturer: default_unknown_manufacturer(),
+            review_file: default_review_file(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NamingConfig {
+    // domain suffixes removed from FQDNs, e.g. "corp.example.com"
+    #[serde(default)]
+    pub strip_domains: Vec<String>,
+    // NetBox allows at most 64 characters
+    #[serde(default = "default_max_name_length")]
+    pub max_length: usize,
+    // regexes for names a device made up itself, replaced like bare MAC addresses
+    #[serde(default)]
+    pub generated: Vec<String>,
+}
+
+impl Default for NamingConfig {
+    fn default() -> Self {
+        Self {
+            strip_domains: Vec::new(),
+            max_length: default_max_name_length(),
+            generated: Vec::new(),
+        }
+    }
+}
+
+fn default_max_name_length() -> usize {
+    64
+}
+
+fn default_unknown_manufacturer() -> String {
+    "Unknown".into()
+}
+
+fn default_review_file() -> PathBuf {
+    "review_queue.json".into()
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LoggingConfig {
+    #[serde(default = "default_log_level")]
+    pub level: String,
+    #[serde(default)]
+    pub format: LogFormat,
+    pub file: Option<PathBuf>,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            level: default_log_level(),
+            format: LogFormat::default(),
+            file: None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReportConfig {
+    #[serde(default = "default_report_json")]
+    pub json: Option<PathBuf>,
+    pub markdown: Option<PathBuf>,
+}
+
+impl Default for ReportConfig {
+    fn default() -> Self {
+        Self {
+            json: default_report_json(),
+            markdown: None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Default)]
+pub struct SnapshotConfig {
+    // every run writes the raw source data to <dir>/<UTC timestamp>/<source>.json
+    pub dir: Option<PathBuf>,
+    // newest snapshots kept in `dir`, all when unset
+    pub keep: Option<usize>,
+    // snapshot to read instead of fetching, set by --from-snapshot
+    pub replay: Option<PathBuf>,
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct TagConfig {
+    // six hex digits, e.g. "ff9800"
+    pub color: Option<String>,
+    pub description: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct OwnershipConfig {
+    // stamped on every device the ingester creates, which it then fully owns
+    #[serde(default = "default_managed_tag")]
+    pub managed_tag: String,
+    // devices with any of these tags are never updated
+    #[serde(default = "default_lock_tags")]
+    pub lock_tags: Vec<String>,
+    // the only fields updated on devices without the managed tag
+    #[serde(default = "default_curated_fields")]
+    pub curated_fields: Vec<DeviceField>,
+}
+
+fn default_managed_tag() -> String {
+    "managed-by-ingester".into()
+}
+
+fn default_lock_tags() -> Vec<String> {
+    vec!["locked".into(), "manual".into()]
+}
+
+fn default_curated_fields() -> Vec<DeviceField> {
+    vec![
+        DeviceField::Serial,
+        DeviceField::Status,
+        DeviceField::Tags,
+        DeviceField::CustomFields,
+    ]
+}
+
+impl Default for OwnershipConfig {
+    fn default() -> Self {
+        Self {
+            managed_tag: default_managed_tag(),
+            lock_tags: default_lock_tags(),
+            curated_fields: default_curated_fields(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct DeleteConfig {
+    // most objects one run deletes, a run that would delete more deletes nothing
+    #[serde(default = "default_max_deletes")]
+    pub max_per_run: usize,
+    // tag slugs that keep an object from ever being deleted by the ingester
+    #[serde(default)]
+    pub protect_tags: Vec<String>,
+}
+
+fn default_max_deletes() -> usize {
+    20
+}
+
+impl Default for DeleteConfig {
+    fn default() -> Self {
+        Self {
+            max_per_run: default_max_deletes(),
+            protect_tags: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Default)]
+pub struct MetricsConfig {
+    // node_exporter textfile collector target, written after each run
+    pub textfile: Option<PathBuf>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DaemonConfig {
+    // serves /health, /metrics and the webhooks when set
+    pub listen: Option<SocketAddr>,
+    #[serde(default = "default_interval_secs")]
+    pub default_interval_secs: u64,
+}
+
+impl Default for DaemonConfig {
+    fn default() -> Self {
+        Self {
+            listen: None,
+            default_interval_secs: default_interval_secs(),
+        }
+    }
+}
+
+fn default_interval_secs() -> u64 {
+    900
+}
+
+// Daemon mode only, served on `daemon.listen`. A route is enabled by its section.
+#[derive(Debug, Deserialize, Default)]
+pub struct WebhooksConfig {
+    // NetBox webhooks posted to /webhooks/netbox update the cache
+    pub netbox: Option<WebhookConfig>,
+    // events posted to /webhooks/sync sync a single device
+    pub events: Option<WebhookConfig>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct WebhookConfig {
+    // HMAC key the payloads are signed with
+    #[serde(default)]
+    pub secret: String,
+    pub secret_file: Option<PathBuf>,
+}
+
+fn default_report_json() -> Option<PathBuf> {
+    Some("run_report.json".into())
+}
+
+fn default_log_level() -> String {
+    "info".into()
+}
+
+#[derive(Debug, Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Pretty,
+    Json,
+}
+
+fn default_api_limit() -> usize {
+    10
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn default_fortigate_cert() -> PathBuf {
+    "certs/FortiGate.crt".into()
+}
+
+fn default_graph_url() -> String {
+    "https://graph.microsoft.com/v1.0".into()
+}
+
+fn default_login_url() -> String {
+    "https://login.microsoftonline.com".into()
+}
+
+// Secrets may be given inline or as `<name>_file` pointing at e.g. a mounted
+// secret; the file wins and is read into the inline field while loading.
 #[derive(Debug, Deserialize)]
 pub struct NetBoxConfig {
+    #[serde(default)]
     pub api_key: String,
+    pub api_key_file: Option<PathBuf>,
+    #[serde(default)]
     pub api_url: String,
+    #[serde(default = "default_api_limit")]
     pub api_limit: usize,
 }
 
-#[derive(Debug, Deserialize)]
+impl Default for NetBoxConfig {
+    fn default() -> Self {
+        Self {
+            api_key: String::new(),
+            api_key_file: None,
+            api_url: String::new(),
+            api_limit: default_api_limit(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
 pub struct AzureConfig {
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    #[serde(default)]
     pub client_id: String,
+    #[serde(default)]
     pub client_secret: String,
+    pub client_secret_file: Option<PathBuf>,
+    #[serde(default)]
     pub tenant_id: String,
+    #[serde(default = "default_graph_url")]
     pub url: String,
+    // OAuth endpoint the token is requested from, the tenant id is appended
+    #[serde(default = "default_login_url")]
+    pub login_url: String,
+    // daemon mode only, falls back to `daemon.default_interval_secs`
+    pub interval_secs: Option<u64>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Clone)]
 pub struct FortiGateConfig {
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    #[serde(default)]
     pub url: String,
+    #[serde(default)]
     pub token: String,
+    pub token_file: Option<PathBuf>,
+    // root certificate of the FortiGate, PEM
+    #[serde(default = "default_fortigate_cert")]
+    pub ca_cert: PathBuf,
+    pub interval_secs: Option<u64>,
+    #[serde(default)]
+    pub filter: FortiGateFilterConfig,
 }
 
-#[derive(Debug, Deserialize)]
+// Drops transient clients before they reach consolidation
+#[derive(Debug, Deserialize, Clone)]
+pub struct FortiGateFilterConfig {
+    // randomized MACs, as used by phones for privacy
+    #[serde(default = "default_enabled")]
+    pub locally_administered: bool,
+    // offline devices not seen for longer than this
+    pub max_offline_days: Option<u64>,
+    // interfaces guests connect through, e.g. the guest SSID
+    #[serde(default)]
+    pub guest_interfaces: Vec<String>,
+    // case-insensitive regexes
+    #[serde(default)]
+    pub exclude_hostnames: Vec<String>,
+    #[serde(default)]
+    pub exclude_macs: Vec<String>,
+}
+
+impl Default for FortiGateFilterConfig {
+    fn default() -> Self {
+        Self {
+            locally_administered: true,
+            max_offline_days: None,
+            guest_interfaces: Vec::new(),
+            exclude_hostnames: Vec::new(),
+            exclude_macs: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
 pub struct NagiosxiConfig {
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    #[serde(default)]
     pub api_key: String,
+    pub api_key_file: Option<PathBuf>,
+    #[serde(default)]
     pub url: String,
+    pub interval_secs: Option<u64>,
 }
 
-pub fn load() -> Result<Settings, ConfigError> {
-    let config_dir = env::var("CONFIG_DIR").unwrap_or_else(|_| "./src".into());
+impl Settings {
+    // Configured and enabled sources, in fetch order
+    pub fn sources(&self) -> Vec<SourceKind> {
+        SourceKind::ALL
+            .into_iter()
+            .filter(|kind| self.is_configured(*kind))
+            .collect()
+    }
+
+    pub fn is_configured(&self, kind: SourceKind) -> bool {
+        match kind {
+            SourceKind::Azure => self.azure.as_ref().is_some_and(|c| c.enabled),
+            SourceKind::FortiGate => self.fortigate.as_ref().is_some_and(|c| c.enabled),
+            SourceKind::NagiosXi => self.nagiosxi.as_ref().is_some_and(|c| c.enabled),
+        }
+    }
+
+    pub fn interval_secs(&self, kind: SourceKind) -> u64 {
+        let interval = match kind {
+            SourceKind::Azure => self.azure.as_ref().and_then(|c| c.interval_secs),
+            SourceKind::FortiGate => self.fortigate.as_ref().and_then(|c| c.interval_secs),
+            SourceKind::NagiosXi => self.nagiosxi.as_ref().and_then(|c| c.interval_secs),
+        };
+        interval.unwrap_or(self.daemon.default_interval_secs)
+    }
+
+    // Reads `*_file` secrets into their inline fields
+    fn resolve_secrets(&mut self, problems: &mut Vec<String>) {
+        resolve_secret(
+            "netbox.api_key",
+            &mut self.netbox.api_key,
+            &self.netbox.api_key_file,
+            problems,
+        );
+        if let Some(azure) = self.azure.as_mut().filter(|c| c.enabled) {
+            resolve_secret(
+                "azure.client_secret",
+                &mut azure.client_secret,
+                &azure.client_secret_file,
+                problems,
+            );
+        }
+        if let Some(fortigate) = self.fortigate.as_mut().filter(|c| c.enabled) {
+            resolve_secret(
+                "fortigate.token",
+                &mut fortigate.token,
+                &fortigate.token_file,
+                problems,
+            );
+        }
+        if let Some(nagiosxi) = self.nagiosxi.as_mut().filter(|c| c.enabled) {
+            resolve_secret(
+                "nagiosxi.api_key",
+                &mut nagiosxi.api_key,
+                &nagiosxi.api_key_file,
+                problems,
+            );
+        }
+        if let Some(netbox) = self.webhooks.netbox.as_mut() {
+            resolve_secret(
+                "webhooks.netbox.secret",
+                &mut netbox.secret,
+                &netbox.secret_file,
+                problems,
+            );
+        }
+        if let Some(events) = self.webhooks.events.as_mut() {
+            resolve_secret(
+                "webhooks.events.secret",
+                &mut events.secret,
+                &events.secret_file,
+                problems,
+            );
+        }
+    }
+
+    fn validate(&self, problems: &mut Vec<String>) {
+        check_url("netbox.api_url", &self.netbox.api_url, problems);
+        if self.netbox.api_limit == 0 {
+            problems.push("netbox.api_limit: must be at least 1".into());
+        }
+
+        if let Some(azure) = self.azure.as_ref().filter(|c| c.enabled) {
+            check_url("azure.url", &azure.url, problems);
+            check_url("azure.login_url", &azure.login_url, problems);
+            check_set("azure.client_id", &azure.client_id, problems);
+            check_set("azure.tenant_id", &azure.tenant_id, problems);
+            check_interval("azure.interval_secs", azure.interval_secs, problems);
+        }
+        if let Some(fortigate) = self.fortigate.as_ref().filter(|c| c.enabled) {
+            check_url("fortigate.url", &fortigate.url, problems);
+            check_interval("fortigate.interval_secs", fortigate.interval_secs, problems);
+            let filter = &fortigate.filter;
+            for (field, patterns) in [
+                ("exclude_hostnames", &filter.exclude_hostnames),
+                ("exclude_macs", &filter.exclude_macs),
+            ] {
+                for (i, pattern) in patterns.iter().enumerate() {
+                    if let Err(e) = normalize::compile(pattern) {
+                        problems.push(format!("fortigate.filter.{}[{}]: {}", field, i, e));
+                    }
+                }
+            }
+        }
+        if let Some(nagiosxi) = self.nagiosxi.as_ref().filter(|c| c.enabled) {
+            check_url("nagiosxi.url", &nagiosxi.url, problems);
+            check_interval("nagiosxi.interval_secs", nagiosxi.interval_secs, problems);
+        }
+        if self.sources().is_empty() {
+            problems.push(
+                "no source enabled, configure at least one of [azure], [fortigate], [nagiosxi]"
+                    .into(),
+            );
+        }
+
+        for (field, sources) in &self.precedence {
+            let mut seen = BTreeSet::new();
+            for kind in sources {
+                if !seen.insert(kind) {
+                    problems.push(format!("precedence.{}: {} is listed twice", field, kind));
+                }
+            }
+        }
+
+        for (i, rule) in self.platforms.rules.iter().enumerate() {
+            if let Err(e) = normalize::compile(&rule.pattern) {
+                problems.push(format!("platforms.rules[{}].pattern: {}", i, e));
+            }
+            check_set(
+                &format!("platforms.rules[{}].name", i),
+                &rule.name,
+                problems,
+            );
+        }
+        if let Some(field) = &self.platforms.build_field {
+            check_set("platforms.build_field", field, problems);
+        }
+        for (i, alias) in self.manufacturers.aliases.iter().enumerate() {
+            check_set(
+                &format!("manufacturers.aliases[{}].name", i),
+                &alias.name,
+                problems,
+            );
+        }
+        for (i, alias) in self.device_types.aliases.iter().enumerate() {
+            let field = format!("device_types.aliases[{}]", i);
+            check_set(
+                &format!("{}.manufacturer", field),
+                &alias.manufacturer,
+                problems,
+            );
+            check_set(&format!("{}.model", field), &alias.model, problems);
+        }
+        for (name, tag) in &self.tags {
+            let valid = |c: &String| c.len() == 6 && c.chars().all(|c| c.is_ascii_hexdigit());
+            if let Some(color) = tag.color.as_ref().filter(|c| !valid(c)) {
+                problems.push(format!(
+                    "tags.{}.color: `{}` is not six hex digits",
+                    name, color
+                ));
+            }
+        }
+        check_set(
+            "ownership.managed_tag",
+            &self.ownership.managed_tag,
+            problems,
+        );
+        let managed = sanitize_slug(&self.ownership.managed_tag);
+        if self
+            .ownership
+            .lock_tags
+            .iter()
+            .any(|t| sanitize_slug(t) == managed)
+        {
+            problems.push("ownership.lock_tags: contains the managed tag".into());
+        }
+        check_set(
+            "fallback.unknown_manufacturer",
+            &self.fallback.unknown_manufacturer,
+            problems,
+        );
+        if let Some(library) = &self.device_types.library {
+            if !library.is_dir() {
+                problems.push(format!(
+                    "device_types.library: {} is not a directory",
+                    library.display()
+                ));
+            }
+        }
+
+        for (i, domain) in self.naming.strip_domains.iter().enumerate() {
+            check_set(&format!("naming.strip_domains[{}]", i), domain, problems);
+        }
+        if !(8..=64).contains(&self.naming.max_length) {
+            problems.push("naming.max_length: must be between 8 and 64".into());
+        }
+        for (i, pattern) in self.naming.generated.iter().enumerate() {
+            if let Err(e) = normalize::compile(pattern) {
+                problems.push(format!("naming.generated[{}]: {}", i, e));
+            }
+        }
+
+        if self.snapshot.keep == Some(0) {
+            problems.push("snapshot.keep: must be at least 1".into());
+        }
+        if let Some(replay) = &self.snapshot.replay {
+            if !replay.is_dir() {
+                problems.push(format!(
+                    "snapshot.replay: {} is not a directory",
+                    replay.display()
+                ));
+            }
+        }
+
+        if let Err(e) = EnvFilter::try_new(&self.logging.level) {
+            problems.push(format!("logging.level: {}", e));
+        }
+        check_interval(
+            "daemon.default_interval_secs",
+            Some(self.daemon.default_interval_secs),
+            problems,
+        );
+        let webhooks = self.webhooks.netbox.is_some() || self.webhooks.events.is_some();
+        if webhooks && self.daemon.listen.is_none() {
+            problems.push("webhooks: served on daemon.listen, which is not set".into());
+        }
+    }
+}
+
+fn resolve_secret(
+    field: &str,
+    value: &mut String,
+    file: &Option<PathBuf>,
+    problems: &mut Vec<String>,
+) {
+    if let Some(file) = file {
+        match fs::read_to_string(file) {
+            Ok(secret) => *value = secret.trim().to_string(),
+            Err(e) => problems.push(format!(
+                "{}_file: cannot read {}: {}",
+                field,
+                file.display(),
+                e
+            )),
+        }
+    } else if value.trim().is_empty() {
+        problems.push(format!("{}: required, set it or {}_file", field, field));
+    }
+}
+
+fn check_url(field: &str, value: &str, problems: &mut Vec<String>) {
+    if value.trim().is_empty() {
+        problems.push(format!("{}: required", field));
+    } else if let Err(e) = url::Url::parse(value) {
+        problems.push(format!("{}: invalid URL `{}`: {}", field, value, e));
+    }
+}
+
+fn check_set(field: &str, value: &str, problems: &mut Vec<String>) {
+    if value.trim().is_empty() {
+        problems.push(format!("{}: required", field));
+    }
+}
+
+fn check_interval(field: &str, value: Option<u64>, problems: &mut Vec<String>) {
+    if value == Some(0) {
+        problems.push(format!("{}: must be at least 1", field));
+    }
+}
+
+// Every problem found in the configuration, reported together
+#[derive(Debug)]
+pub struct InvalidConfig(pub Vec<String>);
+
+impl fmt::Display for InvalidConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "invalid configuration:")?;
+        for problem in &self.0 {
+            writeln!(f, "  - {}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for InvalidConfig {}
+
+// Layers, lowest to highest precedence: built-in defaults, the config file,
+// NB_INGEST_* environment variables and finally `overrides` (CLI flags).
+pub fn load(path: Option<&Path>, overrides: &[(&str, Value)]) -> anyhow::Result<Settings> {
+    // An explicit --config must exist; the default location is optional so a
+    // deployment can be configured through the environment alone.
+    let file = match path {
+        Some(path) => File::from(path).required(true),
+        None => {
+            let config_dir = env::var("CONFIG_DIR").unwrap_or_else(|_| "./src".into());
+            File::from(Path::new(&config_dir).join("config.toml")).required(false)
+        }
+    };
+
+    let mut builder = Config::builder().add_source(file).add_source(
+        Environment::with_prefix(ENV_PREFIX)
+            .prefix_separator("_")
+            .separator("__")
+            .try_parsing(true),
+    );
+    for (key, value) in overrides {
+        builder = builder.set_override(*key, value.clone())?;
+    }
 
-    let config = Config::builder()
-        .add_source(File::with_name(&format!("{}/config.toml", config_dir)))
-        .build()?;
+    let mut settings: Settings = builder.build()?.try_deserialize()?;
 
-    config.try_deserialize()
+    let mut problems = Vec::new();
+    settings.resolve_secrets(&mut problems);
+    settings.validate(&mut problems);
+    if !problems.is_empty() {
+        return Err(InvalidConfig(problems).into());
+    }
+    Ok(settings)
 }
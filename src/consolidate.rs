@@ -1,6 +1,211 @@
-fn normalize_mac(mac: &str) -> String {
-    mac.to_lowercase()
-        .chars()
-        .filter(|c| c.is_ascii_hexdigit())
-        .collect()
+use crate::{
+    config::MissingTypeStrategy,
+    netbox::models::{
+        Device, DeviceRole, Manufacturer, NetBoxIp4, NetBoxModel, Platform, Site, Status,
+        StatusOptions, Tag,
+    },
+    normalize::Normalizer,
+    report::{Conflict, RunReport},
+    source::{Field, Record, Registry, Source},
+    sync::SourceData,
+};
+use dashmap::DashMap;
+use serde_json::Value;
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Mutex,
+};
+use tracing::{debug, info};
+
+const DEFAULT_ROLE: &str = "Desktop";
+const DEFAULT_SITE: &str = "TOS";
+// provenance of values set by the missing device type strategy
+const FALLBACK: &str = "fallback";
+
+// Merges the records of every source into one device per name. Each field is
+// taken from the best ranked source that has a value (see `Registry::rank`),
+// the values it overrode are logged and kept in the report as conflicts.
+pub fn devices(
+    data: &SourceData,
+    registry: &Registry,
+    normalizer: &Normalizer,
+    report: &Mutex<RunReport>,
+) -> DashMap<String, Device> {
+    let mut r = report.lock().unwrap();
+    let mut sources: Vec<&dyn Source> = Vec::new();
+    let mut records: Vec<Record> = Vec::new();
+    for source in registry.iter() {
+        let fetched = data.records(source.kind());
+        for record in fetched {
+            let mut record = record.clone();
+            normalizer.record(&mut record, &mut r);
+            records.push(record);
+            sources.push(source);
+        }
+        debug!(source = %source.kind(), records = fetched.len(), "normalized records");
+    }
+    // across sources, so records sharing a key below are one device
+    normalizer.naming.dedupe(&mut records);
+
+    let mut grouped: BTreeMap<String, Vec<(&dyn Source, &Record)>> = BTreeMap::new();
+    for (source, record) in sources.iter().zip(&records) {
+        grouped
+            .entry(record.key())
+            .or_default()
+            .push((*source, record));
+    }
+
+    let devices = DashMap::<String, Device>::new();
+    for (key, contributors) in grouped {
+        if !contributors.iter().any(|(source, _)| source.adds_devices()) {
+            continue;
+        }
+        // every source ranks the name, so a device without one can't happen
+        if let Some(device) = merge(&key, &contributors, registry, normalizer, &mut r) {
+            devices.insert(key, device);
+        }
+    }
+    info!(
+        count = devices.len(),
+        conflicts = r.conflicts.len(),
+        skipped = r.skipped.len(),
+        "consolidated devices"
+    );
+
+    devices
+}
+
+fn merge(
+    key: &str,
+    contributors: &[(&dyn Source, &Record)],
+    registry: &Registry,
+    normalizer: &Normalizer,
+    report: &mut RunReport,
+) -> Option<Device> {
+    let mut chosen: HashMap<Field, &Record> = HashMap::new();
+    for field in Field::ALL {
+        let mut ranked: Vec<_> = contributors
+            .iter()
+            .filter_map(|(source, record)| {
+                let rank = registry.rank(*source, field)?;
+                let value = field.value(record)?;
+                Some((rank, source.kind(), *record, value))
+            })
+            .collect();
+        // stable, so equal ranks keep fetch order
+        ranked.sort_by_key(|(rank, ..)| *rank);
+
+        let Some((_, winner, record, value)) = ranked.first() else {
+            continue;
+        };
+        report.provenance(key, field.name(), winner.name());
+        chosen.insert(field, record);
+
+        let overridden: BTreeMap<String, String> = ranked[1..]
+            .iter()
+            .filter(|(.., other)| !other.trim().eq_ignore_ascii_case(value.trim()))
+            .map(|(_, kind, _, other)| (kind.name().to_string(), other.clone()))
+            .collect();
+        if !overridden.is_empty() {
+            info!(
+                device = %key,
+                %field,
+                source = %winner,
+                value = %value,
+                overridden = ?overridden,
+                "sources disagree"
+            );
+            report.conflict(Conflict {
+                device: key.to_string(),
+                field: field.name().to_string(),
+                source: winner.name().to_string(),
+                value: value.clone(),
+                overridden,
+            });
+        }
+    }
+
+    let field = |field: Field| chosen.get(&field).copied();
+    let platform = field(Field::Platform)
+        .and_then(|r| normalizer.platform(r.platform.as_deref()?, r.os_version.as_deref()));
+    let mut custom_fields = BTreeMap::new();
+    if let (Some(build_field), Some(build)) = (
+        &normalizer.build_field,
+        platform.as_ref().and_then(|p| p.build.clone()),
+    ) {
+        custom_fields.insert(build_field.clone(), Value::String(build));
+    }
+
+    let mut device = Device {
+        name: field(Field::Name)?.name.clone(),
+        id: None,
+        device_type: field(Field::DeviceType).and_then(|r| {
+            Some(normalizer.device_type(r.manufacturer.as_deref()?, r.model.as_deref()?))
+        }),
+        role: Some(DeviceRole::new(DEFAULT_ROLE.to_string())),
+        site: Some(Site::new(
+            field(Field::Site)
+                .and_then(|r| r.site.clone())
+                .unwrap_or_else(|| DEFAULT_SITE.to_string()),
+        )),
+        status: field(Field::Status)
+            .and_then(|r| r.status.clone())
+            .map(Status::from_value),
+        serial: field(Field::Serial).and_then(|r| r.serial.clone()),
+        platform: platform.map(|p| Platform {
+            manufacturer: p.manufacturer.map(Manufacturer::new),
+            ..Platform::new(p.name)
+        }),
+        primary_ip4: field(Field::PrimaryIp4)
+            .and_then(|r| r.primary_ip4.clone())
+            .map(NetBoxIp4::new),
+        tags: None,
+        custom_fields,
+    };
+
+    if device.device_type.is_none() {
+        let reason = "no device type from any source";
+        let records = || {
+            contributors
+                .iter()
+                .map(|(source, record)| (source.kind().name().to_string(), record.raw.clone()))
+                .collect()
+        };
+        match normalizer.missing_type {
+            MissingTypeStrategy::Skip => {
+                debug!(device = %key, "skipping: {}", reason);
+                report.skip_with_records(Device::get_endpoint(), key, reason, records());
+                return None;
+            }
+            MissingTypeStrategy::Review => {
+                debug!(device = %key, "queued for review: {}", reason);
+                report.skip_with_records(
+                    Device::get_endpoint(),
+                    key,
+                    format!("{}, queued for review", reason),
+                    records(),
+                );
+                report.review.push(key.to_string());
+                return None;
+            }
+            MissingTypeStrategy::Unknown | MissingTypeStrategy::Inventory => {
+                device.device_type = Some(normalizer.unknown_device_type(DEFAULT_ROLE));
+                report.provenance(key, Field::DeviceType.name(), FALLBACK);
+                if normalizer.missing_type == MissingTypeStrategy::Inventory {
+                    device.status = Some(Status::from_value(StatusOptions::Inventory));
+                    report.provenance(key, Field::Status.name(), FALLBACK);
+                }
+            }
+        }
+    }
+
+    for (source, record) in contributors {
+        if let Some(tag) = source.kind().tag() {
+            device.push_tag(Tag::new(tag.to_string()));
+        }
+        for tag in &record.tags {
+            device.push_tag(Tag::new(tag.clone()));
+        }
+    }
+    Some(device)
 }
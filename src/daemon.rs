@@ -0,0 +1,257 @@
+use crate::{
+    cache::LocalCache,
+    config::Settings,
+    consolidate, metrics,
+    netbox::api::ApiClient,
+    normalize::Normalizer,
+    ownership::Ownership,
+    report::RunReport,
+    snapshot::Snapshots,
+    source::Registry,
+    sync::{self, PlanAction, SourceData, SourceKind, SourceRun},
+    tags::TagPolicy,
+    webhooks,
+};
+use anyhow::{Context, Result};
+use axum::{extract::State, http::StatusCode, routing::get, Json, Router};
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{
+    sync::{watch, Mutex},
+    time::Instant,
+};
+use tracing::{error, info, info_span, warn, Instrument};
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SourceHealth {
+    pub last_attempt: Option<DateTime<Utc>>,
+    pub last_success: Option<DateTime<Utc>>,
+    pub records: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+pub type Health = Arc<DashMap<String, SourceHealth>>;
+
+// State that lives for the whole daemon run, shared with the webhooks
+pub(crate) struct Worker {
+    pub(crate) settings: Settings,
+    // plans instead of writing, see `--dry-run`
+    pub(crate) dry_run: bool,
+    pub(crate) registry: Registry,
+    pub(crate) normalizer: Normalizer,
+    pub(crate) netbox_client: Arc<ApiClient>,
+    pub(crate) local_cache: LocalCache,
+    pub(crate) snapshots: Snapshots,
+    pub(crate) health: Health,
+    // held for a whole cycle or event, so the two never overlap
+    pub(crate) data: Mutex<SourceData>,
+}
+
+impl Worker {
+    // Updates /health with the outcome of each fetch
+    pub(crate) fn record(&self, runs: Vec<SourceRun>) {
+        for run in runs {
+            let mut entry = self.health.entry(run.kind.name().to_string()).or_default();
+            entry.last_attempt = Some(Utc::now());
+            if run.error.is_none() {
+                entry.last_success = entry.last_attempt;
+                entry.records = run.records;
+            }
+            entry.error = run.error;
+        }
+    }
+}
+
+// Long-running mode: every source is refetched on its own interval and the cache
+// stays warm between cycles. Cycles run one after another on this task, so a slow
+// cycle delays the next one instead of overlapping with it. A dry run only logs
+// what each cycle would change.
+pub async fn run(settings: Settings, dry_run: bool) -> Result<()> {
+    let (shutdown_tx, mut shutdown) = watch::channel(false);
+    tokio::spawn(async move {
+        shutdown_signal().await;
+        info!("shutdown requested, finishing in-flight work");
+        let _ = shutdown_tx.send(true);
+    });
+
+    let netbox_client = Arc::new(ApiClient::new(&settings.netbox));
+    let normalizer = Normalizer::new(&settings)?;
+    let worker = Arc::new(Worker {
+        registry: Registry::new(&settings),
+        local_cache: LocalCache::preload(netbox_client.clone())
+            .await?
+            .with_naming(normalizer.naming.clone()),
+        normalizer,
+        snapshots: Snapshots::new(&settings.snapshot),
+        netbox_client,
+        health: Arc::new(DashMap::new()),
+        data: Mutex::new(SourceData::default()),
+        settings,
+        dry_run,
+    });
+    let settings = &worker.settings;
+    let server = settings
+        .daemon
+        .listen
+        .map(|addr| tokio::spawn(serve(addr, worker.clone(), shutdown.clone())));
+
+    let now = Instant::now();
+    let mut next_due: HashMap<SourceKind, Instant> = settings
+        .sources()
+        .into_iter()
+        .map(|kind| (kind, now))
+        .collect();
+
+    while !*shutdown.borrow() {
+        let now = Instant::now();
+        let mut due: Vec<SourceKind> = next_due
+            .iter()
+            .filter(|(_, at)| **at <= now)
+            .map(|(kind, _)| *kind)
+            .collect();
+        due.sort();
+
+        if !due.is_empty() {
+            let span = info_span!("cycle", sources = ?due);
+            run_cycle(&worker, &due).instrument(span).await;
+
+            // schedule from completion so a long cycle can't queue up a backlog
+            for kind in due {
+                let interval = Duration::from_secs(settings.interval_secs(kind));
+                next_due.insert(kind, Instant::now() + interval);
+            }
+            continue;
+        }
+
+        let wake = next_due.values().min().copied().unwrap_or(now);
+        tokio::select! {
+            _ = tokio::time::sleep_until(wake) => {}
+            _ = shutdown.changed() => {}
+        }
+    }
+
+    if let Some(server) = server {
+        if let Err(e) = server.await.context("health server task panicked")? {
+            error!(error = format!("{:#}", e), "health server failed");
+        }
+    }
+    info!("daemon stopped");
+    Ok(())
+}
+
+async fn run_cycle(worker: &Worker, due: &[SourceKind]) {
+    let settings = &worker.settings;
+    let mut data = worker.data.lock().await;
+    let start_time = Instant::now();
+    let report = RunReport::shared();
+    let run_id = report.lock().unwrap().run_id.clone();
+    info!(%run_id, "starting cycle");
+
+    // a failed source keeps its previous records so its devices still consolidate
+    let runs =
+        sync::fetch_sources(&worker.registry, &worker.snapshots, due, &mut data, &report).await;
+    worker.record(runs);
+    report.lock().unwrap().phase("fetch", start_time.elapsed());
+
+    if worker.dry_run {
+        let devices = consolidate::devices(&data, &worker.registry, &worker.normalizer, &report);
+        let ownership = Ownership::new(&settings.ownership);
+        let tags = TagPolicy::new(&settings.tags, &worker.registry, &data.kinds(), &ownership);
+        let plans = sync::plan(&devices, &worker.local_cache, &ownership, &tags);
+        let mut totals: BTreeMap<String, usize> = BTreeMap::new();
+        for plan in &plans {
+            *totals
+                .entry(format!("{:?}", plan.action).to_lowercase())
+                .or_default() += 1;
+            if matches!(plan.action, PlanAction::Create | PlanAction::Update) {
+                info!(device = %plan.device, action = ?plan.action, creates = ?plan.creates, "planned");
+            }
+        }
+        info!(elapsed = ?start_time.elapsed(), planned = ?totals, "dry run cycle complete, nothing written");
+        return;
+    }
+
+    sync::sync_devices(
+        settings,
+        &worker.registry,
+        &worker.normalizer,
+        &data,
+        Arc::new(worker.netbox_client.for_run(&run_id)),
+        worker.local_cache.clone(),
+        report.clone(),
+    )
+    .await;
+
+    info!(elapsed = ?start_time.elapsed(), "cycle complete");
+    if let Err(e) = sync::finish(settings, &report) {
+        error!(error = format!("{:#}", e), "failed to write run outputs");
+    }
+}
+
+async fn serve(
+    addr: std::net::SocketAddr,
+    worker: Arc<Worker>,
+    mut shutdown: watch::Receiver<bool>,
+) -> Result<()> {
+    let app = Router::new()
+        .route("/health", get(health_handler))
+        .route("/metrics", get(|| async { metrics::render() }))
+        .with_state(worker.health.clone())
+        .merge(webhooks::router(worker));
+
+    let listener = tokio::net::TcpListener::bind(addr)
+        .await
+        .context(format!("Failed to bind {}", addr))?;
+    info!(%addr, "serving /health and /metrics");
+    axum::serve(listener, app)
+        .with_graceful_shutdown(async move {
+            let _ = shutdown.wait_for(|stop| *stop).await;
+        })
+        .await?;
+    Ok(())
+}
+
+async fn health_handler(
+    State(health): State<Health>,
+) -> (StatusCode, Json<HashMap<String, SourceHealth>>) {
+    let sources: HashMap<String, SourceHealth> = health
+        .iter()
+        .map(|e| (e.key().clone(), e.value().clone()))
+        .collect();
+    let status = if sources.values().all(|s| s.error.is_none()) {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(sources))
+}
+
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut term) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = term.recv() => {}
+                }
+            }
+            Err(e) => {
+                warn!(error = %e, "cannot listen for SIGTERM, only handling Ctrl-C");
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
@@ -1,5 +1,12 @@
-use crate::config::AzureConfig;
-use anyhow::anyhow;
+use crate::{
+    config::AzureConfig,
+    netbox::models::StatusOptions,
+    source::{Fetched, Field, Record, Source},
+    sync::SourceKind,
+};
+use anyhow::{anyhow, Context};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
 use reqwest::{
     header::{HeaderMap, HeaderValue},
     Client, StatusCode,
@@ -7,15 +14,16 @@ use reqwest::{
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::fmt::Debug;
+use tracing::{debug, info, instrument};
 
+/// Microsoft Graph client for Intune managed devices and Entra ID users
 #[derive(Debug)]
 pub struct AzureClient {
     client: Client,
-    token: Option<String>,
-    url: String,
+    config: AzureConfig,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct IntuneDevice {
     #[serde(rename = "deviceName")]
     pub name: String,
@@ -54,6 +62,12 @@ pub struct IntuneUser {
     pub title: Option<String>,
 }
 
+// Graph pages with opaque skip tokens, so its pages can't be fetched in
+// parallel like NetBox's; the largest pages each endpoint allows keep the
+// round trips down instead
+const USERS_PAGE_SIZE: usize = 999;
+const DEVICES_PAGE_SIZE: usize = 1000;
+
 #[derive(Debug, Deserialize)]
 struct UsersResponse {
     #[serde(rename = "@odata.nextLink")]
@@ -65,22 +79,26 @@ struct UsersResponse {
 struct DevicesResponse {
     #[serde(rename = "@odata.nextLink")]
     next: Option<String>,
-    value: Vec<IntuneDevice>,
+    value: Vec<Value>,
 }
 
 impl AzureClient {
-    pub async fn new(config: &AzureConfig) -> anyhow::Result<Self> {
-        let client = Client::new();
-        let url = &config.url;
-        let token = Self::fetch_token(&client, &config).await?;
-
-        Ok(AzureClient {
-            client,
-            url: url.to_string(),
-            token: Some(token),
-        })
+    pub fn new(config: &AzureConfig) -> Self {
+        AzureClient {
+            client: Client::new(),
+            config: config.clone(),
+        }
     }
 
+    // Graph tokens expire, so every fetch asks for a fresh one
+    async fn headers(&self) -> anyhow::Result<HeaderMap> {
+        let token = Self::fetch_token(&self.client, &self.config).await?;
+        let mut headers = HeaderMap::new();
+        headers.insert("Authorization", HeaderValue::from_str(&token)?);
+        Ok(headers)
+    }
+
+    #[instrument(name = "fetch_token", skip_all, fields(source = "azure"))]
     pub async fn fetch_token(client: &Client, config: &AzureConfig) -> anyhow::Result<String> {
         let params = [
             ("client_id", &config.client_id),
@@ -91,14 +109,15 @@ impl AzureClient {
 
         let res = client
             .post(format!(
-                "https://login.microsoftonline.com/{}/oauth2/v2.0/token",
+                "{}/{}/oauth2/v2.0/token",
+                config.login_url.trim_end_matches('/'),
                 config.tenant_id
             ))
             .form(&params)
             .send()
             .await?;
 
-        println!("Response status: {}", res.status());
+        debug!(status = %res.status(), "token response");
 
         let res_text = res.text().await?;
 
@@ -119,16 +138,16 @@ impl AzureClient {
         Ok(token)
     }
 
-    pub async fn fetch_users(&self) -> Result<Vec<IntuneUser>, reqwest::Error> {
+    /// Every user, following Graph's paging
+    #[instrument(name = "fetch", skip(self), fields(source = "azure", kind = "users"))]
+    pub async fn fetch_users(&self) -> anyhow::Result<Vec<IntuneUser>> {
         let mut all_users = Vec::new();
-        let mut next_link: Option<String> = Some(format!("{}/users", self.url));
+        let mut next_link: Option<String> = Some(format!(
+            "{}/users?$top={}",
+            self.config.url, USERS_PAGE_SIZE
+        ));
 
-        let mut headers = HeaderMap::new();
-        if let Some(token) = &self.token {
-            if let Ok(value) = HeaderValue::from_str(token) {
-                headers.insert("Authorization", value);
-            }
-        }
+        let headers = self.headers().await?;
 
         while let Some(url) = next_link {
             let res = self
@@ -144,29 +163,32 @@ impl AzureClient {
                     all_users.extend(json.value);
                     next_link = json.next;
                 }
-                _ => {
-                    let status_code = res.status();
-                    let response = res.text().await;
-                    eprintln!("Error fetching users: {:?} - {:?}", status_code, response);
-                    break;
+                status => {
+                    // a partial list would look like the rest disappeared
+                    let body = res.text().await.unwrap_or_default();
+                    return Err(anyhow!(
+                        "Graph returned {} fetching users: {}",
+                        status,
+                        body
+                    ));
                 }
             }
         }
 
+        info!(count = all_users.len(), "fetched users");
         Ok(all_users)
     }
 
-    pub async fn fetch_devices(&self) -> Result<Vec<IntuneDevice>, reqwest::Error> {
+    /// Every managed device as Graph returned it, following Graph's paging
+    #[instrument(name = "fetch", skip(self), fields(source = "azure", kind = "devices"))]
+    pub async fn fetch_devices(&self) -> anyhow::Result<Vec<Value>> {
         let mut all_devices = Vec::new();
-        let mut next_link: Option<String> =
-            Some(format!("{}/deviceManagement/managedDevices", self.url));
+        let mut next_link: Option<String> = Some(format!(
+            "{}/deviceManagement/managedDevices?$top={}",
+            self.config.url, DEVICES_PAGE_SIZE
+        ));
 
-        let mut headers = HeaderMap::new();
-        if let Some(token) = &self.token {
-            if let Ok(value) = HeaderValue::from_str(token) {
-                headers.insert("Authorization", value);
-            }
-        }
+        let headers = self.headers().await?;
 
         while let Some(url) = next_link {
             let res = self
@@ -182,15 +204,87 @@ impl AzureClient {
                     all_devices.extend(json.value);
                     next_link = json.next;
                 }
-                _ => {
-                    let status_code = res.status();
-                    let response = res.text().await;
-                    eprintln!("Error fetching devices: {:?} - {:?}", status_code, response);
-                    break;
+                status => {
+                    // a partial list would look like the rest disappeared
+                    let body = res.text().await.unwrap_or_default();
+                    return Err(anyhow!(
+                        "Graph returned {} fetching devices: {}",
+                        status,
+                        body
+                    ));
                 }
             }
         }
 
+        info!(count = all_devices.len(), "fetched devices");
         Ok(all_devices)
     }
 }
+
+#[async_trait]
+impl Source for AzureClient {
+    fn kind(&self) -> SourceKind {
+        SourceKind::Azure
+    }
+
+    async fn fetch(&self) -> anyhow::Result<Vec<Value>> {
+        self.fetch_devices().await
+    }
+
+    fn records(&self, raw: Vec<Value>) -> anyhow::Result<Fetched> {
+        let records = raw
+            .into_iter()
+            .map(|value| {
+                let device: IntuneDevice = serde_json::from_value(value.clone())
+                    .context("Failed to parse Intune device")?;
+                Ok(Record {
+                    raw: value,
+                    ..Record::from(device)
+                })
+            })
+            .collect::<anyhow::Result<Vec<Record>>>()?;
+        Ok(records.into())
+    }
+
+    // Intune knows the hardware best, FortiGate knows the network
+    fn priority(&self, field: Field) -> u8 {
+        match field {
+            Field::Name | Field::DeviceType | Field::Platform | Field::Serial => 30,
+            Field::Status => 20,
+            Field::PrimaryIp4 => 10,
+            Field::Site => 0,
+        }
+    }
+}
+
+impl From<IntuneDevice> for Record {
+    fn from(value: IntuneDevice) -> Self {
+        let non_empty = |s: String| Some(s).filter(|s| !s.trim().is_empty());
+        Record {
+            status: status_from_sync(&value.synced),
+            platform: non_empty(value.os),
+            os_version: non_empty(value.os_version),
+            serial: non_empty(value.serial),
+            manufacturer: non_empty(value.manufacturer),
+            model: non_empty(value.model),
+            mac: non_empty(value.wifi_mac),
+            name: value.name,
+            site: None,
+            primary_ip4: None,
+            tags: Vec::new(),
+            raw: Value::Null,
+        }
+    }
+}
+
+// Active when the device checked in with Intune during the last week
+fn status_from_sync(sync_time: &str) -> Option<StatusOptions> {
+    let parsed = sync_time.parse::<DateTime<Utc>>().ok()?;
+    let days = (Utc::now() - parsed).num_days();
+
+    Some(if days <= 7 {
+        StatusOptions::Active
+    } else {
+        StatusOptions::Offline
+    })
+}
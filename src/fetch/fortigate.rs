@@ -1,20 +1,33 @@
-use crate::config::FortiGateConfig;
-use anyhow::anyhow;
+use crate::{
+    config::{FortiGateConfig, FortiGateFilterConfig},
+    netbox::models::StatusOptions,
+    normalize,
+    source::{Fetched, Field, Record, Source},
+    sync::SourceKind,
+};
+use anyhow::{anyhow, Context};
+use async_trait::async_trait;
+use chrono::Utc;
+use regex::Regex;
 use reqwest::{
     header::{HeaderMap, HeaderValue},
     Certificate, Client, StatusCode,
 };
-use serde::Deserialize;
-use std::{fmt::Debug, fs, usize};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{fmt::Debug, fs};
+use tracing::{debug, info, instrument};
+
+// Tag of devices with a DHCP reservation
+const RESERVED_DHCP: &str = "Reserved DHCP";
 
+/// FortiGate REST API client for the devices the firewall has detected
 #[derive(Debug)]
 pub struct FortiGateClient {
-    client: Client,
-    token: String,
-    url: String,
+    config: FortiGateConfig,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct FortiGateDevice {
     pub mac: String,
     pub is_online: bool,
@@ -31,10 +44,11 @@ pub struct FortiGateDevice {
     pub dhcp_lease_lease_reserved: Option<bool>,
     pub device_type: Option<String>,
     pub online_interfaces: Option<Vec<String>>,
+    pub detected_interface: Option<String>,
     pub other_macs: Option<Vec<MacAddress>>,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct MacAddress {
     pub ipv4_address: Option<String>,
     pub mac: String,
@@ -49,18 +63,18 @@ pub struct MacAddress {
 
 #[derive(Debug, Deserialize)]
 pub struct Vlan {
-    name: String,
-    prefix: String,
+    pub name: String,
+    pub prefix: String,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct Ip {
-    name: String,
+    pub name: String,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct FortiGateResponse {
-    results: Vec<FortiGateDevice>,
+    results: Vec<Value>,
 }
 
 /*
@@ -76,50 +90,346 @@ pub struct FortiGateResponse {
 * */
 
 impl FortiGateClient {
-    pub async fn new(config: &FortiGateConfig) -> Result<Self, Box<dyn std::error::Error>> {
+    pub fn new(config: &FortiGateConfig) -> Self {
+        FortiGateClient {
+            config: config.clone(),
+        }
+    }
+
+    // Built per fetch so a missing or rotated certificate only affects this source
+    fn client(&self) -> anyhow::Result<Client> {
         // Load root certificate
-        let cert_bytes = fs::read("certs/FortiGate.crt")?;
+        let cert_bytes = fs::read(&self.config.ca_cert).context(format!(
+            "Failed to read FortiGate certificate {}",
+            self.config.ca_cert.display()
+        ))?;
         let cert = Certificate::from_pem(&cert_bytes)?;
 
         let client = Client::builder()
             .add_root_certificate(cert)
             .danger_accept_invalid_certs(true) // ⚠️ unsafe in prod
             .build()?;
-
-        Ok(FortiGateClient {
-            client,
-            url: config.url.clone(),
-            token: config.token.clone(),
-        })
+        Ok(client)
     }
 
-    pub async fn fetch_devices(&self) -> anyhow::Result<Vec<FortiGateDevice>> {
+    /// Every detected device as the FortiGate returned it, unfiltered
+    #[instrument(
+        name = "fetch",
+        skip(self),
+        fields(source = "fortigate", kind = "devices")
+    )]
+    pub async fn fetch_devices(&self) -> anyhow::Result<Vec<Value>> {
         let mut headers = HeaderMap::new();
         headers.insert(
             "Authorization",
-            HeaderValue::from_str(&format!("Bearer {}", self.token))
+            HeaderValue::from_str(&format!("Bearer {}", self.config.token))
                 .map_err(|e| anyhow!("Invalid header value: {}", e))?,
         );
         headers.insert("Content-Type", HeaderValue::from_str("application/json")?);
 
-        let url = format!("{}/monitor/user/device/query", &self.url);
-        println!("Attempting fetch from FortiGate...");
+        let url = format!("{}/monitor/user/device/query", &self.config.url);
+        debug!(%url, "querying FortiGate");
 
-        let res = self.client.get(url).headers(headers).send().await?;
+        let res = self.client()?.get(url).headers(headers).send().await?;
 
         match res.status() {
             StatusCode::OK => {
                 let json = res.json::<FortiGateResponse>().await?;
-                return Ok(json.results);
+                info!(count = json.results.len(), "fetched devices");
+                Ok(json.results)
+            }
+            status => {
+                // an empty list here would look like every device disappeared
+                let body = res.text().await.unwrap_or_default();
+                Err(anyhow!("FortiGate returned {}: {}", status, body))
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Source for FortiGateClient {
+    fn kind(&self) -> SourceKind {
+        SourceKind::FortiGate
+    }
+
+    fn extra_tags(&self) -> &'static [&'static str] {
+        &[RESERVED_DHCP]
+    }
+
+    async fn fetch(&self) -> anyhow::Result<Vec<Value>> {
+        self.fetch_devices().await
+    }
+
+    fn records(&self, raw: Vec<Value>) -> anyhow::Result<Fetched> {
+        let filter = DeviceFilter::new(&self.config.filter)?;
+
+        let mut fetched = Fetched::default();
+        for value in raw {
+            let device: FortiGateDevice = serde_json::from_value(value.clone())
+                .context("Failed to parse FortiGate device")?;
+            match filter.rule(&device) {
+                Some(rule) => {
+                    debug!(mac = %device.mac, hostname = ?device.hostname, rule, "filtered");
+                    *fetched.filtered.entry(rule).or_default() += 1;
+                }
+                None => fetched.records.push(Record {
+                    raw: value,
+                    ..Record::from(device)
+                }),
+            }
+        }
+        info!(kept = fetched.records.len(), filtered = ?fetched.filtered, "filtered devices");
+        Ok(fetched)
+    }
+
+    fn priority(&self, field: Field) -> u8 {
+        match field {
+            Field::PrimaryIp4 => 30,
+            Field::Name | Field::DeviceType | Field::Platform => 20,
+            Field::Status | Field::Serial => 10,
+            Field::Site => 0,
+        }
+    }
+}
+
+impl From<FortiGateDevice> for Record {
+    fn from(value: FortiGateDevice) -> Self {
+        let status = if value.is_online {
+            StatusOptions::Active
+        } else {
+            StatusOptions::Offline
+        };
+        let mut tags = Vec::new();
+        if let Some(true) = value.dhcp_lease_lease_reserved {
+            tags.push(RESERVED_DHCP.to_string());
+        }
+        Record {
+            name: value.hostname.unwrap_or_else(|| value.mac.clone()),
+            serial: None,
+            manufacturer: value.hardware_vendor,
+            model: value.device_type,
+            platform: value.os_name,
+            os_version: value.os_version,
+            status: Some(status),
+            site: None,
+            primary_ip4: value.ipv4_address,
+            mac: Some(value.mac),
+            tags,
+            raw: Value::Null,
+        }
+    }
+}
+
+// The `[fortigate.filter]` rules, checked in the order below
+struct DeviceFilter {
+    locally_administered: bool,
+    max_offline_secs: Option<i64>,
+    guest_interfaces: Vec<String>,
+    exclude_hostnames: Vec<Regex>,
+    exclude_macs: Vec<Regex>,
+}
+
+impl DeviceFilter {
+    fn new(config: &FortiGateFilterConfig) -> anyhow::Result<Self> {
+        let compile = |patterns: &[String]| -> anyhow::Result<Vec<Regex>> {
+            patterns
+                .iter()
+                .map(|p| normalize::compile(p).context(format!("invalid pattern `{}`", p)))
+                .collect()
+        };
+        Ok(Self {
+            locally_administered: config.locally_administered,
+            max_offline_secs: config.max_offline_days.map(|days| days as i64 * 86_400),
+            guest_interfaces: config
+                .guest_interfaces
+                .iter()
+                .map(|i| i.to_lowercase())
+                .collect(),
+            exclude_hostnames: compile(&config.exclude_hostnames)?,
+            exclude_macs: compile(&config.exclude_macs)?,
+        })
+    }
+
+    // Name of the first rule dropping `device`, None to keep it
+    fn rule(&self, device: &FortiGateDevice) -> Option<&'static str> {
+        if self.locally_administered && is_locally_administered(&device.mac) {
+            return Some("locally_administered_mac");
+        }
+        if let Some(max) = self.max_offline_secs {
+            // last_seen is a unix timestamp
+            let offline_for = Utc::now().timestamp() - device.last_seen as i64;
+            if !device.is_online && offline_for > max {
+                return Some("offline");
             }
-            _ => {
-                eprintln!(
-                    "Error fetching devices from FortiGate: {:?} - {:?}",
-                    res.status(),
-                    res.text().await,
-                );
-                return Ok(Vec::new());
+        }
+        let on_guest = device
+            .detected_interface
+            .iter()
+            .chain(device.online_interfaces.iter().flatten())
+            .any(|i| self.guest_interfaces.contains(&i.to_lowercase()));
+        if on_guest {
+            return Some("guest_interface");
+        }
+        if let Some(hostname) = &device.hostname {
+            if self.exclude_hostnames.iter().any(|p| p.is_match(hostname)) {
+                return Some("excluded_hostname");
             }
         }
+        if self.exclude_macs.iter().any(|p| p.is_match(&device.mac)) {
+            return Some("excluded_mac");
+        }
+        None
+    }
+}
+
+// The second bit of the first octet marks an address as locally administered
+fn is_locally_administered(mac: &str) -> bool {
+    mac.get(..2)
+        .and_then(|octet| u8::from_str_radix(octet, 16).ok())
+        .is_some_and(|octet| octet & 0x02 != 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const DAY: i64 = 86_400;
+
+    fn device(mac: &str, online: bool, seen_ago: i64) -> FortiGateDevice {
+        serde_json::from_value(json!({
+            "mac": mac,
+            "is_online": online,
+            "last_seen": Utc::now().timestamp() - seen_ago,
+        }))
+        .unwrap()
+    }
+
+    fn filter(config: FortiGateFilterConfig) -> DeviceFilter {
+        DeviceFilter::new(&config).unwrap()
+    }
+
+    #[test]
+    fn the_second_bit_marks_a_mac_locally_administered() {
+        for mac in [
+            "02:11:22:33:44:55",
+            "da:a1:19:00:00:01",
+            "DA-A1-19-00-00-01",
+            "0a11.2233.4455",
+        ] {
+            assert!(is_locally_administered(mac), "{}", mac);
+        }
+        for mac in [
+            "00:11:22:33:44:55",
+            "fc:11:22:33:44:55",
+            "01:00:5e:00:00:01",
+        ] {
+            assert!(!is_locally_administered(mac), "{}", mac);
+        }
+    }
+
+    #[test]
+    fn malformed_macs_are_not_locally_administered() {
+        for mac in ["", "2", "zz:11:22:33:44:55", ":2:11:22:33:44"] {
+            assert!(!is_locally_administered(mac), "{:?}", mac);
+        }
+    }
+
+    #[test]
+    fn locally_administered_macs_are_dropped_by_default() {
+        let filter = filter(FortiGateFilterConfig::default());
+        assert_eq!(
+            filter.rule(&device("da:a1:19:00:00:01", true, 0)),
+            Some("locally_administered_mac")
+        );
+        assert_eq!(filter.rule(&device("00:11:22:33:44:55", true, 0)), None);
+
+        let filter = self::filter(FortiGateFilterConfig {
+            locally_administered: false,
+            ..Default::default()
+        });
+        assert_eq!(filter.rule(&device("da:a1:19:00:00:01", true, 0)), None);
+    }
+
+    #[test]
+    fn only_devices_offline_past_the_window_are_dropped() {
+        let filter = filter(FortiGateFilterConfig {
+            max_offline_days: Some(7),
+            ..Default::default()
+        });
+        let mac = "00:11:22:33:44:55";
+        assert_eq!(filter.rule(&device(mac, false, 8 * DAY)), Some("offline"));
+        assert_eq!(filter.rule(&device(mac, false, 6 * DAY)), None);
+        // online devices stay, however long ago they were last seen
+        assert_eq!(filter.rule(&device(mac, true, 30 * DAY)), None);
+        // without a window nothing is dropped for being offline
+        let keep = self::filter(FortiGateFilterConfig::default());
+        assert_eq!(keep.rule(&device(mac, false, 365 * DAY)), None);
+    }
+
+    #[test]
+    fn guest_interfaces_match_regardless_of_case() {
+        let filter = filter(FortiGateFilterConfig {
+            guest_interfaces: vec!["Guest-WiFi".into()],
+            ..Default::default()
+        });
+        let mut detected = device("00:11:22:33:44:55", true, 0);
+        detected.detected_interface = Some("guest-wifi".into());
+        assert_eq!(filter.rule(&detected), Some("guest_interface"));
+
+        let mut online = device("00:11:22:33:44:55", true, 0);
+        online.online_interfaces = Some(vec!["lan".into(), "GUEST-WIFI".into()]);
+        assert_eq!(filter.rule(&online), Some("guest_interface"));
+
+        let mut staff = device("00:11:22:33:44:55", true, 0);
+        staff.detected_interface = Some("staff-wifi".into());
+        assert_eq!(filter.rule(&staff), None);
+    }
+
+    #[test]
+    fn hostname_and_mac_patterns_are_case_insensitive() {
+        let filter = filter(FortiGateFilterConfig {
+            exclude_hostnames: vec!["^iphone".into()],
+            exclude_macs: vec!["^00:50:56".into()],
+            ..Default::default()
+        });
+        let mut phone = device("00:11:22:33:44:55", true, 0);
+        phone.hostname = Some("iPhone-von-Jakob".into());
+        assert_eq!(filter.rule(&phone), Some("excluded_hostname"));
+        assert_eq!(
+            filter.rule(&device("00:50:56:AB:CD:EF", true, 0)),
+            Some("excluded_mac")
+        );
+    }
+
+    #[test]
+    fn the_first_matching_rule_names_the_drop() {
+        let mut config = FortiGateFilterConfig {
+            max_offline_days: Some(1),
+            guest_interfaces: vec!["guest".into()],
+            exclude_hostnames: vec!["phone".into()],
+            exclude_macs: vec!["^da".into()],
+            ..Default::default()
+        };
+        // matches every rule, each step below clears the one that matched
+        let mut device = device("da:a1:19:00:00:01", false, 2 * DAY);
+        device.detected_interface = Some("guest".into());
+        device.hostname = Some("phone".into());
+        let rule = |config: &FortiGateFilterConfig, device: &FortiGateDevice| {
+            filter(config.clone()).rule(device)
+        };
+
+        assert_eq!(rule(&config, &device), Some("locally_administered_mac"));
+        config.locally_administered = false;
+        assert_eq!(rule(&config, &device), Some("offline"));
+        device.is_online = true;
+        assert_eq!(rule(&config, &device), Some("guest_interface"));
+        device.detected_interface = None;
+        assert_eq!(rule(&config, &device), Some("excluded_hostname"));
+        device.hostname = None;
+        assert_eq!(rule(&config, &device), Some("excluded_mac"));
+        device.mac = "00:11:22:33:44:55".into();
+        assert_eq!(rule(&config, &device), None);
     }
 }
@@ -1,9 +1,18 @@
-use anyhow::Ok;
+use anyhow::Context;
+use async_trait::async_trait;
 use reqwest::Client;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::{info, instrument};
 
-use crate::config::NagiosxiConfig;
+use crate::{
+    config::NagiosxiConfig,
+    netbox::models::StatusOptions,
+    source::{Fetched, Field, Record, Source},
+    sync::SourceKind,
+};
 
+/// Nagios XI API client for monitored hosts and services
 #[derive(Debug)]
 pub struct NagiosxiClient {
     pub client: Client,
@@ -11,7 +20,7 @@ pub struct NagiosxiClient {
     pub url: String,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct HostStatus {
     pub host_object_id: String,
     pub host_name: String,
@@ -30,25 +39,25 @@ pub struct HostStatus {
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct ServiceStatus {
-    host_name: String,
-    service_description: String,
-    display_name: String,
-    host_address: String,
-    host_alias: String,
-    output: String,
-    current_state: String,
-    last_check: String,
-    next_check: String,
-    last_time_ok: String,
-    last_time_warning: String,
-    last_time_unknown: String,
-    last_time_critical: String,
+    pub host_name: String,
+    pub service_description: String,
+    pub display_name: String,
+    pub host_address: String,
+    pub host_alias: String,
+    pub output: String,
+    pub current_state: String,
+    pub last_check: String,
+    pub next_check: String,
+    pub last_time_ok: String,
+    pub last_time_warning: String,
+    pub last_time_unknown: String,
+    pub last_time_critical: String,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct HostsList {
     pub recordcount: usize,
-    pub hoststatus: Vec<HostStatus>,
+    pub hoststatus: Vec<Value>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -68,17 +77,87 @@ impl NagiosxiClient {
         }
     }
 
+    #[instrument(
+        name = "fetch",
+        skip(self),
+        fields(source = "nagiosxi", kind = "hosts")
+    )]
     pub async fn get_hosts(&self) -> anyhow::Result<HostsList> {
         let url = format!("{}/objects/hoststatus?apikey={}", self.url, self.api_key);
         let res = self.client.get(url).send().await?;
 
-        Ok(res.json::<HostsList>().await?)
+        let hosts = res.json::<HostsList>().await?;
+        info!(count = hosts.recordcount, "fetched hosts");
+        Ok(hosts)
     }
 
+    #[instrument(
+        name = "fetch",
+        skip(self),
+        fields(source = "nagiosxi", kind = "services")
+    )]
     pub async fn get_services(&self) -> anyhow::Result<ServiceList> {
         let url = format!("{}/objects/servicestatus?apikey={}", self.url, self.api_key);
         let res = self.client.get(url).send().await?;
 
-        Ok(res.json::<ServiceList>().await?)
+        let services = res.json::<ServiceList>().await?;
+        info!(count = services.recordcount, "fetched services");
+        Ok(services)
+    }
+}
+
+#[async_trait]
+impl Source for NagiosxiClient {
+    fn kind(&self) -> SourceKind {
+        SourceKind::NagiosXi
+    }
+
+    async fn fetch(&self) -> anyhow::Result<Vec<Value>> {
+        Ok(self.get_hosts().await?.hoststatus)
+    }
+
+    fn records(&self, raw: Vec<Value>) -> anyhow::Result<Fetched> {
+        let records = raw
+            .into_iter()
+            .map(|value| {
+                let host: HostStatus = serde_json::from_value(value.clone())
+                    .context("Failed to parse Nagios XI host")?;
+                Ok(Record {
+                    raw: value,
+                    ..Record::from(host)
+                })
+            })
+            .collect::<anyhow::Result<Vec<Record>>>()?;
+        Ok(records.into())
+    }
+
+    fn priority(&self, field: Field) -> u8 {
+        match field {
+            Field::PrimaryIp4 => 20,
+            Field::Name | Field::Status => 5,
+            Field::DeviceType | Field::Platform | Field::Serial | Field::Site => 0,
+        }
+    }
+
+    // monitored hosts only fill in devices an inventory source reported
+    fn adds_devices(&self) -> bool {
+        false
+    }
+}
+
+impl From<HostStatus> for Record {
+    fn from(value: HostStatus) -> Self {
+        // 0 is UP, 1 DOWN and 2 UNREACHABLE
+        let status = if value.current_state == "0" {
+            StatusOptions::Active
+        } else {
+            StatusOptions::Offline
+        };
+        Record {
+            name: value.host_name,
+            status: Some(status),
+            primary_ip4: Some(value.address).filter(|a| !a.is_empty()),
+            ..Default::default()
+        }
     }
 }
@@ -0,0 +1,55 @@
+//! Collects inventory from Intune (Azure), FortiGate and Nagios XI, consolidates it
+//! into one record per device and pushes the result to NetBox.
+//!
+//! The `netbox_ingester` binary is a thin CLI over this crate. Other tools can use
+//! the NetBox side on its own:
+//!
+//! - [`ApiClient`] talks to the NetBox REST API,
+//! - [`NetBoxModel`] and the types in [`netbox::models`] describe NetBox objects,
+//! - [`LocalCache`] preloads NetBox and creates missing related objects on demand,
+//! - [`AzureClient`], [`FortiGateClient`] and [`NagiosxiClient`] fetch the sources,
+//!   all behind the [`Source`] trait.
+//!
+//! ```no_run
+//! use netbox_ingester::{config::NetBoxConfig, ApiClient, LocalCache};
+//! use std::sync::Arc;
+//!
+//! # async fn example() -> anyhow::Result<()> {
+//! let config = NetBoxConfig {
+//!     api_url: "https://netbox.example/api".into(),
+//!     api_key: "token".into(),
+//!     ..Default::default()
+//! };
+//! let cache = LocalCache::preload(Arc::new(ApiClient::new(&config))).await?;
+//! println!("{} devices in NetBox", cache.devices.len());
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod cache;
+pub mod cli;
+pub mod config;
+pub mod consolidate;
+pub mod daemon;
+pub mod fetch;
+pub mod logging;
+pub mod metrics;
+pub mod naming;
+pub mod netbox;
+pub mod normalize;
+pub mod ownership;
+pub mod reconcile;
+pub mod report;
+pub mod snapshot;
+pub mod source;
+pub mod sync;
+pub mod tags;
+pub mod undo;
+mod utils;
+pub mod webhooks;
+
+pub use cache::LocalCache;
+pub use fetch::{azure::AzureClient, fortigate::FortiGateClient, nagiosxi::NagiosxiClient};
+pub use netbox::{api::ApiClient, models::NetBoxModel};
+pub use source::{Fetched, Record, Registry, Source};
+pub use sync::SourceKind;
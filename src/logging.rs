@@ -0,0 +1,48 @@
+use crate::config::{LogFormat, LoggingConfig};
+use anyhow::{anyhow, Context, Result};
+use std::{fs::File, io::IsTerminal, sync::Arc};
+use tracing_subscriber::{fmt, fmt::writer::BoxMakeWriter, EnvFilter};
+
+// Sets up the global tracing subscriber.
+// `RUST_LOG` wins over the configured level so a single run can be made
+// more verbose without touching the config file.
+pub fn init(config: &LoggingConfig) -> Result<()> {
+    let filter = EnvFilter::try_from_default_env()
+        .or_else(|_| EnvFilter::try_new(&config.level))
+        .context(format!("Invalid log filter `{}`", config.level))?;
+
+    // Writing the log file ourselves keeps it UTF-8 regardless of how the
+    // host shell would encode a redirected stdout/stderr.
+    let (writer, ansi) = match &config.file {
+        Some(path) => {
+            let file = File::options()
+                .create(true)
+                .append(true)
+                .open(path)
+                .context(format!("Failed to open log file {}", path.display()))?;
+            (BoxMakeWriter::new(Arc::new(file)), false)
+        }
+        None => (
+            BoxMakeWriter::new(std::io::stderr),
+            std::io::stderr().is_terminal(),
+        ),
+    };
+
+    let res = match config.format {
+        LogFormat::Pretty => fmt()
+            .with_env_filter(filter)
+            .with_writer(writer)
+            .with_ansi(ansi)
+            .with_target(false)
+            .try_init(),
+        LogFormat::Json => fmt()
+            .json()
+            .with_env_filter(filter)
+            .with_writer(writer)
+            .with_current_span(true)
+            .with_span_list(true)
+            .try_init(),
+    };
+
+    res.map_err(|e| anyhow!("Failed to initialise logging: {}", e))
+}
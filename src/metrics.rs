@@ -0,0 +1,170 @@
+use anyhow::{Context, Result};
+use prometheus::{
+    HistogramOpts, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use std::{fs, path::Path, sync::LazyLock, time::Duration};
+use tracing::info;
+
+pub static REGISTRY: LazyLock<Registry> = LazyLock::new(Registry::new);
+
+pub static SOURCE_FETCH_SUCCESS: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register(IntGaugeVec::new(
+        Opts::new(
+            "ingester_source_fetch_success",
+            "1 if the last fetch from the source succeeded, 0 otherwise",
+        ),
+        &["source"],
+    ))
+});
+
+pub static SOURCE_RECORDS: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register(IntGaugeVec::new(
+        Opts::new(
+            "ingester_source_records",
+            "Records returned by the last fetch from the source",
+        ),
+        &["source"],
+    ))
+});
+
+pub static NETBOX_REQUESTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register(IntCounterVec::new(
+        Opts::new("ingester_netbox_requests_total", "NetBox API requests"),
+        &["method", "endpoint", "status"],
+    ))
+});
+
+pub static NETBOX_LATENCY: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register(HistogramVec::new(
+        HistogramOpts::new(
+            "ingester_netbox_request_duration_seconds",
+            "NetBox API request latency",
+        ),
+        &["method", "endpoint"],
+    ))
+});
+
+pub static OBJECTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register(IntCounterVec::new(
+        Opts::new(
+            "ingester_objects_total",
+            "NetBox objects handled, by object type and outcome",
+        ),
+        &["object", "outcome"],
+    ))
+});
+
+pub static CACHE_LOOKUPS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register(IntCounterVec::new(
+        Opts::new(
+            "ingester_cache_lookups_total",
+            "LocalCache lookups, by object type and hit/miss",
+        ),
+        &["object", "result"],
+    ))
+});
+
+pub static LAST_SUCCESS: LazyLock<IntGauge> = LazyLock::new(|| {
+    register(IntGauge::new(
+        "ingester_last_success_timestamp_seconds",
+        "Unix time of the last run that completed without failed sources or writes",
+    ))
+});
+
+fn register<T>(metric: prometheus::Result<T>) -> T
+where
+    T: prometheus::core::Collector + Clone + 'static,
+{
+    let metric = metric.expect("metric definition is valid");
+    REGISTRY
+        .register(Box::new(metric.clone()))
+        .expect("metric is registered once");
+    metric
+}
+
+pub fn observe_request(method: &str, endpoint: &str, status: &str, elapsed: Duration) {
+    let endpoint = endpoint_label(endpoint);
+    NETBOX_REQUESTS
+        .with_label_values(&[method, &endpoint, status])
+        .inc();
+    NETBOX_LATENCY
+        .with_label_values(&[method, &endpoint])
+        .observe(elapsed.as_secs_f64());
+}
+
+pub fn source_fetched(source: &str, records: Option<usize>) {
+    SOURCE_FETCH_SUCCESS
+        .with_label_values(&[source])
+        .set(records.is_some() as i64);
+    if let Some(records) = records {
+        SOURCE_RECORDS
+            .with_label_values(&[source])
+            .set(records as i64);
+    }
+}
+
+pub fn cache_lookup(object: &str, hit: bool) {
+    let result = if hit { "hit" } else { "miss" };
+    CACHE_LOOKUPS.with_label_values(&[object, result]).inc();
+}
+
+pub fn run_succeeded() {
+    LAST_SUCCESS.set(chrono::Utc::now().timestamp());
+}
+
+// Collapses ids and query strings so `dcim/devices/42/?x=y` becomes `dcim/devices/{id}`
+pub fn endpoint_label(endpoint: &str) -> String {
+    let path = endpoint.split('?').next().unwrap_or_default();
+    path.split('/')
+        .filter(|p| !p.is_empty())
+        .map(|p| {
+            if p.chars().all(|c| c.is_ascii_digit()) {
+                "{id}"
+            } else {
+                p
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+pub fn render() -> String {
+    // Touch every metric so the families show up even before the first sample
+    LazyLock::force(&SOURCE_FETCH_SUCCESS);
+    LazyLock::force(&SOURCE_RECORDS);
+    LazyLock::force(&NETBOX_REQUESTS);
+    LazyLock::force(&NETBOX_LATENCY);
+    LazyLock::force(&OBJECTS);
+    LazyLock::force(&CACHE_LOOKUPS);
+    LazyLock::force(&LAST_SUCCESS);
+
+    TextEncoder::new()
+        .encode_to_string(&REGISTRY.gather())
+        .unwrap_or_default()
+}
+
+// node_exporter reads every *.prom file in its textfile directory, so write to a
+// temp file first and rename to avoid it scraping a half written file.
+pub fn write_textfile(path: &Path) -> Result<()> {
+    // a run without success of its own keeps the last one the file recorded
+    if LAST_SUCCESS.get() == 0 {
+        if let Some(previous) = previous_success(path) {
+            LAST_SUCCESS.set(previous);
+        }
+    }
+    let tmp = path.with_extension("prom.tmp");
+    fs::write(&tmp, render()).context(format!("Failed to write {}", tmp.display()))?;
+    fs::rename(&tmp, path).context(format!("Failed to move metrics to {}", path.display()))?;
+    info!(path = %path.display(), "wrote metrics textfile");
+    Ok(())
+}
+
+fn previous_success(path: &Path) -> Option<i64> {
+    let previous = fs::read_to_string(path).ok()?;
+    previous.lines().find_map(|line| {
+        line.strip_prefix("ingester_last_success_timestamp_seconds ")?
+            .trim()
+            .parse()
+            .ok()
+    })
+}
@@ -0,0 +1,403 @@
+use crate::{config::NamingConfig, normalize, source::Record};
+use anyhow::{Context, Result};
+use regex::Regex;
+use std::collections::{BTreeMap, HashSet};
+use tracing::{debug, warn};
+
+// Hostnames devices pick for themselves, on top of `naming.generated`
+const GENERATED_NAMES: &[&str] = &[
+    r"^([0-9a-f]{2}[:-]){5}[0-9a-f]{2}$",
+    r"^[0-9a-f]{12}$",
+    r"^android[-_][0-9a-f]{8,}$",
+];
+
+// Turns whatever a source calls a device into the name it gets in NetBox
+#[derive(Debug, Clone)]
+pub struct NamingPolicy {
+    // lowercase, with the leading dot
+    strip_domains: Vec<String>,
+    max_length: usize,
+    generated: Vec<Regex>,
+}
+
+impl Default for NamingPolicy {
+    fn default() -> Self {
+        Self::new(&NamingConfig::default()).expect("built-in name patterns compile")
+    }
+}
+
+impl NamingPolicy {
+    pub fn new(config: &NamingConfig) -> Result<Self> {
+        let generated = GENERATED_NAMES
+            .iter()
+            .map(|p| p.to_string())
+            .chain(config.generated.iter().cloned())
+            .map(|p| normalize::compile(&p).context(format!("invalid name pattern `{}`", p)))
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            strip_domains: config
+                .strip_domains
+                .iter()
+                .map(|d| format!(".{}", d.trim().trim_start_matches('.').to_lowercase()))
+                .collect(),
+            max_length: config.max_length,
+            generated,
+        })
+    }
+
+    // Canonical name of a record whose manufacturer and model are normalized
+    pub fn name(&self, record: &Record) -> String {
+        let mut name = self.strip_domain(sanitize(&record.name));
+
+        let is_mac = record
+            .mac
+            .as_ref()
+            .is_some_and(|mac| mac.eq_ignore_ascii_case(&name));
+        if name.is_empty() || is_mac || self.generated.iter().any(|p| p.is_match(&name)) {
+            if let Some(fallback) = fallback_name(record) {
+                debug!(name = %record.name, %fallback, "replacing generated name");
+                name = fallback;
+            }
+        }
+        truncate(&name, self.max_length).to_string()
+    }
+
+    // The key a device called `raw` is matched under, the same for a record and
+    // for a NetBox device still carrying the name before the policy applied
+    pub fn key(&self, raw: &str) -> String {
+        let name = self.strip_domain(sanitize(raw));
+        truncate(&name, self.max_length).to_lowercase()
+    }
+
+    fn strip_domain(&self, mut name: String) -> String {
+        let domain = self.strip_domains.iter().find(|d| {
+            name.len() > d.len()
+                && name
+                    .get(name.len() - d.len()..)
+                    .is_some_and(|tail| tail.eq_ignore_ascii_case(d))
+        });
+        if let Some(domain) = domain {
+            name.truncate(name.len() - domain.len());
+        }
+        name
+    }
+
+    // NetBox needs names unique per site and devices are keyed by name here, so
+    // the records of all sources sharing a name are split into devices first:
+    // records are one device unless their site, serial or MAC tell them apart.
+    // Every device but the first gets a number.
+    pub fn dedupe(&self, records: &mut [Record]) {
+        let mut taken: HashSet<String> = records.iter().map(|r| r.key()).collect();
+        let mut groups: BTreeMap<String, Vec<usize>> = BTreeMap::new();
+        for (i, record) in records.iter().enumerate() {
+            groups.entry(record.key()).or_default().push(i);
+        }
+
+        for (key, mut indices) in groups {
+            if indices.len() < 2 {
+                continue;
+            }
+            // ordered by identity so every run numbers the same way
+            indices.sort_by_key(|i| identity(&records[*i]));
+            let mut devices: Vec<Vec<usize>> = Vec::new();
+            for i in indices {
+                let same = devices
+                    .iter_mut()
+                    .find(|d| d.iter().all(|j| same_device(&records[i], &records[*j])));
+                match same {
+                    Some(device) => device.push(i),
+                    None => devices.push(vec![i]),
+                }
+            }
+
+            let mut n = 1;
+            for device in devices.iter().skip(1) {
+                let first = &records[device[0]];
+                let name = loop {
+                    n += 1;
+                    let candidate = self.suffixed(&first.name, n);
+                    if taken.insert(candidate.to_lowercase()) {
+                        break candidate;
+                    }
+                };
+                warn!(device = %key, renamed = %name, serial = ?first.serial, site = ?first.site, "duplicate device name");
+                for i in device {
+                    records[*i].name = name.clone();
+                }
+            }
+        }
+    }
+
+    fn suffixed(&self, name: &str, n: usize) -> String {
+        let suffix = format!("-{}", n);
+        let base = truncate(name, self.max_length.saturating_sub(suffix.len()));
+        format!("{}{}", base, suffix)
+    }
+}
+
+// Trimmed, without control characters and with single spaces
+fn sanitize(raw: &str) -> String {
+    raw.split_whitespace()
+        .map(|part| part.chars().filter(|c| !c.is_control()).collect::<String>())
+        .collect::<Vec<_>>()
+        .join(" ")
+        .trim_end_matches('.')
+        .to_string()
+}
+
+fn truncate(name: &str, max: usize) -> &str {
+    let end = name.char_indices().nth(max).map_or(name.len(), |(i, _)| i);
+    name[..end].trim_end_matches(['-', '.', ' '])
+}
+
+// manufacturer-model-serial, the MAC standing in for a missing serial
+fn fallback_name(record: &Record) -> Option<String> {
+    let id = record.serial.clone().or_else(|| mac(record))?;
+    let mut parts: Vec<&str> = [&record.manufacturer, &record.model]
+        .into_iter()
+        .flatten()
+        .map(|s| s.as_str())
+        .collect();
+    if parts.is_empty() {
+        parts.push("device");
+    }
+    parts.push(&id);
+    Some(
+        parts
+            .join("-")
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join("-"),
+    )
+}
+
+fn identity(record: &Record) -> (Option<String>, Option<String>, Option<String>) {
+    let lower = |v: &Option<String>| v.as_ref().map(|v| v.to_lowercase());
+    (lower(&record.site), lower(&record.serial), mac(record))
+}
+
+// Two records of one name are one device unless both know the site and it
+// differs, or the serial when both have one, or else the MAC
+fn same_device(a: &Record, b: &Record) -> bool {
+    let (a, b) = (identity(a), identity(b));
+    let differ =
+        |x: &Option<String>, y: &Option<String>| matches!((x, y), (Some(x), Some(y)) if x != y);
+    if differ(&a.0, &b.0) {
+        return false;
+    }
+    match (&a.1, &b.1) {
+        (Some(x), Some(y)) => x == y,
+        _ => !differ(&a.2, &b.2),
+    }
+}
+
+// hex digits only, sources format MACs differently
+fn mac(record: &Record) -> Option<String> {
+    let mac: String = record
+        .mac
+        .as_ref()?
+        .chars()
+        .filter(|c| c.is_ascii_hexdigit())
+        .collect();
+    (!mac.is_empty()).then(|| mac.to_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(name: &str, serial: Option<&str>, mac: Option<&str>) -> Record {
+        Record {
+            name: name.into(),
+            serial: serial.map(String::from),
+            mac: mac.map(String::from),
+            ..Default::default()
+        }
+    }
+
+    fn policy(strip_domains: &[&str], max_length: usize, generated: &[&str]) -> NamingPolicy {
+        NamingPolicy::new(&NamingConfig {
+            strip_domains: strip_domains.iter().map(|d| d.to_string()).collect(),
+            max_length,
+            generated: generated.iter().map(|p| p.to_string()).collect(),
+        })
+        .unwrap()
+    }
+
+    fn names(records: &[Record]) -> Vec<&str> {
+        records.iter().map(|r| r.name.as_str()).collect()
+    }
+
+    #[test]
+    fn dedupe_keeps_records_of_one_device_across_sources() {
+        // Azure knows the serial, FortiGate the MAC in its own notation
+        let mut records = vec![
+            record("PC01", Some("S1"), Some("00:11:22:33:44:55")),
+            record("pc01", None, Some("00-11-22-33-44-55")),
+        ];
+        NamingPolicy::default().dedupe(&mut records);
+        assert_eq!(names(&records), ["PC01", "pc01"]);
+    }
+
+    #[test]
+    fn dedupe_numbers_distinct_devices_across_sources() {
+        let mut records = vec![
+            record("PC01", Some("S2"), None),
+            record("PC01", None, Some("00:11:22:33:44:55")),
+            record("pc01", Some("S1"), Some("00:11:22:33:44:55")),
+        ];
+        NamingPolicy::default().dedupe(&mut records);
+        // the FortiGate record goes with the device of its MAC
+        assert_eq!(names(&records), ["PC01-2", "PC01", "pc01"]);
+    }
+
+    #[test]
+    fn dedupe_tells_sites_apart() {
+        let mut records = vec![
+            Record {
+                site: Some("Oslo".into()),
+                ..record("PC01", None, None)
+            },
+            Record {
+                site: Some("Bergen".into()),
+                ..record("PC01", None, None)
+            },
+            record("PC01", None, None),
+        ];
+        NamingPolicy::default().dedupe(&mut records);
+        assert_eq!(names(&records), ["PC01-2", "PC01", "PC01"]);
+    }
+
+    #[test]
+    fn dedupe_skips_suffixes_already_taken() {
+        let mut records = vec![
+            record("PC01", Some("S1"), None),
+            record("PC01", Some("S2"), None),
+            record("PC01-2", Some("S3"), None),
+        ];
+        NamingPolicy::default().dedupe(&mut records);
+        assert_eq!(names(&records), ["PC01", "PC01-3", "PC01-2"]);
+    }
+
+    #[test]
+    fn sanitize_trims_and_collapses() {
+        for (raw, clean) in [
+            ("  JAKOB-DESKTOP   ", "JAKOB-DESKTOP"),
+            ("Meeting\t Room  PC", "Meeting Room PC"),
+            ("pc01\u{7}", "pc01"),
+            ("host.example.com.", "host.example.com"),
+            ("", ""),
+        ] {
+            assert_eq!(sanitize(raw), clean, "{:?}", raw);
+        }
+    }
+
+    #[test]
+    fn truncate_cuts_at_characters_and_drops_separators() {
+        assert_eq!(truncate("abcdef", 3), "abc");
+        assert_eq!(truncate("abc", 64), "abc");
+        assert_eq!(truncate("ab-cdef", 3), "ab");
+        assert_eq!(truncate("ab. cdef", 4), "ab");
+        assert_eq!(truncate("æøåæøå", 4), "æøåæ");
+    }
+
+    #[test]
+    fn key_strips_configured_domains() {
+        let naming = policy(&[".Corp.Local", "example.com"], 64, &[]);
+        assert_eq!(naming.key(" PC01.corp.local "), "pc01");
+        assert_eq!(naming.key("pc01.EXAMPLE.com"), "pc01");
+        assert_eq!(naming.key("pc01.other.local"), "pc01.other.local");
+        // only whole labels, and never the whole name
+        assert_eq!(naming.key("pc01corp.local"), "pc01corp.local");
+        assert_eq!(naming.key("corp.local"), "corp.local");
+    }
+
+    #[test]
+    fn names_are_cut_to_the_length_limit() {
+        let long = "x".repeat(70);
+        assert_eq!(
+            NamingPolicy::default()
+                .name(&record(&long, None, None))
+                .len(),
+            64
+        );
+        let naming = policy(&["example.com"], 10, &[]);
+        let name = naming.name(&record("workstation-01.example.com", None, None));
+        assert_eq!(name, "workstatio");
+        assert_eq!(naming.key("workstation-01.example.com"), name);
+    }
+
+    #[test]
+    fn names_colliding_after_truncation_are_numbered_within_the_limit() {
+        let naming = policy(&[], 10, &[]);
+        let mut records: Vec<Record> = [("ACCOUNTING-PC-A", "S1"), ("ACCOUNTING-PC-B", "S2")]
+            .into_iter()
+            .map(|(name, serial)| {
+                let mut record = record(name, Some(serial), None);
+                record.name = naming.name(&record);
+                record
+            })
+            .collect();
+        assert_eq!(names(&records), ["ACCOUNTING", "ACCOUNTING"]);
+        naming.dedupe(&mut records);
+        assert_eq!(names(&records), ["ACCOUNTING", "ACCOUNTI-2"]);
+    }
+
+    #[test]
+    fn generated_names_fall_back_to_manufacturer_model_serial() {
+        let naming = policy(&[], 64, &["^desktop-[a-z0-9]{7}$"]);
+        let device = |name: &str, serial: Option<&str>, mac: Option<&str>| Record {
+            manufacturer: Some("Dell".into()),
+            model: Some("Latitude 5440".into()),
+            ..record(name, serial, mac)
+        };
+        for (record, name) in [
+            (
+                device(
+                    "00:11:22:33:44:55",
+                    Some("5CD1234"),
+                    Some("00:11:22:33:44:55"),
+                ),
+                "Dell-Latitude-5440-5CD1234",
+            ),
+            // the MAC stands in for the serial
+            (
+                device("001122334455", None, Some("00:11:22:33:44:55")),
+                "Dell-Latitude-5440-001122334455",
+            ),
+            (
+                device("Android_1a2b3c4d5e6f", None, Some("DA:A1:19:00:00:01")),
+                "Dell-Latitude-5440-daa119000001",
+            ),
+            (
+                device("DESKTOP-4F2KQ9Z", Some("5CD1234"), None),
+                "Dell-Latitude-5440-5CD1234",
+            ),
+            // named after its MAC in another notation than the MAC field
+            (
+                device(
+                    "da-a1-19-00-00-01",
+                    Some("5CD1234"),
+                    Some("DA:A1:19:00:00:01"),
+                ),
+                "Dell-Latitude-5440-5CD1234",
+            ),
+            (
+                device("", Some("5CD1234"), None),
+                "Dell-Latitude-5440-5CD1234",
+            ),
+            (record("", Some("5CD1234"), None), "device-5CD1234"),
+            // nothing to build a name from
+            (
+                device("Android_1a2b3c4d5e6f", None, None),
+                "Android_1a2b3c4d5e6f",
+            ),
+            (
+                device("JAKOB-DESKTOP", Some("5CD1234"), None),
+                "JAKOB-DESKTOP",
+            ),
+        ] {
+            assert_eq!(naming.name(&record), name, "{:?}", record.name);
+        }
+    }
+}
@@ -1,47 +1,81 @@
 use anyhow::{Context, Result};
-use futures::{stream, StreamExt};
-use reqwest::{Client, Error as ReqwestError};
+use futures::{stream, Stream, StreamExt, TryStreamExt};
+use reqwest::{Client, RequestBuilder, StatusCode};
 use serde::{Deserialize, Serialize};
-use std::{fmt::Debug, sync::Arc};
-use tokio::sync::Semaphore;
+use serde_json::{json, Value};
+use std::{fmt::Debug, pin::pin, sync::Arc};
+use tokio::{sync::Semaphore, time::Instant};
 
-use crate::config::NetBoxConfig;
+use crate::{
+    config::NetBoxConfig,
+    metrics,
+    netbox::{changes, models::NetBoxModel, query::Query},
+};
 use async_trait::async_trait;
+use tracing::{debug, error, info};
+use url::Url;
 
+/// An object that can be created in NetBox as is, see [`ApiClient::sync_objects`]
 #[async_trait]
 pub trait CreateTable: Send + Sync + std::fmt::Debug {
     async fn create(&self, api: &ApiClient) -> anyhow::Result<()>;
 }
 
+/// Client for the NetBox REST API.
+///
+/// Endpoints are given relative to `api_url` without slashes at either end,
+/// e.g. `dcim/devices`. Every request is recorded in the request metrics.
 #[derive(Debug, Clone)]
 pub struct ApiClient {
     client: Client,
     api_url: String,
     api_key: String,
     api_limit: usize,
+    run_id: Option<String>,
 }
 
+/// One page of a NetBox list response
 #[derive(Debug, Deserialize, Clone)]
 pub struct NetBoxResponse<T> {
-    count: i32,
-    next: Option<String>,
-    results: Vec<T>,
+    pub count: i32,
+    pub next: Option<String>,
+    pub results: Vec<T>,
 }
 
 impl ApiClient {
+    /// `config.api_limit` caps the requests [`ApiClient::sync_objects`] and
+    /// [`ApiClient::get`] run at once
     pub fn new(config: &NetBoxConfig) -> Self {
         let api_url = config.api_url.clone();
         let api_key = config.api_key.clone();
-        let api_limit = config.api_limit.clone();
+        let api_limit = config.api_limit;
 
         Self {
             client: Client::new(),
             api_url,
             api_key,
             api_limit,
+            run_id: None,
         }
     }
 
+    /// The same client, attributing every change it makes to the run `run_id`
+    /// through the changelog message of its writes, see [`changes::message`].
+    /// Writes also carry the id as `X-Request-ID`, which is informational only:
+    /// NetBox records its own request id, the header helps match proxy logs.
+    pub fn for_run(&self, run_id: &str) -> Self {
+        Self {
+            run_id: Some(run_id.to_string()),
+            ..self.clone()
+        }
+    }
+
+    /// The run this client attributes its changes to, if any
+    pub fn run_id(&self) -> Option<&str> {
+        self.run_id.as_deref()
+    }
+
+    /// Creates every object, `api_limit` at a time. Failures are logged, not returned.
     pub async fn sync_objects<T>(&self, objects: Vec<T>, semaphore: Arc<Semaphore>, name: &str)
     where
         T: CreateTable + 'static,
@@ -49,7 +83,7 @@ impl ApiClient {
         let client = Arc::new(self.clone());
         let api_limit = self.api_limit;
 
-        println!("Syncing {} {} objects...", objects.len(), name);
+        info!(count = objects.len(), name, "syncing objects");
 
         stream::iter(objects)
             .for_each_concurrent(api_limit, |obj| {
@@ -57,54 +91,85 @@ impl ApiClient {
                 let sem = semaphore.clone();
                 async move {
                     let permit = sem.acquire_owned().await.unwrap();
-                    println!("-> Creating: {:?}", obj);
+                    debug!(?obj, "creating");
                     if let Err(e) = obj.create(&client).await {
-                        eprintln!("Failed to create {:?}: {:?}", obj, e);
+                        error!(?obj, error = format!("{:#}", e), "failed to create");
                     } else {
-                        println!("Created: {:?}", obj);
+                        debug!(?obj, "created");
                     }
                     drop(permit);
                 }
             })
             .await;
 
-        println!("Finished syncing {}.", name);
+        info!(name, "finished syncing");
     }
 
-    // Generic GET request
-    pub async fn get<T>(&self, endpoint: &str, id: Option<i32>) -> Result<Vec<T>, ReqwestError>
+    /// Lists `endpoint`, which may include filters such as `dcim/devices/?site=hq`,
+    /// or fetches the single object `id`. After the first page
+    /// the others are requested `api_limit` at a time at the offsets its `count`
+    /// implies. Listing sorts by id unless `endpoint` orders it, so objects
+    /// created meanwhile land behind the last page and are read by following its
+    /// `next` link. Any page NetBox
+    /// doesn't return fails the listing, a partial list would look like objects
+    /// missing from NetBox.
+    pub async fn get<T>(&self, endpoint: &str, id: Option<i32>) -> Result<Vec<T>>
     where
         T: for<'de> Deserialize<'de> + Debug,
     {
-        let mut next_link = match id {
-            Some(id) => Some(format!("{}/{}/{}", self.api_url, endpoint, id)),
-            None => Some(format!("{}/{}", self.api_url, endpoint)),
+        let link = match id {
+            Some(id) => format!("{}/{}/{}", self.api_url, endpoint, id),
+            None => {
+                // `endpoint` may carry filters of its own
+                let link = format!("{}/{}", self.api_url, endpoint);
+                match Url::parse(&link) {
+                    Ok(mut url) => {
+                        if !url.query_pairs().any(|(k, _)| k == "ordering") {
+                            url.query_pairs_mut().append_pair("ordering", "id");
+                        }
+                        url.to_string()
+                    }
+                    // left to fail as a request error
+                    Err(_) => link,
+                }
+            }
         };
-        let mut results = Vec::new();
+        let first = self.page::<T>(&link).await?;
+        let mut results = first.results;
+        let mut next_link = first.next.filter(|s| !s.is_empty());
 
-        while let Some(link) = &next_link {
-            let response = self
-                .client
-                .get(link)
-                .header("Authorization", format!("Token {}", self.api_key))
-                .header("Content-Type", "application/json")
-                .send()
-                .await?;
-
-            if response.status().is_success() {
-                let response_data: NetBoxResponse<T> = response.json().await?;
-                results.extend(response_data.results);
-                next_link = response_data.next.clone().filter(|s| !s.is_empty());
-            } else {
-                println!("Failed to get data: {:?}", response.status());
-                break;
+        if let Some(next) = &next_link {
+            let links = page_links(next, results.len(), first.count);
+            if !links.is_empty() {
+                debug!(
+                    endpoint,
+                    count = first.count,
+                    pages = links.len() + 1,
+                    "fetching pages"
+                );
+                let pages: Vec<_> = stream::iter(links)
+                    .map(|link| async move { self.page::<T>(&link).await })
+                    .buffered(self.api_limit)
+                    .collect()
+                    .await;
+                for page in pages {
+                    let page = page?;
+                    results.extend(page.results);
+                    next_link = page.next.filter(|s| !s.is_empty());
+                }
             }
         }
 
+        while let Some(link) = &next_link {
+            let page = self.page::<T>(link).await?;
+            results.extend(page.results);
+            next_link = page.next.filter(|s| !s.is_empty());
+        }
+
         Ok(results)
     }
 
-    // Generic POST request
+    /// Creates an object at `endpoint` and returns NetBox's representation of it
     pub async fn post<T, B>(&self, endpoint: &str, body: &B) -> Result<T>
     where
         T: for<'de> Deserialize<'de>,
@@ -112,37 +177,38 @@ impl ApiClient {
     {
         let url = format!("{}/{}/", self.api_url, endpoint);
 
-        let response = self
+        let started = Instant::now();
+        let request = self
             .client
             .post(&url)
             .header("Authorization", format!("Token {}", self.api_key))
-            .header("Content-Type", "application/json")
-            .json(body)
+            .header("Content-Type", "application/json");
+        let response = self
+            .attribute(request, body)?
             .send()
             .await
             .context("Failed to send POST request")?;
 
         let status = response.status();
+        self.observe("POST", &url, status.as_str(), started);
         let text = response
             .text()
             .await
-            .context("❌ Failed to read response body")?;
+            .context("Failed to read response body")?;
 
         if !status.is_success() {
-            return Err(anyhow::anyhow!("❌ NetBox returned {}:\n{}", status, text));
+            return Err(anyhow::anyhow!("NetBox returned {}:\n{}", status, text));
         }
 
-        let parsed = serde_json::from_str::<T>(&text).context(format!(
-            "❌ Failed to parse NetBox JSON response:\n{}",
-            text
-        ))?;
+        let parsed = serde_json::from_str::<T>(&text)
+            .context(format!("Failed to parse NetBox JSON response:\n{}", text))?;
 
         Ok(parsed)
 
         //response.json::<T>().await
     }
 
-    // Generic PATCH request
+    /// Updates the object at `endpoint` (including its id) with the fields in `body`
     pub async fn patch<T, B>(&self, endpoint: &str, body: &B) -> Result<T>
     where
         T: for<'de> Deserialize<'de>,
@@ -150,20 +216,20 @@ impl ApiClient {
     {
         let url = format!("{}/{}/", self.api_url, endpoint);
 
-        let response = self
+        let started = Instant::now();
+        let request = self
             .client
             .patch(&url)
             .header("Authorization", format!("Token {}", self.api_key))
-            .header("Content-Type", "application/json")
-            .json(body)
-            .send()
-            .await?;
+            .header("Content-Type", "application/json");
+        let response = self.attribute(request, body)?.send().await?;
 
         let status = response.status();
+        self.observe("PATCH", &url, status.as_str(), started);
         let text = response.text().await?;
 
         if !status.is_success() {
-            return Err(anyhow::anyhow!("❌ NetBox returned {}:\n{}", status, text));
+            return Err(anyhow::anyhow!("NetBox returned {}:\n{}", status, text));
         }
 
         let parsed = serde_json::from_str::<T>(&text)?;
@@ -172,4 +238,233 @@ impl ApiClient {
 
         //response.json::<T>().await
     }
+
+    /// Overwrites the object at `endpoint` (including its id) with `body`
+    pub async fn put<T, B>(&self, endpoint: &str, body: &B) -> Result<T>
+    where
+        T: for<'de> Deserialize<'de>,
+        B: Serialize + Debug,
+    {
+        let url = format!("{}/{}/", self.api_url, endpoint);
+
+        let started = Instant::now();
+        let request = self
+            .client
+            .put(&url)
+            .header("Authorization", format!("Token {}", self.api_key))
+            .header("Content-Type", "application/json");
+        let response = self
+            .attribute(request, body)?
+            .send()
+            .await
+            .context(format!("Failed to send PUT {}", url))?;
+
+        let status = response.status();
+        self.observe("PUT", &url, status.as_str(), started);
+        let text = response.text().await?;
+
+        if !status.is_success() {
+            return Err(anyhow::anyhow!("NetBox returned {}:\n{}", status, text));
+        }
+
+        serde_json::from_str::<T>(&text)
+            .context(format!("Failed to parse NetBox JSON response:\n{}", text))
+    }
+
+    /// The object `id` at `endpoint`, None when NetBox doesn't have it
+    pub async fn get_one<T>(&self, endpoint: &str, id: impl ToString) -> Result<Option<T>>
+    where
+        T: for<'de> Deserialize<'de>,
+    {
+        let url = format!("{}/{}/{}/", self.api_url, endpoint, id.to_string());
+
+        let started = Instant::now();
+        let response = self
+            .client
+            .get(&url)
+            .header("Authorization", format!("Token {}", self.api_key))
+            .header("Accept", "application/json")
+            .send()
+            .await
+            .context(format!("Failed to send GET {}", url))?;
+
+        let status = response.status();
+        self.observe("GET", &url, status.as_str(), started);
+        if status == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let text = response.text().await?;
+
+        if !status.is_success() {
+            return Err(anyhow::anyhow!("NetBox returned {}:\n{}", status, text));
+        }
+
+        serde_json::from_str::<T>(&text)
+            .map(Some)
+            .context(format!("Failed to parse NetBox JSON response:\n{}", text))
+    }
+
+    /// Deletes the object `id` at `endpoint`
+    pub async fn delete(&self, endpoint: &str, id: impl ToString) -> Result<()> {
+        let url = format!("{}/{}/{}/", self.api_url, endpoint, id.to_string());
+        self.send_delete(&url, Value::Null).await
+    }
+
+    /// Deletes every object in `ids` at `endpoint` with a single request.
+    /// NetBox deletes all of them or, when one can't be deleted, none.
+    pub async fn bulk_delete<I: Serialize>(&self, endpoint: &str, ids: &[I]) -> Result<()> {
+        if ids.is_empty() {
+            return Ok(());
+        }
+        let url = format!("{}/{}/", self.api_url, endpoint);
+        let body: Vec<Value> = ids.iter().map(|id| json!({ "id": id })).collect();
+        self.send_delete(&url, Value::Array(body)).await
+    }
+
+    async fn send_delete(&self, url: &str, body: Value) -> Result<()> {
+        let started = Instant::now();
+        let request = self
+            .client
+            .delete(url)
+            .header("Authorization", format!("Token {}", self.api_key));
+        let response = self
+            .attribute(request, &body)?
+            .send()
+            .await
+            .context(format!("Failed to send DELETE {}", url))?;
+
+        let status = response.status();
+        self.observe("DELETE", url, status.as_str(), started);
+        if !status.is_success() {
+            let text = response.text().await.unwrap_or_default();
+            return Err(anyhow::anyhow!("NetBox returned {}:\n{}", status, text));
+        }
+        Ok(())
+    }
+
+    /// Every object matching `query`, reading all pages
+    pub async fn query<T: NetBoxModel>(&self, query: &Query<T>) -> Result<Vec<T>> {
+        let pages: Vec<Vec<T>> = self.query_pages(query).try_collect().await?;
+        Ok(pages.into_iter().flatten().collect())
+    }
+
+    /// The first object matching `query`, fetching a single result
+    pub async fn first<T: NetBoxModel>(&self, query: &Query<T>) -> Result<Option<T>> {
+        let query = query.clone().page_size(1);
+        let mut pages = pin!(self.query_pages(&query));
+        let first = pages.try_next().await?;
+        Ok(first.and_then(|page| page.into_iter().next()))
+    }
+
+    /// Pages of `query` as they arrive, the next page is only requested once
+    /// the previous one was consumed. A non-success status ends the stream with
+    /// an error.
+    pub fn query_pages<'a, T: NetBoxModel + 'a>(
+        &'a self,
+        query: &Query<T>,
+    ) -> impl Stream<Item = Result<Vec<T>>> + 'a {
+        let first = query
+            .url(&self.api_url)
+            .map(String::from)
+            .context(format!("Invalid query for {}", T::get_endpoint()));
+        stream::try_unfold(Some(first), move |next| async move {
+            let Some(url) = next else {
+                return Ok(None);
+            };
+            let page: NetBoxResponse<T> = self.page(&url?).await?;
+            let next = page.next.filter(|s| !s.is_empty()).map(Ok);
+            Ok(Some((page.results, next)))
+        })
+    }
+
+    // One page of a listing, a non-success status is an error
+    async fn page<T: for<'de> Deserialize<'de>>(&self, url: &str) -> Result<NetBoxResponse<T>> {
+        let started = Instant::now();
+        let response = self
+            .client
+            .get(url)
+            .header("Authorization", format!("Token {}", self.api_key))
+            .header("Accept", "application/json")
+            .send()
+            .await
+            .context(format!("Failed to send GET {}", url))?;
+        let status = response.status();
+        self.observe("GET", url, status.as_str(), started);
+
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(anyhow::anyhow!("NetBox returned {}:\n{}", status, body));
+        }
+        response
+            .json()
+            .await
+            .context(format!("Failed to parse NetBox page {}", url))
+    }
+
+    // Adds `body` (none when null) to a write, along with the run it belongs to
+    // when there is one. NetBox versions without changelog messages ignore the
+    // extra field; the header is for logs in front of NetBox, which ignores it.
+    fn attribute<B: Serialize>(&self, request: RequestBuilder, body: &B) -> Result<RequestBuilder> {
+        let mut body = serde_json::to_value(body).context("Failed to serialize request body")?;
+        let Some(run_id) = &self.run_id else {
+            return Ok(match body {
+                Value::Null => request,
+                body => request.json(&body),
+            });
+        };
+        if body.is_null() {
+            body = json!({});
+        }
+        let message = Value::String(changes::message(run_id));
+        // bulk requests take the message per object
+        let objects = match &mut body {
+            Value::Array(items) => items.iter_mut().collect(),
+            object => vec![object],
+        };
+        for object in objects {
+            if let Value::Object(fields) = object {
+                fields.insert("changelog_message".into(), message.clone());
+            }
+        }
+        Ok(request.header("X-Request-ID", run_id).json(&body))
+    }
+
+    fn observe(&self, method: &str, url: &str, status: &str, started: Instant) {
+        // without the query string, filters and offsets would each get their own series
+        let path = url.split('?').next().unwrap_or(url);
+        let endpoint = path.strip_prefix(&self.api_url).unwrap_or(path);
+        metrics::observe_request(method, endpoint, status, started.elapsed());
+    }
+}
+
+// Links to the pages after the first, from its `next` link: the same query at
+// every further offset below `count`, a page size apart
+fn page_links(next: &str, fetched: usize, count: i32) -> Vec<String> {
+    let Ok(next) = Url::parse(next) else {
+        return Vec::new();
+    };
+    let params: Vec<(String, String)> = next.query_pairs().into_owned().collect();
+    let param = |name: &str| {
+        params
+            .iter()
+            .find(|(k, _)| k == name)
+            .and_then(|(_, v)| v.parse::<usize>().ok())
+    };
+    let limit = param("limit").unwrap_or(fetched);
+    let start = param("offset").unwrap_or(fetched);
+    if limit == 0 {
+        return Vec::new();
+    }
+
+    (start..count.max(0) as usize)
+        .step_by(limit)
+        .map(|offset| {
+            let mut link = next.clone();
+            link.query_pairs_mut()
+                .clear()
+                .extend_pairs(params.iter().filter(|(k, _)| k != "offset"))
+                .append_pair("offset", &offset.to_string());
+            link.to_string()
+        })
+        .collect()
 }
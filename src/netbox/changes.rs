@@ -0,0 +1,151 @@
+use crate::netbox::{api::ApiClient, models::NetBoxModel, query::Query};
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+/// NetBox object types the ingester writes, with their endpoints
+pub const OBJECT_TYPES: [(&str, &str); 10] = [
+    ("dcim.device", "dcim/devices"),
+    ("dcim.devicerole", "dcim/device-roles"),
+    ("dcim.devicetype", "dcim/device-types"),
+    ("dcim.manufacturer", "dcim/manufacturers"),
+    ("dcim.platform", "dcim/platforms"),
+    ("dcim.site", "dcim/sites"),
+    ("extras.tag", "extras/tags"),
+    ("ipam.ipaddress", "ipam/ip-addresses"),
+    ("tenancy.contact", "tenancy/contacts"),
+    (
+        "virtualization.virtualmachine",
+        "virtualization/virtual-machines",
+    ),
+];
+
+/// The changelog message of every change made by the run `run_id`
+pub fn message(run_id: &str) -> String {
+    format!("netbox-ingester run {}", run_id)
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeAction {
+    Create,
+    Update,
+    Delete,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Action {
+    value: ChangeAction,
+}
+
+/// One entry of the NetBox changelog
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ObjectChange {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<u32>,
+    pub time: DateTime<Utc>,
+    pub action: Action,
+    /// e.g. `dcim.device`
+    pub changed_object_type: String,
+    pub changed_object_id: u32,
+    pub object_repr: String,
+    #[serde(default)]
+    pub message: Option<String>,
+    /// The object before the change, None for creations
+    #[serde(default)]
+    pub prechange_data: Option<Value>,
+    /// The object after the change, None for deletions
+    #[serde(default)]
+    pub postchange_data: Option<Value>,
+}
+
+impl ObjectChange {
+    pub fn action(&self) -> ChangeAction {
+        self.action.value
+    }
+
+    /// Endpoint of the changed object, None for types the ingester doesn't write
+    pub fn endpoint(&self) -> Option<&'static str> {
+        OBJECT_TYPES
+            .iter()
+            .find(|(object_type, _)| *object_type == self.changed_object_type)
+            .map(|(_, endpoint)| *endpoint)
+    }
+}
+
+#[async_trait]
+impl NetBoxModel for ObjectChange {
+    type Id = u32;
+
+    fn get_id(&self) -> Option<Self::Id> {
+        self.id
+    }
+
+    fn get_slug(&self) -> String {
+        format!("{}-{}", self.changed_object_type, self.changed_object_id)
+    }
+
+    fn get_endpoint() -> &'static str {
+        "core/object-changes"
+    }
+
+    fn set_id(&mut self, id: Self::Id) {
+        self.id = Some(id);
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct ChangeCounts {
+    pub created: usize,
+    pub updated: usize,
+    pub deleted: usize,
+}
+
+/// What one run changed, read back from the NetBox changelog
+#[derive(Debug, Clone, Serialize)]
+pub struct RunChanges {
+    pub run_id: String,
+    /// keyed by object type, e.g. `dcim.device`
+    pub counts: BTreeMap<String, ChangeCounts>,
+    /// oldest first
+    pub changes: Vec<ObjectChange>,
+}
+
+impl RunChanges {
+    /// The changes NetBox recorded with the changelog message of `run_id`
+    pub async fn fetch(api: &ApiClient, run_id: &str) -> Result<Self> {
+        let message = message(run_id);
+        // only the message ties a change to the run, NetBox keeps its own request ids
+        let query = Query::<ObjectChange>::new()
+            .filter("message", &message)
+            .order_by("time");
+        // in case a NetBox version ignores the filter
+        let mut changes: Vec<ObjectChange> = api
+            .query(&query)
+            .await?
+            .into_iter()
+            .filter(|c| c.message.as_deref() == Some(message.as_str()))
+            .collect();
+        changes.sort_by_key(|c| (c.time, c.id));
+
+        let mut counts: BTreeMap<String, ChangeCounts> = BTreeMap::new();
+        for change in &changes {
+            let entry = counts
+                .entry(change.changed_object_type.clone())
+                .or_default();
+            match change.action() {
+                ChangeAction::Create => entry.created += 1,
+                ChangeAction::Update => entry.updated += 1,
+                ChangeAction::Delete => entry.deleted += 1,
+            }
+        }
+        Ok(Self {
+            run_id: run_id.to_string(),
+            counts,
+            changes,
+        })
+    }
+}
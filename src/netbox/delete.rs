@@ -0,0 +1,135 @@
+use crate::{
+    config::DeleteConfig,
+    netbox::{api::ApiClient, models::NetBoxModel},
+    report::{Outcome, RunReport},
+    utils::sanitize_slug,
+};
+use anyhow::{bail, Result};
+use std::sync::Mutex;
+use tracing::{info, warn};
+
+/// Deletes NetBox objects within the limits of one run.
+///
+/// Objects tagged with one of `protect_tags` are never deleted, and a batch
+/// that would take the run past `max_per_run` deletions is refused as a whole,
+/// so a source that suddenly returns nothing can't empty NetBox.
+#[derive(Debug)]
+pub struct Deletions {
+    max_per_run: usize,
+    protect_tags: Vec<String>,
+    deleted: Mutex<usize>,
+}
+
+impl Deletions {
+    /// Limits for a new run, nothing deleted yet
+    pub fn new(config: &DeleteConfig) -> Self {
+        Self {
+            max_per_run: config.max_per_run,
+            protect_tags: config
+                .protect_tags
+                .iter()
+                .map(|t| sanitize_slug(t))
+                .collect(),
+            deleted: Mutex::new(0),
+        }
+    }
+
+    /// Whether the object carries a protection tag
+    pub fn is_protected<T: NetBoxModel>(&self, object: &T) -> bool {
+        self.protects(&object.get_tag_slugs())
+    }
+
+    /// Whether one of the tag slugs `slugs` is a protection tag
+    pub fn protects(&self, slugs: &[String]) -> bool {
+        slugs
+            .iter()
+            .any(|slug| self.protect_tags.contains(&sanitize_slug(slug)))
+    }
+
+    /// Counts `count` deletions from `endpoint` against the limit before they
+    /// are sent, so concurrent batches can't both slip under it. Fails without
+    /// counting any when they would exceed it.
+    pub fn reserve(&self, endpoint: &str, count: usize) -> Result<()> {
+        let mut deleted = self.deleted.lock().unwrap();
+        if *deleted + count > self.max_per_run {
+            bail!(
+                "Refusing to delete {} objects from {}: delete.max_per_run is {} and {} were deleted already",
+                count,
+                endpoint,
+                self.max_per_run,
+                *deleted
+            );
+        }
+        *deleted += count;
+        Ok(())
+    }
+
+    /// Gives back reserved deletions that failed
+    pub fn release(&self, count: usize) {
+        *self.deleted.lock().unwrap() -= count;
+    }
+
+    /// Objects deleted so far in this run
+    pub fn deleted(&self) -> usize {
+        *self.deleted.lock().unwrap()
+    }
+
+    /// Deletes `objects` with one bulk request and returns how many went.
+    /// Protected objects and objects without an id are skipped in the report.
+    /// Fails without deleting anything when the rest exceeds what the run may
+    /// still delete.
+    pub async fn delete<T: NetBoxModel>(
+        &self,
+        api: &ApiClient,
+        objects: &[T],
+        report: &Mutex<RunReport>,
+    ) -> Result<usize> {
+        let endpoint = T::get_endpoint();
+        let mut targets = Vec::new();
+        for object in objects {
+            let key = object.get_cache_key();
+            if self.is_protected(object) {
+                info!(endpoint, %key, "protected, not deleting");
+                report
+                    .lock()
+                    .unwrap()
+                    .skip(endpoint, &key, "protected from deletion by tag");
+            } else if let Some(id) = object.get_id() {
+                targets.push((key, id));
+            } else {
+                report
+                    .lock()
+                    .unwrap()
+                    .skip(endpoint, &key, "not in NetBox, nothing to delete");
+            }
+        }
+        if targets.is_empty() {
+            return Ok(0);
+        }
+
+        self.reserve(endpoint, targets.len())?;
+
+        let ids: Vec<&T::Id> = targets.iter().map(|(_, id)| id).collect();
+        if let Err(e) = api.bulk_delete(endpoint, &ids).await {
+            self.release(targets.len());
+            warn!(
+                endpoint,
+                count = targets.len(),
+                error = format!("{:#}", e),
+                "failed to delete"
+            );
+            let mut report = report.lock().unwrap();
+            for (key, _) in &targets {
+                report.fail(endpoint, key, &e);
+            }
+            return Err(e);
+        }
+
+        let mut report = report.lock().unwrap();
+        for (key, id) in &targets {
+            info!(endpoint, %key, id = %id.to_string(), "deleted");
+            report.record(endpoint, Outcome::Deleted);
+        }
+        Ok(targets.len())
+    }
+}
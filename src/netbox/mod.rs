@@ -1,2 +1,5 @@
 pub mod api;
+pub mod changes;
+pub mod delete;
 pub mod models;
+pub mod query;
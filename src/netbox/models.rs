@@ -1,31 +1,81 @@
 use crate::{
-    fetch::{
-        azure::{IntuneDevice, IntuneUser},
-        fortigate::FortiGateDevice,
-        nagiosxi::HostStatus,
-    },
+    cache::LocalCache,
+    fetch::{azure::IntuneUser, nagiosxi::HostStatus},
+    metrics,
     netbox::api::{ApiClient, CreateTable},
+    ownership::{Control, DeviceField, Ownership},
+    report::{Outcome, RunReport},
+    tags::TagPolicy,
     utils::sanitize_slug,
-    LocalCache,
 };
 use anyhow::{anyhow, Context, Result};
 use async_trait::async_trait;
-use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use std::fmt::Debug;
+use serde_json::Value;
+use std::{collections::BTreeMap, fmt::Debug, sync::Mutex};
+use tracing::{debug, info, instrument, trace, Span};
 
+/// An object type stored in NetBox
 #[async_trait]
 pub trait NetBoxModel: Send + Sync + Clone + Debug + Serialize + for<'de> Deserialize<'de> {
-    type Id: ToString + Clone;
+    type Id: ToString + Clone + Serialize + Send + Sync;
 
+    /// NetBox id, None until the object is created or looked up
     fn get_id(&self) -> Option<Self::Id>;
     fn get_slug(&self) -> String;
+    /// API endpoint relative to the API root, e.g. `dcim/devices`
     fn get_endpoint() -> &'static str;
     fn set_id(&mut self, id: Self::Id);
 
+    /// Key of the object in [`LocalCache`], the slug unless the type needs more
     fn get_cache_key(&self) -> String {
         self.get_slug()
     }
+
+    /// Slugs of the object's tags, empty for types without tags
+    fn get_tag_slugs(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// The object with id `id`, None when NetBox has no such object
+    async fn get_by_id(api: &ApiClient, id: Self::Id) -> Result<Option<Self>> {
+        api.get_one(Self::get_endpoint(), id).await
+    }
+
+    /// Applies `changes` to the object in NetBox and returns the updated object
+    async fn update<B>(&self, api: &ApiClient, changes: &B) -> Result<Self>
+    where
+        B: Serialize + Debug + Sync,
+    {
+        let endpoint = format!(
+            "{}/{}",
+            Self::get_endpoint(),
+            self.require_id()?.to_string()
+        );
+        api.patch(&endpoint, changes).await
+    }
+
+    /// Overwrites the object in NetBox with this one
+    async fn replace(&self, api: &ApiClient) -> Result<Self> {
+        let endpoint = format!(
+            "{}/{}",
+            Self::get_endpoint(),
+            self.require_id()?.to_string()
+        );
+        api.put(&endpoint, self).await
+    }
+
+    /// Deletes the object from NetBox. This skips the safety rails,
+    /// see [`Deletions`](crate::netbox::delete::Deletions) for those.
+    async fn delete(&self, api: &ApiClient) -> Result<()> {
+        api.delete(Self::get_endpoint(), self.require_id()?).await
+    }
+
+    /// The id, or an error for objects not in NetBox yet
+    fn require_id(&self) -> Result<Self::Id> {
+        self.get_id()
+            .ok_or_else(|| anyhow!("{} `{}` has no id", Self::get_endpoint(), self.get_slug()))
+    }
 }
 
 #[async_trait]
@@ -53,6 +103,8 @@ pub struct Tag {
     pub slug: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub color: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
 }
 impl Tag {
     pub fn new(name: String) -> Self {
@@ -61,6 +113,7 @@ impl Tag {
             slug: sanitize_slug(&name),
             name,
             color: None,
+            description: None,
         }
     }
 }
@@ -86,7 +139,7 @@ impl NetBoxModel for Tag {
     }
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
 #[serde(rename_all = "lowercase")]
 pub enum StatusOptions {
     Active,
@@ -107,6 +160,10 @@ impl Status {
     pub fn from_value(value: StatusOptions) -> Self {
         Status { value }
     }
+
+    pub fn value(&self) -> &StatusOptions {
+        &self.value
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -229,6 +286,8 @@ pub struct Device {
     pub platform: Option<Platform>,
     pub primary_ip4: Option<NetBoxIp4>,
     pub tags: Option<Vec<Tag>>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub custom_fields: BTreeMap<String, Value>,
 }
 
 #[async_trait]
@@ -254,6 +313,10 @@ impl NetBoxModel for Device {
     fn set_id(&mut self, id: Self::Id) {
         self.id = Some(id);
     }
+
+    fn get_tag_slugs(&self) -> Vec<String> {
+        self.tags.iter().flatten().map(|t| t.slug.clone()).collect()
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -269,6 +332,57 @@ pub struct PostDevice {
     pub serial: Option<String>,
     pub platform: Option<u32>,
     pub tags: Vec<u32>,
+    // only the fields this ingester manages, others are left untouched
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub custom_fields: BTreeMap<String, Value>,
+}
+
+impl PostDevice {
+    // True when `current` (as read from NetBox) already holds this payload
+    pub fn matches(&self, current: &Device) -> bool {
+        self.matches_fields(current, &DeviceField::ALL)
+    }
+
+    // True when `current` holds this payload's value for each of `fields`
+    pub fn matches_fields(&self, current: &Device, fields: &[DeviceField]) -> bool {
+        let non_empty = |s: &Option<String>| s.clone().filter(|s| !s.is_empty());
+
+        fields.iter().all(|field| match field {
+            DeviceField::Name => current.name == self.name,
+            DeviceField::DeviceType => {
+                current.device_type.as_ref().and_then(|d| d.id) == Some(self.device_type)
+            }
+            DeviceField::Role => current.role.as_ref().and_then(|r| r.id) == Some(self.role),
+            DeviceField::Site => current.site.as_ref().and_then(|s| s.id) == Some(self.site),
+            DeviceField::Status => current.status.as_ref().map(|s| &s.value) == Some(&self.status),
+            DeviceField::Serial => non_empty(&current.serial) == non_empty(&self.serial),
+            DeviceField::Platform => current.platform.as_ref().and_then(|p| p.id) == self.platform,
+            DeviceField::Tags => {
+                let mut current_tags: Vec<u32> =
+                    current.tags.iter().flatten().filter_map(|t| t.id).collect();
+                current_tags.sort_unstable();
+                let mut tags = self.tags.clone();
+                tags.sort_unstable();
+                current_tags == tags
+            }
+            DeviceField::CustomFields => self
+                .custom_fields
+                .iter()
+                .all(|(k, v)| current.custom_fields.get(k) == Some(v)),
+        })
+    }
+
+    // The payload cut down to `fields`, for updating only what the ingester owns
+    pub fn only(&self, fields: &[DeviceField]) -> Result<Value> {
+        let Value::Object(all) = serde_json::to_value(self)? else {
+            return Err(anyhow!("device payload is not an object"));
+        };
+        let owned = all
+            .into_iter()
+            .filter(|(k, _)| fields.iter().any(|f| f.name() == k))
+            .collect();
+        Ok(Value::Object(owned))
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -277,6 +391,8 @@ pub struct Platform {
     pub id: Option<u32>,
     pub name: String,
     pub slug: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub manufacturer: Option<Manufacturer>,
 }
 impl Platform {
     pub fn new(name: String) -> Self {
@@ -284,6 +400,7 @@ impl Platform {
             id: None,
             slug: sanitize_slug(&name),
             name,
+            manufacturer: None,
         }
     }
 }
@@ -309,7 +426,7 @@ impl NetBoxModel for Platform {
     }
 
     fn get_cache_key(&self) -> String {
-        self.name.to_lowercase().into()
+        self.name.to_lowercase()
     }
 }
 
@@ -360,6 +477,10 @@ pub struct DeviceType {
     pub id: Option<u32>,
     pub model: String,
     pub slug: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub part_number: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub u_height: Option<f64>,
 }
 
 impl DeviceType {
@@ -370,6 +491,8 @@ impl DeviceType {
             manufacturer,
             model,
             slug,
+            part_number: None,
+            u_height: None,
         }
     }
 }
@@ -505,17 +628,22 @@ pub struct ContactList {
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct VirtualMachine {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<u32>,
     name: String,
-    status: String,
-    site: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status: Option<Status>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    site: Option<Site>,
     #[serde(skip_serializing_if = "Option::is_none")]
     role: Option<DeviceRole>,
     #[serde(skip_serializing_if = "Option::is_none")]
     serial: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    platform: Option<String>,
+    platform: Option<Platform>,
+    // a decimal in NetBox, e.g. 2.0
     #[serde(skip_serializing_if = "Option::is_none")]
-    vcpus: Option<usize>,
+    vcpus: Option<f64>,
     #[serde(skip_serializing_if = "Option::is_none")]
     memory: Option<usize>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -529,7 +657,7 @@ impl NetBoxModel for VirtualMachine {
     type Id = u32;
 
     fn get_id(&self) -> Option<Self::Id> {
-        None // Replace with real ID logic if your struct gets populated with NetBox IDs
+        self.id
     }
 
     fn get_slug(&self) -> String {
@@ -540,8 +668,8 @@ impl NetBoxModel for VirtualMachine {
         "virtualization/virtual-machines"
     }
 
-    fn set_id(&mut self, _id: Self::Id) {
-        // Handle if needed
+    fn set_id(&mut self, id: Self::Id) {
+        self.id = Some(id);
     }
 }
 
@@ -563,9 +691,10 @@ impl From<IntuneUser> for Contact {
 impl From<HostStatus> for VirtualMachine {
     fn from(value: HostStatus) -> Self {
         VirtualMachine {
+            id: None,
             name: value.host_name,
-            status: String::from("active"),
-            site: 5,
+            status: Some(Status::from_value(StatusOptions::Active)),
+            site: None,
             role: None,
             serial: None,
             platform: None,
@@ -577,74 +706,6 @@ impl From<HostStatus> for VirtualMachine {
     }
 }
 
-impl From<IntuneDevice> for Device {
-    fn from(value: IntuneDevice) -> Self {
-        Device {
-            name: value.name,
-            id: None,
-            device_type: Some(DeviceType::new(
-                Manufacturer::new(value.manufacturer.to_string()),
-                value.model.to_string(),
-            )),
-            role: Some(DeviceRole::new("Desktop".to_string())),
-            site: Some(Site::new("TOS".to_string())),
-            status: Some(Status::from_value(StatusOptions::Active)),
-            serial: Some(value.serial),
-            platform: Some(Platform::new(format!("{} {}", value.os, value.os_version))),
-            primary_ip4: None,
-            tags: Some(vec![Tag::new("AAD".to_string())]),
-        }
-    }
-}
-
-impl From<FortiGateDevice> for Device {
-    fn from(value: FortiGateDevice) -> Self {
-        let status = Some(if value.is_online {
-            Status::from_value(StatusOptions::Active)
-        } else {
-            Status::from_value(StatusOptions::Offline)
-        });
-        let platform = {
-            if let Some(os) = value.os_name {
-                Some(Platform::new(os.to_string()))
-            } else {
-                None
-            }
-        };
-        let primary_ip4 = {
-            if let Some(ip) = value.ipv4_address {
-                Some(NetBoxIp4::new(ip.to_string()))
-            } else {
-                None
-            }
-        };
-        let name = {
-            if let Some(hostname) = value.hostname.clone() {
-                hostname
-            } else {
-                value.mac.clone()
-            }
-        };
-        let device_type = match (value.device_type, value.hardware_vendor) {
-            (Some(ty), Some(vendor)) => Some(DeviceType::new(Manufacturer::new(vendor), ty)),
-            _ => None,
-        };
-        let role = Some(DeviceRole::new("Desktop".to_string()));
-        Device {
-            name,
-            id: None,
-            device_type,
-            role,
-            site: Some(Site::new("TOS".to_string())),
-            status,
-            serial: None,
-            platform,
-            primary_ip4,
-            tags: Some(vec![Tag::new("FortiGate".to_string())]),
-        }
-    }
-}
-
 // Note: Before converting Device to PostDevice, ensure_tags() should be called
 // to sync tags with NetBox and populate their IDs.
 impl TryFrom<Device> for PostDevice {
@@ -693,136 +754,121 @@ impl TryFrom<Device> for PostDevice {
             serial: value.serial,
             platform: value.platform.as_ref().and_then(|p| p.id),
             tags: id_tags,
+            custom_fields: value.custom_fields,
         })
     }
 }
 
 impl Device {
-    pub async fn push_to_netbox(mut self, api: &ApiClient, cache: &LocalCache) -> Result<()> {
-        // 1️⃣ Normalize and log the cache key
-        let key = self.get_cache_key();
-        println!("🔍 [push_to_netbox] lookup key=`{}`", key);
-
-        // 2️⃣ Try cache
-        if let Some(cached) = cache.devices.get(&key) {
+    #[instrument(
+        name = "push_device",
+        skip_all,
+        fields(device = %self.get_cache_key(), netbox_id = tracing::field::Empty)
+    )]
+    pub async fn push_to_netbox(
+        mut self,
+        api: &ApiClient,
+        cache: &LocalCache,
+        ownership: &Ownership,
+        tags: &TagPolicy,
+        report: &Mutex<RunReport>,
+    ) -> Result<()> {
+        // 1️⃣ Normalize the cache key
+        let key = cache.device_key(&self.name);
+
+        // 2️⃣ Try cache, keeping the current NetBox state to diff against
+        let current = cache.devices.get(&key).map(|c| c.clone());
+        metrics::cache_lookup(Self::get_endpoint(), current.is_some());
+        if let Some(cached) = &current {
             if let Some(cached_id) = cached.get_id() {
-                println!("✅ [push_to_netbox] cache HIT `{}` → id={}", key, cached_id);
+                debug!(id = cached_id, "cache hit");
                 self.id = Some(cached_id);
+                Span::current().record("netbox_id", cached_id);
             }
         } else {
-            println!("❌ [push_to_netbox] cache MISS `{}`", key);
+            debug!("cache miss");
+        }
+
+        // 3️⃣ Check who owns the device: locked ones are left alone, ours carry the managed tag
+        let control = match &current {
+            Some(current) if self.id.is_some() => ownership.control(current),
+            _ => Control::Managed,
+        };
+        if let Control::Locked(tag) = &control {
+            debug!(%tag, "device locked");
+            report.lock().unwrap().skip(
+                Self::get_endpoint(),
+                &key,
+                format!("locked by tag `{}`", tag),
+            );
+            return Ok(());
+        }
+        if control == Control::Managed {
+            self.push_tag(ownership.managed_tag());
         }
+        // keeping manual tags and dropping derived ones that no longer apply
+        tags.merge(&mut self, current.as_ref());
 
-        // 3️⃣ Ensure all related NetBox objects (types, roles, tags, etc.) exist
-        //self.ensure_components(api, cache).await?;
+        // 4️⃣ Ensure all related NetBox objects (types, roles, tags, etc.) exist
         cache
-            .ensure_device_components(&mut self, api)
+            .ensure_device_components(&mut self, api, report)
             .await
             .context(format!("While ensuring sub-objects for `{}`", key))?;
 
-        // 4️⃣ Build the payload struct
+        // 5️⃣ Build the payload struct
         let postable: PostDevice = PostDevice::try_from(self.clone())
             .context(format!("Failed to build PostDevice for `{}`", key))?;
+        trace!(payload = ?postable, "device payload");
 
-        // ─────────────────────────────────────────────
-        // 💡 **DEBUG: dump the exact JSON you'll send**
-        // let json_payload = serde_json::to_string_pretty(&postable)?;
-        // println!("🗳️  Payload JSON:\n{}", json_payload);
-        // ─────────────────────────────────────────────
-
-        // 5️⃣ Decide: PATCH if we already have an id, else POST
+        // 6️⃣ Decide: skip if nothing we own changed, PATCH our fields if we already have an id, else POST
         if let Some(id) = self.id {
-            let endpoint = format!("dcim/devices/{}/", id);
-            let _updated: Device = api
-                .patch(&endpoint, &postable)
+            let fields = control.fields();
+            if current
+                .as_ref()
+                .is_some_and(|c| postable.matches_fields(c, fields))
+            {
+                debug!("device unchanged");
+                report
+                    .lock()
+                    .unwrap()
+                    .record(Self::get_endpoint(), Outcome::Unchanged);
+                return Ok(());
+            }
+            let endpoint = format!("dcim/devices/{}", id);
+            let updated: Device = api
+                .patch(&endpoint, &postable.only(fields)?)
                 .await
                 .context(format!("patching device `{}` (id={})", key, id))?;
-            println!("🔄 [push_to_netbox] updated `{}` → id={}", key, id);
+            info!(fields = ?fields, "updated device");
+            report
+                .lock()
+                .unwrap()
+                .record(Self::get_endpoint(), Outcome::Updated);
+            // keep the cache current so later cycles can detect unchanged devices
+            cache.devices.insert(key, updated);
         } else {
             let created: Device = api
-                .post("dcim/devices/", &postable)
+                .post(Self::get_endpoint(), &postable)
                 .await
                 .context(format!("Creating new device `{}`", key))?;
             let created_id = created
                 .get_id()
                 .expect("NetBox must return an ID on creation");
-            println!("✅ [push_to_netbox] created `{}` → id={}", key, created_id);
-
-            // 6️⃣ Insert into cache under the same normalized key
-            cache.devices.insert(key.clone(), created.clone());
-            self.id = Some(created_id);
+            Span::current().record("netbox_id", created_id);
+            info!("created device");
+            report
+                .lock()
+                .unwrap()
+                .record(Self::get_endpoint(), Outcome::Created);
+
+            // 7️⃣ Insert into cache under the same normalized key
+            cache.devices.insert(key, created);
         }
 
         Ok(())
     }
 
-    pub fn merge_from_intune(&mut self, src: &IntuneDevice) {
-        if self.serial.is_none() {
-            self.serial = Some(src.serial.clone());
-        }
-        if self.platform.is_none() {
-            self.platform = Some(Platform {
-                id: None,
-                name: src.os.clone().into(),
-                slug: sanitize_slug(&src.os),
-            })
-        }
-        if self.role.is_none() {
-            self.role = Some(DeviceRole::new("Desktop".to_string()));
-        }
-
-        if self.status.is_none() {
-            self.status = Self::status_from_sync(&src.synced);
-        }
-
-        if self.device_type.is_none() {
-            self.device_type = Some(DeviceType::new(
-                Manufacturer::new(src.manufacturer.to_string()),
-                src.model.to_string(),
-            ));
-        }
-        self.push_tag(Tag::new("AAD".to_string()));
-    }
-
-    pub fn merge_from_fortigate(&mut self, src: &FortiGateDevice) {
-        if self.platform.is_none() {
-            if let Some(os) = &src.os_name {
-                self.platform = Some(Platform {
-                    id: None,
-                    name: os.to_string(),
-                    slug: sanitize_slug(&os.to_string()),
-                })
-            }
-        }
-
-        if src.is_online {
-            self.status = Some(Status::from_value(StatusOptions::Active));
-        }
-
-        if self.device_type.is_none() {
-            let device_type = match (src.device_type.clone(), src.hardware_vendor.clone()) {
-                (Some(ty), Some(vendor)) => Some(DeviceType::new(Manufacturer::new(vendor), ty)),
-                _ => Some(DeviceType::new(
-                    Manufacturer::new("default".into()),
-                    "default".into(),
-                )),
-            };
-            if device_type.is_some() {
-                self.device_type = device_type;
-            } else {
-                println!("DeviceType is somehow None");
-            }
-        }
-
-        self.push_tag(Tag::new("FortiGate".to_string()));
-        if let Some(true) = src.dhcp_lease_lease_reserved {
-            self.push_tag(Tag::new("Reserved DHCP".to_string()));
-        }
-    }
-
-    //pub fn merge_from_eset(&mut self, src: Device) {}
-
-    fn push_tag(&mut self, tag: Tag) {
+    pub fn push_tag(&mut self, tag: Tag) {
         match &mut self.tags {
             Some(tags) => {
                 if !tags.iter().any(|t| t.slug == tag.slug) {
@@ -834,15 +880,4 @@ impl Device {
             }
         }
     }
-
-    fn status_from_sync(sync_time: &str) -> Option<Status> {
-        let parsed = sync_time.parse::<DateTime<Utc>>().ok()?;
-        let days = (Utc::now() - parsed).num_days();
-
-        Some(if days <= 7 {
-            Status::from_value(StatusOptions::Active)
-        } else {
-            Status::from_value(StatusOptions::Offline)
-        })
-    }
 }
@@ -0,0 +1,129 @@
+use crate::netbox::models::NetBoxModel;
+use chrono::{DateTime, SecondsFormat, Utc};
+use std::marker::PhantomData;
+use url::Url;
+
+/// A filtered list request for one model, run with [`ApiClient::query`] or
+/// streamed page by page with [`ApiClient::query_pages`].
+///
+/// ```
+/// use netbox_ingester::netbox::{models::Device, query::Query};
+///
+/// let query = Query::<Device>::new()
+///     .serial("5CD1234XYZ")
+///     .tag("aad")
+///     .order_by("-last_updated")
+///     .page_size(50);
+/// ```
+///
+/// [`ApiClient::query`]: crate::ApiClient::query
+/// [`ApiClient::query_pages`]: crate::ApiClient::query_pages
+#[derive(Debug)]
+pub struct Query<T> {
+    params: Vec<(String, String)>,
+    model: PhantomData<fn() -> T>,
+}
+
+impl<T> Clone for Query<T> {
+    fn clone(&self) -> Self {
+        Self {
+            params: self.params.clone(),
+            model: PhantomData,
+        }
+    }
+}
+
+impl<T: NetBoxModel> Default for Query<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: NetBoxModel> Query<T> {
+    /// Every object of the model
+    pub fn new() -> Self {
+        Self {
+            params: Vec::new(),
+            model: PhantomData,
+        }
+    }
+
+    /// Any NetBox filter, e.g. `filter("status", "active")` or
+    /// `filter("name__ic", "desk")`. Repeating a field matches any of the values.
+    pub fn filter(mut self, field: &str, value: impl ToString) -> Self {
+        self.params.push((field.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn name(self, name: &str) -> Self {
+        self.filter("name", name)
+    }
+
+    pub fn serial(self, serial: &str) -> Self {
+        self.filter("serial", serial)
+    }
+
+    pub fn mac_address(self, mac: &str) -> Self {
+        self.filter("mac_address", mac)
+    }
+
+    /// Tag slug
+    pub fn tag(self, slug: &str) -> Self {
+        self.filter("tag", slug)
+    }
+
+    pub fn site_id(self, id: u32) -> Self {
+        self.filter("site_id", id)
+    }
+
+    /// NetBox's free text search
+    pub fn search(self, q: &str) -> Self {
+        self.filter("q", q)
+    }
+
+    /// Objects changed at or after `since`
+    pub fn updated_since(self, since: DateTime<Utc>) -> Self {
+        self.filter(
+            "last_updated__gte",
+            since.to_rfc3339_opts(SecondsFormat::Secs, true),
+        )
+    }
+
+    /// Sort field, prefixed with `-` for descending order
+    pub fn order_by(self, field: &str) -> Self {
+        self.filter("ordering", field)
+    }
+
+    /// Minimal representations; fields brief mode leaves out stay empty
+    pub fn brief(self) -> Self {
+        self.filter("brief", true)
+    }
+
+    /// Only these fields in the results (NetBox 4), the others stay empty
+    pub fn fields(self, fields: &[&str]) -> Self {
+        self.filter("fields", fields.join(","))
+    }
+
+    /// Objects per page, NetBox's `PAGINATE_COUNT` when unset
+    pub fn page_size(mut self, size: usize) -> Self {
+        self.params.retain(|(k, _)| k != "limit");
+        self.filter("limit", size)
+    }
+
+    pub fn params(&self) -> &[(String, String)] {
+        &self.params
+    }
+
+    /// URL of the first page below `api_url`
+    pub fn url(&self, api_url: &str) -> Result<Url, url::ParseError> {
+        let mut url = Url::parse(&format!(
+            "{}/{}/",
+            api_url.trim_end_matches('/'),
+            T::get_endpoint()
+        ))?;
+        if !self.params.is_empty() {
+            url.query_pairs_mut().extend_pairs(&self.params);
+        }
+        Ok(url)
+    }
+}
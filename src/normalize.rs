@@ -0,0 +1,620 @@
+use crate::{
+    config::{DeviceTypeConfig, ManufacturerConfig, MissingTypeStrategy, PlatformConfig, Settings},
+    naming::NamingPolicy,
+    netbox::models::{DeviceType, Manufacturer},
+    report::RunReport,
+    source::Record,
+};
+use anyhow::{Context, Result};
+use regex::{Regex, RegexBuilder};
+use serde::Deserialize;
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::Path,
+};
+use tracing::{debug, info, warn};
+
+// canonical name -> spellings seen in the sources
+const MANUFACTURER_ALIASES: &[(&str, &[&str])] = &[
+    (
+        "Dell",
+        &["dell inc.", "dell inc", "dell computer corporation"],
+    ),
+    ("Lenovo", &["lenovo group limited"]),
+    ("HP", &["hp inc.", "hewlett-packard", "hewlett packard"]),
+    ("Microsoft", &["microsoft corporation"]),
+    ("Apple", &["apple inc.", "apple inc"]),
+    (
+        "Samsung",
+        &["samsung electronics co., ltd.", "samsung electronics"],
+    ),
+    ("ASUS", &["asustek computer inc.", "asustek"]),
+    ("Acer", &["acer inc."]),
+    (
+        "Fujitsu",
+        &[
+            "fujitsu client computing limited",
+            "fujitsu technology solutions",
+        ],
+    ),
+    ("Google", &[]),
+    ("VMware", &["vmware, inc."]),
+    ("Cisco", &["cisco systems"]),
+    ("Fortinet", &[]),
+];
+
+// FortiGate device_type values naming a kind of device, not a model
+const GENERIC_MODELS: &[&str] = &[
+    "windows pc",
+    "linux pc",
+    "mac",
+    "android phone",
+    "android tablet",
+    "iphone",
+    "ipad",
+    "router",
+    "router/nat device",
+    "switch",
+    "printer",
+    "ip phone",
+    "media streaming",
+    "gaming console",
+    "server",
+    "smartphone",
+    "tablet",
+    "fortinet device",
+    "other network device",
+    "unknown",
+];
+
+// Legal suffixes dropped before looking a manufacturer up
+const COMPANY_SUFFIXES: &[&str] = &[
+    ", inc.",
+    " inc.",
+    " inc",
+    " corporation",
+    " corp.",
+    " co., ltd.",
+    " ltd.",
+    " limited",
+    " gmbh",
+];
+
+// The part of a devicetype-library definition the ingester uses
+#[derive(Debug, Clone, Deserialize)]
+pub struct LibraryDeviceType {
+    pub manufacturer: String,
+    pub model: String,
+    pub part_number: Option<String>,
+    pub u_height: Option<f64>,
+}
+
+// Canonical platform for an OS string reported by a source
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CanonicalPlatform {
+    pub name: String,
+    pub manufacturer: Option<String>,
+    // the exact version as reported, e.g. "10.0.22631.4317"
+    pub build: Option<String>,
+}
+
+#[derive(Debug)]
+struct PlatformRule {
+    pattern: Regex,
+    name: String,
+    manufacturer: Option<String>,
+}
+
+// Maps raw source values onto the names NetBox should hold
+#[derive(Debug, Default)]
+pub struct Normalizer {
+    platform_rules: Vec<PlatformRule>,
+    pub build_field: Option<String>,
+    // lowercase spelling -> canonical manufacturer
+    manufacturers: HashMap<String, String>,
+    // (lowercase manufacturer, lowercase alias) -> model
+    models: HashMap<(String, String), String>,
+    generic_models: HashSet<String>,
+    // (lowercase manufacturer, lowercase model or part number) -> definition
+    library: HashMap<(String, String), LibraryDeviceType>,
+    pub missing_type: MissingTypeStrategy,
+    unknown_manufacturer: String,
+    pub naming: NamingPolicy,
+}
+
+impl Normalizer {
+    pub fn new(settings: &Settings) -> Result<Self> {
+        let mut normalizer = Self {
+            platform_rules: platform_rules(&settings.platforms)?,
+            build_field: settings.platforms.build_field.clone(),
+            missing_type: settings.fallback.device_type,
+            unknown_manufacturer: settings.fallback.unknown_manufacturer.clone(),
+            naming: NamingPolicy::new(&settings.naming)?,
+            ..Default::default()
+        };
+        if let Some(dir) = &settings.device_types.library {
+            normalizer.load_library(dir)?;
+        }
+        normalizer.add_manufacturers(&settings.manufacturers);
+        normalizer.add_device_types(&settings.device_types);
+        Ok(normalizer)
+    }
+
+    fn add_manufacturers(&mut self, config: &ManufacturerConfig) {
+        let builtin = MANUFACTURER_ALIASES
+            .iter()
+            .map(|(name, aliases)| (name.to_string(), aliases.iter().map(|a| a.to_string())));
+        let configured = config
+            .aliases
+            .iter()
+            .map(|a| (a.name.trim().to_string(), a.aliases.clone().into_iter()));
+        for (name, aliases) in builtin {
+            self.manufacturers.insert(name.to_lowercase(), name.clone());
+            for alias in aliases {
+                self.manufacturers
+                    .insert(alias.to_lowercase(), name.clone());
+            }
+        }
+        // config wins over the built-in table
+        for (name, aliases) in configured {
+            self.manufacturers.insert(name.to_lowercase(), name.clone());
+            for alias in aliases {
+                self.manufacturers
+                    .insert(alias.trim().to_lowercase(), name.clone());
+            }
+        }
+    }
+
+    fn add_device_types(&mut self, config: &DeviceTypeConfig) {
+        self.generic_models = GENERIC_MODELS
+            .iter()
+            .map(|m| m.to_string())
+            .chain(config.ignore.iter().map(|m| m.trim().to_lowercase()))
+            .collect();
+        for alias in &config.aliases {
+            let manufacturer = self.manufacturer(&alias.manufacturer).0.to_lowercase();
+            let model = alias.model.trim().to_string();
+            self.models
+                .insert((manufacturer.clone(), model.to_lowercase()), model.clone());
+            for spelling in &alias.aliases {
+                self.models.insert(
+                    (manufacturer.clone(), spelling.trim().to_lowercase()),
+                    model.clone(),
+                );
+            }
+        }
+    }
+
+    // Reads every definition below `dir`; files that don't parse are skipped
+    fn load_library(&mut self, dir: &Path) -> Result<()> {
+        let mut files = Vec::new();
+        collect_yaml(dir, &mut files).context(format!(
+            "Failed to read device type library {}",
+            dir.display()
+        ))?;
+
+        let mut loaded = 0;
+        for file in &files {
+            let parsed = fs::read_to_string(file)
+                .map_err(anyhow::Error::from)
+                .and_then(|yaml| Ok(serde_yaml::from_str::<LibraryDeviceType>(&yaml)?));
+            let definition = match parsed {
+                Ok(definition) => definition,
+                Err(e) => {
+                    warn!(file = %file.display(), error = %e, "skipping device type definition");
+                    continue;
+                }
+            };
+            let manufacturer = definition.manufacturer.trim().to_string();
+            // library manufacturers are canonical too
+            self.manufacturers
+                .entry(manufacturer.to_lowercase())
+                .or_insert_with(|| manufacturer.clone());
+            let manufacturer = manufacturer.to_lowercase();
+            if let Some(part) = &definition.part_number {
+                self.library.insert(
+                    (manufacturer.clone(), part.to_lowercase()),
+                    definition.clone(),
+                );
+            }
+            self.library
+                .insert((manufacturer, definition.model.to_lowercase()), definition);
+            loaded += 1;
+        }
+        info!(files = files.len(), loaded, "loaded device type library");
+        Ok(())
+    }
+
+    // Canonical manufacturer name and whether the spelling was known
+    pub fn manufacturer(&self, raw: &str) -> (String, bool) {
+        let raw = raw.trim();
+        let lower = raw.to_lowercase();
+        if let Some(name) = self.manufacturers.get(&lower) {
+            return (name.clone(), true);
+        }
+        for suffix in COMPANY_SUFFIXES {
+            if let Some(stripped) = lower.strip_suffix(suffix) {
+                if let Some(name) = self.manufacturers.get(stripped.trim()) {
+                    return (name.clone(), true);
+                }
+            }
+        }
+        (raw.to_string(), false)
+    }
+
+    // Canonical model name and whether it was known, None for generic values
+    // like "Windows PC" that don't name a model
+    pub fn model(&self, manufacturer: &str, raw: &str) -> Option<(String, bool)> {
+        let raw = raw.trim();
+        let lower = raw.to_lowercase();
+        if raw.is_empty() || self.generic_models.contains(&lower) {
+            return None;
+        }
+        let key = (manufacturer.to_lowercase(), lower);
+        if let Some(model) = self.models.get(&key) {
+            return Some((model.clone(), true));
+        }
+        if let Some(definition) = self.library.get(&key) {
+            return Some((definition.model.clone(), true));
+        }
+        Some((raw.to_string(), false))
+    }
+
+    // Rewrites the manufacturer and model of a source record in place. Values
+    // without a mapping are kept as they are and counted in the report.
+    pub fn record(&self, record: &mut Record, report: &mut RunReport) {
+        if let Some(raw) = record.manufacturer.take() {
+            let (manufacturer, known) = self.manufacturer(&raw);
+            if !known {
+                report.unmapped("manufacturer", &raw);
+            }
+            record.model = record.model.take().and_then(|raw| {
+                let Some((model, known)) = self.model(&manufacturer, &raw) else {
+                    debug!(device = %record.name, model = %raw, "ignoring generic model");
+                    return None;
+                };
+                // without a library or aliases there's nothing to map models against
+                if !known && (!self.library.is_empty() || !self.models.is_empty()) {
+                    report.unmapped("device_type", &format!("{} {}", manufacturer, raw));
+                }
+                Some(model)
+            });
+            record.manufacturer = Some(manufacturer);
+        }
+        // the fallback name is built from the normalized manufacturer and model
+        record.name = self.naming.name(record);
+    }
+
+    // Placeholder type for devices no source could identify, one per role
+    pub fn unknown_device_type(&self, role: &str) -> DeviceType {
+        DeviceType::new(
+            Manufacturer::new(self.unknown_manufacturer.clone()),
+            format!("Unknown {}", role),
+        )
+    }
+
+    // Device type for already normalized names, with the library's details
+    pub fn device_type(&self, manufacturer: &str, model: &str) -> DeviceType {
+        let mut device_type = DeviceType::new(
+            Manufacturer::new(manufacturer.to_string()),
+            model.to_string(),
+        );
+        let key = (manufacturer.to_lowercase(), model.to_lowercase());
+        if let Some(definition) = self.library.get(&key) {
+            device_type.part_number = definition.part_number.clone();
+            device_type.u_height = definition.u_height;
+        }
+        device_type
+    }
+
+    // Config rules are tried first, in order, against "<os> <version>"; then
+    // the built-in rules. Unknown systems keep their name without the version
+    // so patch levels don't turn into separate platforms.
+    pub fn platform(&self, os: &str, version: Option<&str>) -> Option<CanonicalPlatform> {
+        let os = os.trim();
+        if os.is_empty() {
+            return None;
+        }
+        let version = version.map(str::trim).filter(|v| !v.is_empty());
+        let full = match version {
+            Some(version) => format!("{} {}", os, version),
+            None => os.to_string(),
+        };
+
+        for rule in &self.platform_rules {
+            if let Some(caps) = rule.pattern.captures(&full) {
+                let mut name = String::new();
+                caps.expand(&rule.name, &mut name);
+                return Some(CanonicalPlatform {
+                    name,
+                    manufacturer: rule.manufacturer.clone(),
+                    build: version.map(String::from),
+                });
+            }
+        }
+
+        let (name, manufacturer) =
+            builtin_platform(os, version).unwrap_or_else(|| (os.to_string(), None));
+        Some(CanonicalPlatform {
+            name,
+            manufacturer: manufacturer.map(String::from),
+            build: version.map(String::from),
+        })
+    }
+}
+
+fn platform_rules(config: &PlatformConfig) -> Result<Vec<PlatformRule>> {
+    config
+        .rules
+        .iter()
+        .map(|rule| {
+            let pattern = compile(&rule.pattern)
+                .context(format!("invalid platform pattern `{}`", rule.pattern))?;
+            Ok(PlatformRule {
+                pattern,
+                name: rule.name.clone(),
+                manufacturer: rule.manufacturer.clone(),
+            })
+        })
+        .collect()
+}
+
+fn collect_yaml(dir: &Path, files: &mut Vec<std::path::PathBuf>) -> std::io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_yaml(&path, files)?;
+        } else if path
+            .extension()
+            .is_some_and(|ext| ext == "yaml" || ext == "yml")
+        {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+// Patterns from the config always match case-insensitively
+pub fn compile(pattern: &str) -> Result<Regex, regex::Error> {
+    RegexBuilder::new(pattern).case_insensitive(true).build()
+}
+
+fn builtin_platform(os: &str, version: Option<&str>) -> Option<(String, Option<&'static str>)> {
+    let lower = os.to_lowercase();
+    let major = version
+        .and_then(|v| v.split(['.', ' ']).next())
+        .filter(|m| !m.is_empty() && m.chars().all(|c| c.is_ascii_digit()));
+    let versioned = |name: &str| match major {
+        Some(major) => format!("{} {}", name, major),
+        None => name.to_string(),
+    };
+
+    let platform = match lower.as_str() {
+        os if os.starts_with("windows") && os.contains("server") => {
+            ("Windows Server".to_string(), Some("Microsoft"))
+        }
+        os if os.starts_with("windows") => (windows(version, major), Some("Microsoft")),
+        "macos" | "mac os x" | "os x" | "macmdm" => (versioned("macOS"), Some("Apple")),
+        "ios" => (versioned("iOS"), Some("Apple")),
+        "ipados" => (versioned("iPadOS"), Some("Apple")),
+        os if os.starts_with("android") => (versioned("Android"), Some("Google")),
+        "chromeos" | "chrome os" => ("ChromeOS".to_string(), Some("Google")),
+        "linux" => ("Linux".to_string(), None),
+        _ => return None,
+    };
+    Some(platform)
+}
+
+// Intune reports Windows as "10.0.<build>.<patch>" for both 10 and 11, the
+// build number tells them apart
+fn windows(version: Option<&str>, major: Option<&str>) -> String {
+    let build = version
+        .and_then(|v| v.strip_prefix("10.0."))
+        .and_then(|rest| rest.split('.').next())
+        .and_then(|b| b.parse::<u32>().ok());
+    match (build, major) {
+        (Some(build), _) if build >= 22000 => "Windows 11".into(),
+        (Some(_), _) => "Windows 10".into(),
+        (None, Some(major)) => format!("Windows {}", major),
+        (None, None) => "Windows".into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{DeviceTypeAlias, ManufacturerAlias};
+
+    fn normalizer(manufacturers: ManufacturerConfig, device_types: DeviceTypeConfig) -> Normalizer {
+        let mut normalizer = Normalizer::default();
+        normalizer.add_manufacturers(&manufacturers);
+        normalizer.add_device_types(&device_types);
+        normalizer
+    }
+
+    fn builtin() -> Normalizer {
+        normalizer(Default::default(), Default::default())
+    }
+
+    #[test]
+    fn builtin_aliases_map_to_canonical_manufacturers() {
+        let normalizer = builtin();
+        for (raw, canonical) in [
+            ("Dell Inc.", "Dell"),
+            ("DELL COMPUTER CORPORATION", "Dell"),
+            ("dell", "Dell"),
+            ("Hewlett-Packard", "HP"),
+            ("ASUSTeK Computer Inc.", "ASUS"),
+            ("VMware, Inc.", "VMware"),
+            ("  apple inc  ", "Apple"),
+            ("Samsung Electronics Co., Ltd.", "Samsung"),
+            ("google", "Google"),
+        ] {
+            assert_eq!(
+                normalizer.manufacturer(raw),
+                (canonical.to_string(), true),
+                "{}",
+                raw
+            );
+        }
+    }
+
+    #[test]
+    fn company_suffixes_are_dropped_before_the_lookup() {
+        let normalizer = builtin();
+        for (raw, canonical) in [
+            ("Lenovo Ltd.", "Lenovo"),
+            ("Microsoft Corp.", "Microsoft"),
+            ("Fortinet, Inc.", "Fortinet"),
+            ("Acer Inc", "Acer"),
+            ("Google Limited", "Google"),
+            ("Cisco Systems GmbH", "Cisco"),
+            ("Fujitsu Corporation", "Fujitsu"),
+        ] {
+            assert_eq!(
+                normalizer.manufacturer(raw),
+                (canonical.to_string(), true),
+                "{}",
+                raw
+            );
+        }
+        // unknown spellings are kept, suffix and all
+        assert_eq!(
+            normalizer.manufacturer(" Framework Computer Inc. "),
+            ("Framework Computer Inc.".to_string(), false)
+        );
+    }
+
+    #[test]
+    fn configured_aliases_win_over_builtin_ones() {
+        let normalizer = normalizer(
+            ManufacturerConfig {
+                aliases: vec![ManufacturerAlias {
+                    name: "Hewlett Packard Enterprise".into(),
+                    aliases: vec![" hewlett packard ".into()],
+                }],
+            },
+            Default::default(),
+        );
+        let hpe = ("Hewlett Packard Enterprise".to_string(), true);
+        assert_eq!(normalizer.manufacturer("Hewlett Packard"), hpe);
+        assert_eq!(normalizer.manufacturer("hewlett packard enterprise"), hpe);
+        assert_eq!(normalizer.manufacturer("HP Inc."), ("HP".to_string(), true));
+    }
+
+    #[test]
+    fn generic_models_name_no_model() {
+        let normalizer = normalizer(
+            Default::default(),
+            DeviceTypeConfig {
+                ignore: vec![" Virtual Machine ".into()],
+                ..Default::default()
+            },
+        );
+        for generic in GENERIC_MODELS {
+            assert_eq!(normalizer.model("Dell", &generic.to_uppercase()), None);
+        }
+        assert_eq!(normalizer.model("VMware", "virtual machine"), None);
+        assert_eq!(normalizer.model("Dell", "  "), None);
+        assert_eq!(
+            normalizer.model("Dell", " Latitude 5440 "),
+            Some(("Latitude 5440".to_string(), false))
+        );
+    }
+
+    #[test]
+    fn model_aliases_are_looked_up_per_manufacturer() {
+        let normalizer = normalizer(
+            Default::default(),
+            DeviceTypeConfig {
+                aliases: vec![DeviceTypeAlias {
+                    manufacturer: "Dell Inc.".into(),
+                    model: "Latitude 5440".into(),
+                    aliases: vec!["LAT5440".into()],
+                }],
+                ..Default::default()
+            },
+        );
+        let latitude = Some(("Latitude 5440".to_string(), true));
+        assert_eq!(normalizer.model("Dell", "lat5440"), latitude);
+        assert_eq!(normalizer.model("dell", "LATITUDE 5440"), latitude);
+        assert_eq!(
+            normalizer.model("HP", "LAT5440"),
+            Some(("LAT5440".to_string(), false))
+        );
+    }
+
+    #[test]
+    fn builtin_platforms_keep_the_major_version() {
+        for (os, version, name, manufacturer) in [
+            (
+                "Windows",
+                Some("10.0.22631.4317"),
+                "Windows 11",
+                Some("Microsoft"),
+            ),
+            (
+                "Windows",
+                Some("10.0.19045.5011"),
+                "Windows 10",
+                Some("Microsoft"),
+            ),
+            (
+                "Windows Server 2022",
+                Some("10.0.20348"),
+                "Windows Server",
+                Some("Microsoft"),
+            ),
+            ("macOS", Some("14.5"), "macOS 14", Some("Apple")),
+            ("Mac OS X", Some("13.6.1"), "macOS 13", Some("Apple")),
+            ("MacMDM", None, "macOS", Some("Apple")),
+            ("iOS", Some("17.5.1"), "iOS 17", Some("Apple")),
+            ("iPadOS", None, "iPadOS", Some("Apple")),
+            ("Android", Some("14"), "Android 14", Some("Google")),
+            ("AndroidForWork", Some("beta"), "Android", Some("Google")),
+            ("Chrome OS", Some("120.0"), "ChromeOS", Some("Google")),
+            ("Linux", Some("6.1"), "Linux", None),
+        ] {
+            assert_eq!(
+                builtin_platform(os, version),
+                Some((name.to_string(), manufacturer)),
+                "{} {:?}",
+                os,
+                version
+            );
+        }
+        assert_eq!(builtin_platform("FortiOS", Some("7.2.8")), None);
+    }
+
+    #[test]
+    fn unknown_platforms_drop_the_version_from_their_name() {
+        let platform = builtin().platform(" FortiOS ", Some("7.2.8")).unwrap();
+        assert_eq!(
+            platform,
+            CanonicalPlatform {
+                name: "FortiOS".into(),
+                manufacturer: None,
+                build: Some("7.2.8".into()),
+            }
+        );
+        assert_eq!(builtin().platform("  ", Some("1.0")), None);
+    }
+
+    #[test]
+    fn windows_builds_tell_10_and_11_apart() {
+        for (version, major, name) in [
+            (Some("10.0.22000.194"), Some("10"), "Windows 11"),
+            (Some("10.0.26100"), Some("10"), "Windows 11"),
+            (Some("10.0.21999.1"), Some("10"), "Windows 10"),
+            (Some("10.0.19045.5011"), Some("10"), "Windows 10"),
+            // no build to go by
+            (Some("10.0.preview"), Some("10"), "Windows 10"),
+            (Some("11"), Some("11"), "Windows 11"),
+            (Some("XP"), None, "Windows"),
+            (None, None, "Windows"),
+        ] {
+            assert_eq!(windows(version, major), name, "{:?}", version);
+        }
+    }
+}
@@ -0,0 +1,122 @@
+use crate::{
+    config::OwnershipConfig,
+    netbox::models::{Device, NetBoxModel, Tag},
+    utils::sanitize_slug,
+};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+// Device fields the ingester writes, named like NetBox's API fields
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeviceField {
+    Name,
+    DeviceType,
+    Role,
+    Site,
+    Status,
+    Serial,
+    Platform,
+    Tags,
+    CustomFields,
+}
+
+impl DeviceField {
+    pub const ALL: [DeviceField; 9] = [
+        DeviceField::Name,
+        DeviceField::DeviceType,
+        DeviceField::Role,
+        DeviceField::Site,
+        DeviceField::Status,
+        DeviceField::Serial,
+        DeviceField::Platform,
+        DeviceField::Tags,
+        DeviceField::CustomFields,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            DeviceField::Name => "name",
+            DeviceField::DeviceType => "device_type",
+            DeviceField::Role => "role",
+            DeviceField::Site => "site",
+            DeviceField::Status => "status",
+            DeviceField::Serial => "serial",
+            DeviceField::Platform => "platform",
+            DeviceField::Tags => "tags",
+            DeviceField::CustomFields => "custom_fields",
+        }
+    }
+}
+
+impl fmt::Display for DeviceField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+// How much of an existing NetBox device the ingester may change
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Control<'a> {
+    // Created by the ingester, every field is its own
+    Managed,
+    // Curated by hand, only these fields are updated
+    Curated(&'a [DeviceField]),
+    // Carries this lock tag and is left alone
+    Locked(String),
+}
+
+impl Control<'_> {
+    // The fields an update writes, none for locked devices
+    pub fn fields(&self) -> &[DeviceField] {
+        match self {
+            Control::Managed => &DeviceField::ALL,
+            Control::Curated(fields) => fields,
+            Control::Locked(_) => &[],
+        }
+    }
+}
+
+// Which NetBox devices belong to the ingester, see `[ownership]`
+#[derive(Debug, Clone)]
+pub struct Ownership {
+    managed_tag: String,
+    // slugs
+    lock_tags: Vec<String>,
+    curated_fields: Vec<DeviceField>,
+}
+
+impl Default for Ownership {
+    fn default() -> Self {
+        Self::new(&OwnershipConfig::default())
+    }
+}
+
+impl Ownership {
+    pub fn new(config: &OwnershipConfig) -> Self {
+        Self {
+            managed_tag: config.managed_tag.clone(),
+            lock_tags: config.lock_tags.iter().map(|t| sanitize_slug(t)).collect(),
+            curated_fields: config.curated_fields.clone(),
+        }
+    }
+
+    // The tag marking devices the ingester created
+    pub fn managed_tag(&self) -> Tag {
+        Tag::new(self.managed_tag.clone())
+    }
+
+    // What the ingester may do to `current`, a device as NetBox has it.
+    // A lock tag wins over the managed tag.
+    pub fn control(&self, current: &Device) -> Control<'_> {
+        let slugs = current.get_tag_slugs();
+        if let Some(lock) = slugs.iter().find(|s| self.lock_tags.contains(s)) {
+            return Control::Locked(lock.clone());
+        }
+        if slugs.contains(&self.managed_tag().slug) {
+            Control::Managed
+        } else {
+            Control::Curated(&self.curated_fields)
+        }
+    }
+}
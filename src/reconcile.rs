@@ -0,0 +1,131 @@
+use crate::{
+    cache::LocalCache,
+    netbox::{
+        api::ApiClient,
+        delete::Deletions,
+        models::{Device, NetBoxModel, StatusOptions},
+    },
+    ownership::{Control, Ownership},
+    report::{Outcome, RunReport},
+    sync::SourceKind,
+    utils::sanitize_slug,
+};
+use anyhow::{Context, Result};
+use dashmap::DashMap;
+use serde::Serialize;
+use serde_json::json;
+use std::sync::Mutex;
+use tracing::{info, warn};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StaleDevice {
+    pub device: String,
+    pub netbox_id: Option<u32>,
+    pub sources: Vec<SourceKind>,
+}
+
+// NetBox devices that sources tagged in the past but none of them returned this run.
+// Only sources in `fetched` count: a device is stale when every source that
+// claims it was fetched and no longer reports it. Locked devices never are.
+pub fn find_stale(
+    local_cache: &LocalCache,
+    devices: &DashMap<String, Device>,
+    fetched: &[SourceKind],
+    ownership: &Ownership,
+) -> Vec<StaleDevice> {
+    let mut stale: Vec<StaleDevice> = local_cache
+        .devices
+        .iter()
+        .filter(|entry| !devices.contains_key(entry.key()))
+        .filter(|entry| {
+            !matches!(
+                entry.value().status.as_ref().map(|s| s.value()),
+                Some(StatusOptions::Offline)
+            )
+        })
+        .filter(|entry| !matches!(ownership.control(entry.value()), Control::Locked(_)))
+        .filter_map(|entry| {
+            let sources = claimed_by(entry.value());
+            let all_fetched = sources.iter().all(|s| fetched.contains(s));
+            (!sources.is_empty() && all_fetched).then(|| StaleDevice {
+                device: entry.key().clone(),
+                netbox_id: entry.value().get_id(),
+                sources,
+            })
+        })
+        .collect();
+    stale.sort_by(|a, b| a.device.cmp(&b.device));
+    stale
+}
+
+fn claimed_by(device: &Device) -> Vec<SourceKind> {
+    let slugs: Vec<&str> = device
+        .tags
+        .iter()
+        .flatten()
+        .map(|t| t.slug.as_str())
+        .collect();
+    SourceKind::ALL
+        .into_iter()
+        .filter(|kind| {
+            kind.tag()
+                .is_some_and(|tag| slugs.contains(&sanitize_slug(tag).as_str()))
+        })
+        .collect()
+}
+
+// Marks stale devices offline in NetBox
+pub async fn apply(stale: &[StaleDevice], api: &ApiClient, report: &Mutex<RunReport>) {
+    for device in stale {
+        let Some(id) = device.netbox_id else {
+            continue;
+        };
+        let res: Result<Device> = api
+            .patch(
+                &format!("{}/{}", Device::get_endpoint(), id),
+                &json!({ "status": "offline" }),
+            )
+            .await
+            .context(format!("marking `{}` offline", device.device));
+        match res {
+            Ok(_) => {
+                info!(device = %device.device, id, "marked stale device offline");
+                report
+                    .lock()
+                    .unwrap()
+                    .record(Device::get_endpoint(), Outcome::Updated);
+            }
+            Err(e) => {
+                warn!(device = %device.device, error = format!("{:#}", e), "failed to mark offline");
+                report
+                    .lock()
+                    .unwrap()
+                    .fail(Device::get_endpoint(), &device.device, &e);
+            }
+        }
+    }
+}
+
+// Deletes stale devices from NetBox instead, within the run's deletion limits
+pub async fn delete(
+    stale: &[StaleDevice],
+    local_cache: &LocalCache,
+    api: &ApiClient,
+    deletions: &Deletions,
+    report: &Mutex<RunReport>,
+) -> Result<()> {
+    let devices: Vec<Device> = stale
+        .iter()
+        .filter_map(|d| {
+            local_cache
+                .devices
+                .get(&d.device)
+                .map(|e| e.value().clone())
+        })
+        .collect();
+    deletions
+        .delete(api, &devices, report)
+        .await
+        .context("deleting stale devices")?;
+    Ok(())
+}
@@ -0,0 +1,429 @@
+use crate::{config::ReportConfig, metrics};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::Value;
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    fs,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tracing::info;
+
+pub type SharedReport = Arc<Mutex<RunReport>>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Created,
+    Updated,
+    Deleted,
+    Unchanged,
+    Skipped,
+    Failed,
+}
+
+impl Outcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Outcome::Created => "created",
+            Outcome::Updated => "updated",
+            Outcome::Deleted => "deleted",
+            Outcome::Unchanged => "unchanged",
+            Outcome::Skipped => "skipped",
+            Outcome::Failed => "failed",
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct ObjectCounts {
+    pub created: usize,
+    pub updated: usize,
+    pub deleted: usize,
+    pub unchanged: usize,
+    pub skipped: usize,
+    pub failed: usize,
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct SourceReport {
+    pub records: usize,
+    // filter rule -> records dropped after fetching
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub filtered: BTreeMap<String, usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PhaseTiming {
+    pub phase: String,
+    pub elapsed_ms: u128,
+}
+
+// Sources disagreeing on a field of one device
+#[derive(Debug, Clone, Serialize)]
+pub struct Conflict {
+    pub device: String,
+    pub field: String,
+    pub source: String,
+    pub value: String,
+    // source -> value that lost
+    pub overridden: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Skipped {
+    pub reason: String,
+    // source -> record as the source returned it
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub records: BTreeMap<String, Value>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RunReport {
+    // attached to every change in NetBox, see `netbox::changes`
+    pub run_id: String,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub sources: BTreeMap<String, SourceReport>,
+    pub devices_consolidated: usize,
+    // device key -> field -> source that supplied the final value
+    pub provenance: BTreeMap<String, BTreeMap<String, String>>,
+    pub conflicts: Vec<Conflict>,
+    // "manufacturer" / "device_type" -> raw value without a mapping -> records
+    pub unmapped: BTreeMap<String, BTreeMap<String, usize>>,
+    // keyed by NetBox endpoint, e.g. `dcim/devices`
+    pub objects: BTreeMap<String, ObjectCounts>,
+    pub skipped: BTreeMap<String, Skipped>,
+    // skipped devices routed to the review queue
+    pub review: Vec<String>,
+    // device key -> error chain, outermost first
+    pub failures: BTreeMap<String, Vec<String>>,
+    pub phases: Vec<PhaseTiming>,
+}
+
+impl Default for RunReport {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RunReport {
+    pub fn new() -> Self {
+        Self {
+            run_id: new_run_id(),
+            started_at: Utc::now(),
+            finished_at: None,
+            sources: BTreeMap::new(),
+            devices_consolidated: 0,
+            provenance: BTreeMap::new(),
+            conflicts: Vec::new(),
+            unmapped: BTreeMap::new(),
+            objects: BTreeMap::new(),
+            skipped: BTreeMap::new(),
+            review: Vec::new(),
+            failures: BTreeMap::new(),
+            phases: Vec::new(),
+        }
+    }
+
+    pub fn shared() -> SharedReport {
+        Arc::new(Mutex::new(Self::new()))
+    }
+
+    pub fn source_ok(&mut self, source: &str, records: usize, filtered: BTreeMap<String, usize>) {
+        metrics::source_fetched(source, Some(records));
+        self.sources.insert(
+            source.to_string(),
+            SourceReport {
+                records,
+                filtered,
+                error: None,
+            },
+        );
+    }
+
+    pub fn source_failed(&mut self, source: &str, error: &anyhow::Error) {
+        metrics::source_fetched(source, None);
+        self.sources.insert(
+            source.to_string(),
+            SourceReport {
+                records: 0,
+                filtered: BTreeMap::new(),
+                error: Some(format!("{:#}", error)),
+            },
+        );
+    }
+
+    pub fn provenance(&mut self, device: &str, field: &str, source: &str) {
+        self.provenance
+            .entry(device.to_string())
+            .or_default()
+            .insert(field.to_string(), source.to_string());
+    }
+
+    pub fn conflict(&mut self, conflict: Conflict) {
+        self.conflicts.push(conflict);
+    }
+
+    pub fn unmapped(&mut self, kind: &str, value: &str) {
+        *self
+            .unmapped
+            .entry(kind.to_string())
+            .or_default()
+            .entry(value.to_string())
+            .or_default() += 1;
+    }
+
+    pub fn record(&mut self, endpoint: &str, outcome: Outcome) {
+        metrics::OBJECTS
+            .with_label_values(&[endpoint, outcome.as_str()])
+            .inc();
+        let counts = self.objects.entry(endpoint.to_string()).or_default();
+        match outcome {
+            Outcome::Created => counts.created += 1,
+            Outcome::Updated => counts.updated += 1,
+            Outcome::Deleted => counts.deleted += 1,
+            Outcome::Unchanged => counts.unchanged += 1,
+            Outcome::Skipped => counts.skipped += 1,
+            Outcome::Failed => counts.failed += 1,
+        }
+    }
+
+    pub fn skip(&mut self, endpoint: &str, key: &str, reason: impl Into<String>) {
+        self.skip_with_records(endpoint, key, reason, BTreeMap::new());
+    }
+
+    pub fn skip_with_records(
+        &mut self,
+        endpoint: &str,
+        key: &str,
+        reason: impl Into<String>,
+        records: BTreeMap<String, Value>,
+    ) {
+        self.record(endpoint, Outcome::Skipped);
+        self.skipped.insert(
+            key.to_string(),
+            Skipped {
+                reason: reason.into(),
+                records,
+            },
+        );
+    }
+
+    pub fn fail(&mut self, endpoint: &str, key: &str, error: &anyhow::Error) {
+        self.record(endpoint, Outcome::Failed);
+        self.failures.insert(
+            key.to_string(),
+            error.chain().map(|cause| cause.to_string()).collect(),
+        );
+    }
+
+    pub fn phase(&mut self, phase: &str, elapsed: Duration) {
+        self.phases.push(PhaseTiming {
+            phase: phase.to_string(),
+            elapsed_ms: elapsed.as_millis(),
+        });
+    }
+
+    pub fn finish(&mut self) {
+        self.finished_at = Some(Utc::now());
+    }
+
+    pub fn write(&self, config: &ReportConfig) -> Result<()> {
+        if let Some(path) = &config.json {
+            let json = serde_json::to_string_pretty(self)?;
+            write_file(path, &json)?;
+            info!(path = %path.display(), "wrote JSON report");
+        }
+        if let Some(path) = &config.markdown {
+            write_file(path, &self.to_markdown())?;
+            info!(path = %path.display(), "wrote Markdown report");
+        }
+        Ok(())
+    }
+
+    // Skipped devices routed to review, one entry per device as JSON, or one row
+    // per source record as CSV
+    pub fn write_review_queue(&self, path: &Path) -> Result<()> {
+        let queued: BTreeMap<&String, &Skipped> = self
+            .review
+            .iter()
+            .filter_map(|key| self.skipped.get(key).map(|s| (key, s)))
+            .collect();
+
+        let contents = if path.extension().is_some_and(|ext| ext == "csv") {
+            let mut csv = String::from("device,reason,source,record\n");
+            for (key, skipped) in &queued {
+                for (source, record) in &skipped.records {
+                    let _ = writeln!(
+                        csv,
+                        "{},{},{},{}",
+                        csv_field(key),
+                        csv_field(&skipped.reason),
+                        csv_field(source),
+                        csv_field(&record.to_string())
+                    );
+                }
+            }
+            csv
+        } else {
+            serde_json::to_string_pretty(&queued)?
+        };
+        write_file(path, &contents)?;
+        info!(path = %path.display(), devices = queued.len(), "wrote review queue");
+        Ok(())
+    }
+
+    pub fn to_markdown(&self) -> String {
+        let mut md = String::new();
+        let finished = self
+            .finished_at
+            .map(|t| t.to_rfc3339())
+            .unwrap_or_else(|| "-".into());
+
+        // Writing to a String cannot fail
+        let _ = writeln!(md, "# NetBox ingestion run\n");
+        let _ = writeln!(md, "- Run: {}", self.run_id);
+        let _ = writeln!(md, "- Started: {}", self.started_at.to_rfc3339());
+        let _ = writeln!(md, "- Finished: {}", finished);
+        let _ = writeln!(md, "- Devices consolidated: {}", self.devices_consolidated);
+
+        let _ = writeln!(md, "\n## Sources\n");
+        let _ = writeln!(md, "| Source | Records | Filtered | Error |");
+        let _ = writeln!(md, "|---|---:|---|---|");
+        for (name, src) in &self.sources {
+            let filtered: Vec<String> = src
+                .filtered
+                .iter()
+                .map(|(rule, count)| format!("{}: {}", rule, count))
+                .collect();
+            let _ = writeln!(
+                md,
+                "| {} | {} | {} | {} |",
+                name,
+                src.records,
+                filtered.join(", "),
+                src.error.as_deref().unwrap_or("")
+            );
+        }
+
+        let _ = writeln!(md, "\n## NetBox objects\n");
+        let _ = writeln!(
+            md,
+            "| Object | Created | Updated | Deleted | Unchanged | Skipped | Failed |"
+        );
+        let _ = writeln!(md, "|---|---:|---:|---:|---:|---:|---:|");
+        for (endpoint, c) in &self.objects {
+            let _ = writeln!(
+                md,
+                "| {} | {} | {} | {} | {} | {} | {} |",
+                endpoint, c.created, c.updated, c.deleted, c.unchanged, c.skipped, c.failed
+            );
+        }
+
+        if !self.provenance.is_empty() {
+            // field -> source -> devices
+            let mut supplied: BTreeMap<&str, BTreeMap<&str, usize>> = BTreeMap::new();
+            for fields in self.provenance.values() {
+                for (field, source) in fields {
+                    *supplied
+                        .entry(field)
+                        .or_default()
+                        .entry(source)
+                        .or_default() += 1;
+                }
+            }
+            let _ = writeln!(md, "\n## Field provenance\n");
+            let _ = writeln!(md, "| Field | Source | Devices |");
+            let _ = writeln!(md, "|---|---|---:|");
+            for (field, sources) in supplied {
+                for (source, count) in sources {
+                    let _ = writeln!(md, "| {} | {} | {} |", field, source, count);
+                }
+            }
+        }
+
+        let _ = writeln!(md, "\n## Phases\n");
+        for p in &self.phases {
+            let _ = writeln!(md, "- {}: {} ms", p.phase, p.elapsed_ms);
+        }
+
+        if !self.failures.is_empty() {
+            let _ = writeln!(md, "\n## Failures\n");
+            for (key, chain) in &self.failures {
+                let _ = writeln!(md, "- `{}`", key);
+                for cause in chain {
+                    let _ = writeln!(md, "  - {}", cause);
+                }
+            }
+        }
+
+        if !self.conflicts.is_empty() {
+            let _ = writeln!(md, "\n## Conflicts\n");
+            for c in &self.conflicts {
+                let overridden: Vec<String> = c
+                    .overridden
+                    .iter()
+                    .map(|(source, value)| format!("`{}` ({})", value, source))
+                    .collect();
+                let _ = writeln!(
+                    md,
+                    "- `{}` {}: `{}` ({}) over {}",
+                    c.device,
+                    c.field,
+                    c.value,
+                    c.source,
+                    overridden.join(", ")
+                );
+            }
+        }
+
+        if !self.unmapped.is_empty() {
+            let _ = writeln!(md, "\n## Unmapped values\n");
+            for (kind, values) in &self.unmapped {
+                for (value, count) in values {
+                    let _ = writeln!(md, "- {} `{}`: {} records", kind, value, count);
+                }
+            }
+        }
+
+        if !self.skipped.is_empty() {
+            let _ = writeln!(md, "\n## Skipped\n");
+            // the source records are only in the JSON report
+            for (key, skipped) in &self.skipped {
+                let _ = writeln!(md, "- `{}`: {}", key, skipped.reason);
+            }
+        }
+
+        md
+    }
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn write_file(path: &Path, contents: &str) -> Result<()> {
+    if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+        fs::create_dir_all(dir).context(format!("Failed to create {}", dir.display()))?;
+    }
+    fs::write(path, contents).context(format!("Failed to write report {}", path.display()))
+}
+
+// Sorts by start time, the random suffix tells apart runs started in the same second
+fn new_run_id() -> String {
+    format!(
+        "{}-{:06x}",
+        Utc::now().format("%Y%m%dT%H%M%SZ"),
+        rand::random::<u32>() >> 8
+    )
+}
@@ -0,0 +1,102 @@
+use crate::{config::SnapshotConfig, source::Source, sync::SourceKind};
+use anyhow::{Context, Result};
+use chrono::{NaiveDateTime, Utc};
+use serde_json::Value;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+use tracing::{info, warn};
+
+const DIR_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+
+// Raw source data on disk: written per run for audits and read back instead
+// of fetching when replaying
+#[derive(Debug, Default, Clone)]
+pub struct Snapshots {
+    dir: Option<PathBuf>,
+    keep: Option<usize>,
+    replay: Option<PathBuf>,
+}
+
+impl Snapshots {
+    pub fn new(config: &SnapshotConfig) -> Self {
+        Self {
+            dir: config.dir.clone(),
+            keep: config.keep,
+            replay: config.replay.clone(),
+        }
+    }
+
+    // Directory this run writes to, None when snapshots are off or replaying
+    pub fn run_dir(&self) -> Option<PathBuf> {
+        if self.replay.is_some() {
+            return None;
+        }
+        let name = Utc::now().format(DIR_FORMAT).to_string();
+        self.dir.as_ref().map(|dir| dir.join(name))
+    }
+
+    // The source's raw objects, read from the replayed snapshot if there is one
+    pub async fn fetch(&self, source: &dyn Source) -> Result<Vec<Value>> {
+        let Some(dir) = &self.replay else {
+            return source.fetch().await;
+        };
+        let path = file(dir, source.kind());
+        let json = fs::read_to_string(&path).context(format!(
+            "No snapshot of {} at {}",
+            source.kind(),
+            path.display()
+        ))?;
+        let raw: Vec<Value> =
+            serde_json::from_str(&json).context(format!("Failed to parse {}", path.display()))?;
+        info!(source = %source.kind(), path = %path.display(), count = raw.len(), "replaying snapshot");
+        Ok(raw)
+    }
+
+    pub fn write(&self, run_dir: &Path, kind: SourceKind, raw: &[Value]) -> Result<()> {
+        fs::create_dir_all(run_dir).context(format!("Failed to create {}", run_dir.display()))?;
+        let path = file(run_dir, kind);
+        let json = serde_json::to_string_pretty(raw)?;
+        fs::write(&path, json).context(format!("Failed to write {}", path.display()))?;
+        info!(source = %kind, path = %path.display(), "wrote snapshot");
+        self.prune();
+        Ok(())
+    }
+
+    // Drops the oldest snapshot directories beyond `keep`
+    fn prune(&self) {
+        let (Some(dir), Some(keep)) = (&self.dir, self.keep) else {
+            return;
+        };
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                warn!(dir = %dir.display(), error = %e, "cannot list snapshots");
+                return;
+            }
+        };
+        // only directories this module named, anything else in there is left alone
+        let mut runs: Vec<PathBuf> = entries
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|p| p.is_dir())
+            .filter(|p| {
+                p.file_name()
+                    .and_then(|n| n.to_str())
+                    .is_some_and(|n| NaiveDateTime::parse_from_str(n, DIR_FORMAT).is_ok())
+            })
+            .collect();
+        runs.sort();
+        let excess = runs.len().saturating_sub(keep);
+        for old in &runs[..excess] {
+            if let Err(e) = fs::remove_dir_all(old) {
+                warn!(dir = %old.display(), error = %e, "cannot remove old snapshot");
+            }
+        }
+    }
+}
+
+fn file(dir: &Path, kind: SourceKind) -> PathBuf {
+    dir.join(format!("{}.json", kind.name()))
+}
@@ -0,0 +1,207 @@
+use crate::{
+    config::Settings,
+    fetch::{azure::AzureClient, fortigate::FortiGateClient, nagiosxi::NagiosxiClient},
+    netbox::models::StatusOptions,
+    sync::SourceKind,
+};
+use anyhow::Result;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{collections::BTreeMap, fmt, fmt::Debug};
+
+/// A device as reported by one source, before consolidation. Fields a source
+/// doesn't know are left empty.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Record {
+    pub name: String,
+    pub serial: Option<String>,
+    pub manufacturer: Option<String>,
+    pub model: Option<String>,
+    // operating system name, normalized into a platform during consolidation
+    pub platform: Option<String>,
+    pub os_version: Option<String>,
+    pub status: Option<StatusOptions>,
+    pub site: Option<String>,
+    pub primary_ip4: Option<String>,
+    pub mac: Option<String>,
+    // extra tags besides the source tag, e.g. "Reserved DHCP"
+    pub tags: Vec<String>,
+    // the record as the source returned it
+    pub raw: Value,
+}
+
+impl Record {
+    pub fn key(&self) -> String {
+        self.name.to_lowercase()
+    }
+}
+
+/// Records of one fetch
+#[derive(Debug, Default)]
+pub struct Fetched {
+    pub records: Vec<Record>,
+    // filter rule -> records dropped by it
+    pub filtered: BTreeMap<&'static str, usize>,
+}
+
+impl From<Vec<Record>> for Fetched {
+    fn from(records: Vec<Record>) -> Self {
+        Self {
+            records,
+            filtered: BTreeMap::new(),
+        }
+    }
+}
+
+// Device fields sources compete for during consolidation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Field {
+    // spelling of the name, devices are matched case-insensitively
+    Name,
+    Serial,
+    Platform,
+    // manufacturer and model, always taken together
+    DeviceType,
+    Status,
+    Site,
+    PrimaryIp4,
+}
+
+impl Field {
+    pub const ALL: [Field; 7] = [
+        Field::Name,
+        Field::Serial,
+        Field::Platform,
+        Field::DeviceType,
+        Field::Status,
+        Field::Site,
+        Field::PrimaryIp4,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Field::Name => "name",
+            Field::Serial => "serial",
+            Field::Platform => "platform",
+            Field::DeviceType => "device_type",
+            Field::Status => "status",
+            Field::Site => "site",
+            Field::PrimaryIp4 => "primary_ip4",
+        }
+    }
+
+    // The record's value for this field, as shown in logs and the report
+    pub fn value(&self, record: &Record) -> Option<String> {
+        match self {
+            Field::Name => Some(record.name.clone()),
+            Field::Serial => record.serial.clone(),
+            Field::Platform => record.platform.clone(),
+            Field::DeviceType => match (&record.manufacturer, &record.model) {
+                (Some(manufacturer), Some(model)) => Some(format!("{} {}", manufacturer, model)),
+                _ => None,
+            },
+            Field::Status => record
+                .status
+                .as_ref()
+                .map(|s| format!("{:?}", s).to_lowercase()),
+            Field::Site => record.site.clone(),
+            Field::PrimaryIp4 => record.primary_ip4.clone(),
+        }
+    }
+}
+
+impl fmt::Display for Field {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// An inventory provider. Adding one means implementing this trait, adding its
+/// `SourceKind` and config section and registering it in `Registry::new`.
+#[async_trait]
+pub trait Source: Send + Sync + Debug {
+    fn kind(&self) -> SourceKind;
+
+    /// Device objects exactly as the API returned them, kept in snapshots
+    async fn fetch(&self) -> Result<Vec<Value>>;
+
+    /// Filters and converts fetched objects, also used to replay a snapshot
+    fn records(&self, raw: Vec<Value>) -> Result<Fetched>;
+
+    /// Default precedence: when sources disagree on a field the value of the
+    /// highest priority wins, 0 means the source never supplies the field
+    fn priority(&self, field: Field) -> u8;
+
+    /// False for sources that only enrich devices reported by another source
+    fn adds_devices(&self) -> bool {
+        true
+    }
+
+    /// Tags besides [`SourceKind::tag`] its records may carry
+    fn extra_tags(&self) -> &'static [&'static str] {
+        &[]
+    }
+}
+
+/// The configured and enabled sources, in fetch order, and how they rank per field
+#[derive(Debug, Default)]
+pub struct Registry {
+    sources: Vec<Box<dyn Source>>,
+    precedence: BTreeMap<Field, Vec<SourceKind>>,
+}
+
+impl Registry {
+    pub fn new(settings: &Settings) -> Self {
+        let mut sources: Vec<Box<dyn Source>> = Vec::new();
+        for kind in settings.sources() {
+            match kind {
+                SourceKind::Azure => {
+                    if let Some(config) = &settings.azure {
+                        sources.push(Box::new(AzureClient::new(config)));
+                    }
+                }
+                SourceKind::FortiGate => {
+                    if let Some(config) = &settings.fortigate {
+                        sources.push(Box::new(FortiGateClient::new(config)));
+                    }
+                }
+                SourceKind::NagiosXi => {
+                    if let Some(config) = &settings.nagiosxi {
+                        sources.push(Box::new(NagiosxiClient::new(config)));
+                    }
+                }
+            }
+        }
+        Self {
+            sources,
+            precedence: settings.precedence.clone(),
+        }
+    }
+
+    /// Sort key of `source` for `field`, lower wins. Sources listed in the
+    /// `[precedence]` table come first in their listed order, then the others by
+    /// their own priority. None when the source doesn't supply the field.
+    pub fn rank(&self, source: &dyn Source, field: Field) -> Option<(usize, usize)> {
+        let listed = self.precedence.get(&field);
+        if let Some(position) = listed.and_then(|l| l.iter().position(|k| *k == source.kind())) {
+            return Some((0, position));
+        }
+        match source.priority(field) {
+            0 => None,
+            priority => Some((1, usize::from(u8::MAX - priority))),
+        }
+    }
+
+    pub fn get(&self, kind: SourceKind) -> Option<&dyn Source> {
+        self.sources
+            .iter()
+            .find(|s| s.kind() == kind)
+            .map(|s| s.as_ref())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &dyn Source> {
+        self.sources.iter().map(|s| s.as_ref())
+    }
+}
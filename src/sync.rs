@@ -0,0 +1,508 @@
+use crate::{
+    cache::LocalCache,
+    config::{MissingTypeStrategy, Settings},
+    consolidate,
+    fetch::azure::AzureClient,
+    metrics,
+    netbox::{
+        api::ApiClient,
+        models::{Contact, Device, NetBoxModel, PostDevice},
+    },
+    normalize::Normalizer,
+    ownership::{Control, Ownership},
+    report::{RunReport, SharedReport},
+    snapshot::Snapshots,
+    source::{Record, Registry},
+    tags::TagPolicy,
+};
+use anyhow::{anyhow, Result};
+use clap::ValueEnum;
+use dashmap::DashMap;
+use futures::{
+    future::join_all,
+    stream::{self, StreamExt},
+};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fmt, sync::Arc};
+use tokio::time::Instant;
+use tracing::{error, info, warn, Instrument};
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, ValueEnum, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum SourceKind {
+    Azure,
+    #[value(name = "fortigate")]
+    FortiGate,
+    #[value(name = "nagiosxi")]
+    NagiosXi,
+}
+
+impl SourceKind {
+    pub const ALL: [SourceKind; 3] = [
+        SourceKind::Azure,
+        SourceKind::FortiGate,
+        SourceKind::NagiosXi,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SourceKind::Azure => "azure",
+            SourceKind::FortiGate => "fortigate",
+            SourceKind::NagiosXi => "nagiosxi",
+        }
+    }
+
+    // Tag stamped on devices this source contributed to
+    pub fn tag(&self) -> Option<&'static str> {
+        match self {
+            SourceKind::Azure => Some("AAD"),
+            SourceKind::FortiGate => Some("FortiGate"),
+            SourceKind::NagiosXi => None,
+        }
+    }
+}
+
+impl fmt::Display for SourceKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+// Latest known records of every source. The daemon keeps this between cycles so a
+// source that isn't due still contributes its last fetch to consolidation.
+#[derive(Debug, Default, Clone)]
+pub struct SourceData {
+    records: BTreeMap<SourceKind, Vec<Record>>,
+}
+
+impl SourceData {
+    pub fn records(&self, kind: SourceKind) -> &[Record] {
+        self.records
+            .get(&kind)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    pub fn apply(&mut self, kind: SourceKind, records: Vec<Record>) {
+        self.records.insert(kind, records);
+    }
+
+    // Sources that have records, possibly from an earlier fetch
+    pub fn kinds(&self) -> Vec<SourceKind> {
+        self.records.keys().copied().collect()
+    }
+}
+
+// Outcome of fetching one source
+#[derive(Debug, Clone)]
+pub struct SourceRun {
+    pub kind: SourceKind,
+    pub records: usize,
+    pub error: Option<String>,
+}
+
+// Fetches `sources` concurrently. A failed source is logged and recorded in the
+// report; its entry in `data` keeps whatever it held before, and the other
+// sources carry on.
+pub async fn fetch_sources(
+    registry: &Registry,
+    snapshots: &Snapshots,
+    sources: &[SourceKind],
+    data: &mut SourceData,
+    report: &SharedReport,
+) -> Vec<SourceRun> {
+    let run_dir = snapshots.run_dir();
+    let run_dir = run_dir.as_deref();
+    let results = join_all(sources.iter().map(|kind| async move {
+        let source = registry
+            .get(*kind)
+            .ok_or_else(|| anyhow!("[{}] is not configured", kind))?;
+        let raw = snapshots.fetch(source).await?;
+        if let Some(dir) = run_dir {
+            // losing the snapshot is no reason to fail the run
+            if let Err(e) = snapshots.write(dir, *kind, &raw) {
+                warn!(source = %kind, error = format!("{:#}", e), "failed to write snapshot");
+            }
+        }
+        source.records(raw)
+    }))
+    .await;
+    let mut runs = Vec::with_capacity(sources.len());
+    let mut r = report.lock().unwrap();
+
+    for (kind, result) in sources.iter().zip(results) {
+        match result {
+            Ok(fetched) => {
+                let records = fetched.records.len();
+                info!(source = %kind, records, "fetched source");
+                let filtered = fetched
+                    .filtered
+                    .into_iter()
+                    .map(|(rule, count)| (rule.to_string(), count))
+                    .collect();
+                r.source_ok(kind.name(), records, filtered);
+                data.apply(*kind, fetched.records);
+                runs.push(SourceRun {
+                    kind: *kind,
+                    records,
+                    error: None,
+                });
+            }
+            Err(e) => {
+                error!(source = %kind, error = format!("{:#}", e), "source unavailable");
+                r.source_failed(kind.name(), &e);
+                runs.push(SourceRun {
+                    kind: *kind,
+                    records: 0,
+                    error: Some(format!("{:#}", e)),
+                });
+            }
+        }
+    }
+
+    runs
+}
+
+// Sources of `runs` that were fetched successfully
+pub fn available(runs: &[SourceRun]) -> Vec<SourceKind> {
+    runs.iter()
+        .filter(|run| run.error.is_none())
+        .map(|run| run.kind)
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ObjectKind {
+    Devices,
+    Contacts,
+}
+
+#[derive(Debug, Clone)]
+pub struct SyncOptions {
+    pub sources: Vec<SourceKind>,
+    pub objects: Vec<ObjectKind>,
+}
+
+impl SyncOptions {
+    // Devices from every configured source
+    pub fn new(settings: &Settings) -> Self {
+        Self {
+            sources: settings.sources(),
+            objects: vec![ObjectKind::Devices],
+        }
+    }
+}
+
+// Everything a run needs before it can write to NetBox
+pub struct Prepared {
+    pub registry: Registry,
+    pub normalizer: Normalizer,
+    pub netbox_client: Arc<ApiClient>,
+    pub local_cache: LocalCache,
+    pub data: SourceData,
+    pub report: SharedReport,
+    // sources that were fetched successfully this run
+    pub available: Vec<SourceKind>,
+}
+
+// Preloads the cache while fetching `sources`
+pub async fn prepare(settings: &Settings, sources: &[SourceKind]) -> Result<Prepared> {
+    let start_time = Instant::now();
+    let report = RunReport::shared();
+    let registry = Registry::new(settings);
+    let normalizer = Normalizer::new(settings)?;
+    let run_id = report.lock().unwrap().run_id.clone();
+    let netbox_client = Arc::new(ApiClient::new(&settings.netbox).for_run(&run_id));
+    let mut data = SourceData::default();
+
+    // Build cache and get data
+    let cache_future = LocalCache::preload(netbox_client.clone());
+    let snapshots = Snapshots::new(&settings.snapshot);
+    let fetch_future = fetch_sources(&registry, &snapshots, sources, &mut data, &report);
+    let (local_cache, runs) = tokio::join!(cache_future, fetch_future);
+    let local_cache = local_cache?.with_naming(normalizer.naming.clone());
+    report.lock().unwrap().phase("fetch", start_time.elapsed());
+
+    Ok(Prepared {
+        registry,
+        normalizer,
+        netbox_client,
+        local_cache,
+        data,
+        report,
+        available: available(&runs),
+    })
+}
+
+// One-shot run: preload the cache and fetch the selected sources, then push.
+pub async fn run_once(settings: &Settings, options: &SyncOptions) -> Result<RunReport> {
+    let start_time = Instant::now();
+    let Prepared {
+        registry,
+        normalizer,
+        netbox_client,
+        local_cache,
+        data,
+        report,
+        available,
+    } = prepare(settings, &options.sources).await?;
+    info!(run_id = %report.lock().unwrap().run_id, "writing to NetBox");
+
+    if options.objects.contains(&ObjectKind::Contacts) {
+        if available.contains(&SourceKind::Azure) {
+            sync_contacts(settings, netbox_client.clone(), &local_cache, &report).await?;
+        } else {
+            warn!("contacts come from Azure, which is not selected or unavailable");
+        }
+    }
+    if options.objects.contains(&ObjectKind::Devices) {
+        sync_devices(
+            settings,
+            &registry,
+            &normalizer,
+            &data,
+            netbox_client,
+            local_cache,
+            report.clone(),
+        )
+        .await;
+    }
+
+    info!(elapsed = ?start_time.elapsed(), "run complete");
+    let report = finish(settings, &report)?;
+    Ok(report)
+}
+
+// Creates Azure users missing from NetBox as contacts
+pub async fn sync_contacts(
+    settings: &Settings,
+    netbox_client: Arc<ApiClient>,
+    local_cache: &LocalCache,
+    report: &SharedReport,
+) -> Result<()> {
+    let phase_start = Instant::now();
+    let config = settings
+        .azure
+        .as_ref()
+        .ok_or_else(|| anyhow!("[azure] is not configured"))?;
+    let client = AzureClient::new(config);
+    let users = client.fetch_users().await?;
+
+    let missing: Vec<Contact> = users
+        .into_iter()
+        .map(Contact::from)
+        .filter(|c| !local_cache.contacts.contains_key(&c.get_cache_key()))
+        .collect();
+    info!(count = missing.len(), "contacts to create");
+
+    stream::iter(missing)
+        .map(|mut contact| {
+            let api = netbox_client.clone();
+            async move {
+                let res = local_cache
+                    .ensure_cached(&mut contact, &api, &local_cache.contacts, report)
+                    .await;
+                (contact.get_cache_key(), res)
+            }
+        })
+        .buffer_unordered(settings.netbox.api_limit)
+        .for_each(|(key, res)| async move {
+            if let Err(e) = res {
+                error!(contact = %key, error = format!("{:#}", e), "failed to create contact");
+                report
+                    .lock()
+                    .unwrap()
+                    .fail(Contact::get_endpoint(), &key, &e);
+            }
+        })
+        .await;
+
+    report
+        .lock()
+        .unwrap()
+        .phase("contacts", phase_start.elapsed());
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PlanAction {
+    Create,
+    Update,
+    Unchanged,
+    Skip,
+    // carries a lock tag, never updated
+    Locked,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DevicePlan {
+    pub device: String,
+    pub action: PlanAction,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub netbox_id: Option<u32>,
+    // related objects NetBox doesn't have yet, as `endpoint:key`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub creates: Vec<String>,
+}
+
+// What `push_devices` would do, without writing anything
+pub fn plan(
+    devices: &DashMap<String, Device>,
+    local_cache: &LocalCache,
+    ownership: &Ownership,
+    tags: &TagPolicy,
+) -> Vec<DevicePlan> {
+    let mut plans: Vec<DevicePlan> = devices
+        .iter()
+        .map(|entry| plan_device(entry.key(), entry.value(), local_cache, ownership, tags))
+        .collect();
+    plans.sort_by(|a, b| a.device.cmp(&b.device));
+    plans
+}
+
+pub fn plan_device(
+    key: &str,
+    device: &Device,
+    local_cache: &LocalCache,
+    ownership: &Ownership,
+    tags: &TagPolicy,
+) -> DevicePlan {
+    let mut device = device.clone();
+    let current = local_cache.devices.get(key).map(|c| c.clone());
+    let netbox_id = current.as_ref().and_then(|c| c.get_id());
+    let control = match (&current, netbox_id) {
+        (Some(current), Some(_)) => ownership.control(current),
+        _ => Control::Managed,
+    };
+    if control == Control::Managed {
+        device.push_tag(ownership.managed_tag());
+    }
+    tags.merge(&mut device, current.as_ref());
+    let creates = match control {
+        Control::Locked(_) => Vec::new(),
+        _ => local_cache.resolve_device_components(&mut device),
+    };
+
+    let action = if device.device_type.is_none() {
+        PlanAction::Skip
+    } else if netbox_id.is_none() {
+        PlanAction::Create
+    } else if matches!(control, Control::Locked(_)) {
+        PlanAction::Locked
+    } else {
+        device.id = netbox_id;
+        let unchanged = creates.is_empty()
+            && current.as_ref().is_some_and(|c| {
+                PostDevice::try_from(device.clone())
+                    .is_ok_and(|post| post.matches_fields(c, control.fields()))
+            });
+        if unchanged {
+            PlanAction::Unchanged
+        } else {
+            PlanAction::Update
+        }
+    };
+
+    DevicePlan {
+        device: key.to_string(),
+        action,
+        netbox_id,
+        creates,
+    }
+}
+
+// Consolidates `data` and pushes the resulting devices
+pub async fn sync_devices(
+    settings: &Settings,
+    registry: &Registry,
+    normalizer: &Normalizer,
+    data: &SourceData,
+    netbox_client: Arc<ApiClient>,
+    local_cache: LocalCache,
+    report: SharedReport,
+) {
+    let phase_start = Instant::now();
+    let devices = consolidate::devices(data, registry, normalizer, &report);
+    {
+        let mut r = report.lock().unwrap();
+        r.devices_consolidated = devices.len();
+        r.phase("consolidate", phase_start.elapsed());
+    }
+
+    let phase_start = Instant::now();
+    let ownership = Ownership::new(&settings.ownership);
+    let tags = TagPolicy::new(&settings.tags, registry, &data.kinds(), &ownership);
+    push_devices(
+        devices,
+        netbox_client,
+        local_cache,
+        ownership,
+        tags,
+        report.clone(),
+        settings.netbox.api_limit,
+    )
+    .await;
+    report.lock().unwrap().phase("push", phase_start.elapsed());
+}
+
+pub async fn push_devices(
+    devices: DashMap<String, Device>,
+    netbox_client: Arc<ApiClient>,
+    local_cache: LocalCache,
+    ownership: Ownership,
+    tags: TagPolicy,
+    report: SharedReport,
+    concurrency: usize,
+) {
+    stream::iter(devices)
+        .map(|(key, device)| {
+            // consolidation skips or falls back for devices without a type
+            debug_assert!(device.device_type.is_some(), "{} has no device type", key);
+            let api = netbox_client.clone();
+            let cache = local_cache.clone();
+            let (ownership, tags) = (&ownership, &tags);
+            let report = report.clone();
+            async move {
+                let res = device
+                    .push_to_netbox(&api, &cache, ownership, tags, &report)
+                    .await;
+                (key, res)
+            }
+        })
+        .buffer_unordered(concurrency)
+        .for_each(|(key, res)| {
+            let report = report.clone();
+            async move {
+                if let Err(e) = res {
+                    error!(device = %key, error = format!("{:#}", e), "push_to_netbox failed");
+                    report
+                        .lock()
+                        .unwrap()
+                        .fail(Device::get_endpoint(), &key, &e);
+                }
+            }
+        })
+        .in_current_span()
+        .await;
+}
+
+// Stamps the report, updates run metrics and writes the configured outputs
+pub fn finish(settings: &Settings, report: &SharedReport) -> Result<RunReport> {
+    let mut report = report.lock().unwrap().clone();
+    report.finish();
+    // a run whose writes failed is no success either
+    if report.sources.values().all(|s| s.error.is_none()) && report.failures.is_empty() {
+        metrics::run_succeeded();
+    }
+    report.write(&settings.report)?;
+    if settings.fallback.device_type == MissingTypeStrategy::Review {
+        report.write_review_queue(&settings.fallback.review_file)?;
+    }
+    if let Some(path) = &settings.metrics.textfile {
+        metrics::write_textfile(path)?;
+    }
+    Ok(report)
+}
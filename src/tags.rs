@@ -0,0 +1,77 @@
+use crate::{
+    config::TagConfig,
+    netbox::models::{Device, Tag},
+    ownership::Ownership,
+    source::Registry,
+    sync::SourceKind,
+    utils::sanitize_slug,
+};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+// Which device tags the ingester decides on this run, and how the tags it creates look
+#[derive(Debug, Clone, Default)]
+pub struct TagPolicy {
+    // slugs; a device keeps one of these only while it is derived for it again
+    decided: HashSet<String>,
+    // slug -> color and description of new tags
+    styles: HashMap<String, TagConfig>,
+}
+
+impl TagPolicy {
+    // `sources` are the ones with records this run, tags of the others stay as they are
+    pub fn new(
+        config: &BTreeMap<String, TagConfig>,
+        registry: &Registry,
+        sources: &[SourceKind],
+        ownership: &Ownership,
+    ) -> Self {
+        let mut decided: HashSet<String> = registry
+            .iter()
+            .filter(|source| sources.contains(&source.kind()))
+            .flat_map(|source| {
+                source
+                    .kind()
+                    .tag()
+                    .into_iter()
+                    .chain(source.extra_tags().iter().copied())
+            })
+            .map(sanitize_slug)
+            .collect();
+        decided.insert(ownership.managed_tag().slug);
+        Self {
+            decided,
+            styles: config
+                .iter()
+                .map(|(name, style)| (sanitize_slug(name), style.clone()))
+                .collect(),
+        }
+    }
+
+    // Fills in the configured color and description, unless the tag has its own
+    pub fn style(&self, tag: &mut Tag) {
+        if let Some(style) = self.styles.get(&tag.slug) {
+            tag.color = tag.color.take().or_else(|| style.color.clone());
+            tag.description = tag.description.take().or_else(|| style.description.clone());
+        }
+    }
+
+    // Gives `device`, carrying the tags derived for it this run, the tag set it
+    // should end up with: those plus every tag of `current` this run doesn't
+    // decide on, i.e. manual tags and tags of sources that weren't fetched
+    pub fn merge(&self, device: &mut Device, current: Option<&Device>) {
+        let mut tags = device.tags.take().unwrap_or_default();
+        for tag in &mut tags {
+            self.style(tag);
+        }
+        let kept = current
+            .and_then(|c| c.tags.as_ref())
+            .into_iter()
+            .flatten()
+            .filter(|t| !self.decided.contains(&sanitize_slug(&t.slug)))
+            .filter(|t| !tags.iter().any(|d| d.slug == t.slug))
+            .cloned()
+            .collect::<Vec<_>>();
+        tags.extend(kept);
+        device.tags = Some(tags);
+    }
+}
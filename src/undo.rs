@@ -0,0 +1,251 @@
+use crate::{
+    netbox::{
+        api::ApiClient,
+        changes::{ChangeAction, ObjectChange, RunChanges},
+        delete::Deletions,
+    },
+    report::{Outcome, RunReport},
+};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration};
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Mutex,
+};
+use tracing::{info, warn};
+
+// Fields NetBox maintains itself, never sent back
+const READ_ONLY: [&str; 5] = ["id", "url", "display", "created", "last_updated"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UndoAction {
+    // the run created the object
+    Delete,
+    // the run updated it, its changed fields are set back
+    Restore,
+    // the run deleted it, it is created again under a new id
+    Recreate,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UndoStep {
+    pub action: UndoAction,
+    // e.g. `dcim.device`
+    pub object_type: String,
+    pub id: u32,
+    pub object: String,
+    // None for object types the ingester doesn't write
+    pub endpoint: Option<&'static str>,
+    // the fields sent to restore or recreate the object
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub body: Option<Value>,
+    // why the step is left out
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub skipped: Option<String>,
+}
+
+// The steps reverting `run`, newest change first so objects go before the
+// objects they refer to. An object the run changed several times is reverted
+// to its state before the first change. Objects changed again since the run,
+// gone since or protected from deletion are skipped.
+pub async fn plan(
+    api: &ApiClient,
+    run: &RunChanges,
+    deletions: &Deletions,
+) -> Result<Vec<UndoStep>> {
+    // (object type, id) -> first and last change
+    let mut objects: HashMap<(&str, u32), (usize, usize)> = HashMap::new();
+    for (i, change) in run.changes.iter().enumerate() {
+        let key = (
+            change.changed_object_type.as_str(),
+            change.changed_object_id,
+        );
+        objects.entry(key).or_insert((i, i)).1 = i;
+    }
+    let mut order: Vec<(usize, usize)> = objects.into_values().collect();
+    order.sort_by_key(|(_, last)| std::cmp::Reverse(*last));
+
+    let mut steps = Vec::new();
+    for (first, last) in order {
+        let (first, last) = (&run.changes[first], &run.changes[last]);
+        let action = match (first.action(), last.action()) {
+            // nothing of it is left
+            (ChangeAction::Create, ChangeAction::Delete) => continue,
+            (ChangeAction::Create, _) => UndoAction::Delete,
+            (_, ChangeAction::Delete) => UndoAction::Recreate,
+            _ => UndoAction::Restore,
+        };
+        let mut step = UndoStep {
+            action,
+            object_type: last.changed_object_type.clone(),
+            id: last.changed_object_id,
+            object: last.object_repr.clone(),
+            endpoint: last.endpoint(),
+            body: None,
+            skipped: None,
+        };
+        let Some(endpoint) = step.endpoint else {
+            step.skipped = Some("not an object type the ingester writes".into());
+            steps.push(step);
+            continue;
+        };
+
+        let current: Option<Value> = match action {
+            UndoAction::Recreate => None,
+            _ => api
+                .get_one(endpoint, step.id)
+                .await
+                .context(format!("reading {} {}", step.object_type, step.id))?,
+        };
+        step.skipped = match (action, &current) {
+            (UndoAction::Recreate, _) => match &first.prechange_data {
+                Some(before) => {
+                    step.body = Some(recreated(before));
+                    None
+                }
+                None => Some("no pre-change data to recreate from".into()),
+            },
+            (_, None) => Some("deleted since the run".into()),
+            (_, Some(current)) if changed_since(current, last) => {
+                Some("changed since the run".into())
+            }
+            (UndoAction::Delete, Some(current)) if deletions.protects(&tag_slugs(current)) => {
+                Some("protected from deletion by tag".into())
+            }
+            (UndoAction::Delete, _) => None,
+            (UndoAction::Restore, _) => {
+                let restored = restored(first, last);
+                let unchanged = restored.is_empty();
+                step.body = Some(Value::Object(restored));
+                unchanged.then(|| "no field to restore".into())
+            }
+        };
+        steps.push(step);
+    }
+    Ok(steps)
+}
+
+// Runs the steps that aren't skipped in order, recording each in the report.
+// The deletions are counted against the limits up front, a plan deleting more
+// than allowed runs nothing.
+pub async fn apply(
+    steps: &[UndoStep],
+    api: &ApiClient,
+    deletions: &Deletions,
+    report: &Mutex<RunReport>,
+) -> Result<()> {
+    let steps: Vec<(&UndoStep, &str)> = steps
+        .iter()
+        .filter(|s| s.skipped.is_none())
+        .filter_map(|s| s.endpoint.map(|endpoint| (s, endpoint)))
+        .collect();
+    let mut deletes: BTreeMap<&str, usize> = BTreeMap::new();
+    for (step, endpoint) in &steps {
+        if step.action == UndoAction::Delete {
+            *deletes.entry(endpoint).or_default() += 1;
+        }
+    }
+    for (endpoint, count) in &deletes {
+        deletions.reserve(endpoint, *count)?;
+    }
+
+    for (step, endpoint) in steps {
+        let key = format!("{} {}", step.object_type, step.id);
+        let body = step.body.clone().unwrap_or_default();
+        let (res, outcome) = match step.action {
+            UndoAction::Delete => (api.delete(endpoint, step.id).await, Outcome::Deleted),
+            UndoAction::Restore => (
+                api.patch::<Value, _>(&format!("{}/{}", endpoint, step.id), &body)
+                    .await
+                    .map(|_| ()),
+                Outcome::Updated,
+            ),
+            UndoAction::Recreate => (
+                api.post::<Value, _>(endpoint, &body).await.map(|_| ()),
+                Outcome::Created,
+            ),
+        };
+        match res {
+            Ok(()) => {
+                info!(action = ?step.action, object = %step.object, %key, "undone");
+                report.lock().unwrap().record(endpoint, outcome);
+            }
+            Err(e) => {
+                if step.action == UndoAction::Delete {
+                    deletions.release(1);
+                }
+                warn!(action = ?step.action, object = %step.object, %key, error = format!("{:#}", e), "failed to undo");
+                report.lock().unwrap().fail(endpoint, &key, &e);
+            }
+        }
+    }
+    Ok(())
+}
+
+// The fields `last` left different from before `first`, as they were before
+fn restored(first: &ObjectChange, last: &ObjectChange) -> Map<String, Value> {
+    let (Some(Value::Object(before)), Some(Value::Object(after))) =
+        (&first.prechange_data, &last.postchange_data)
+    else {
+        return Map::new();
+    };
+    before
+        .iter()
+        .filter(|(field, _)| !READ_ONLY.contains(&field.as_str()))
+        .filter(|(field, value)| after.get(field.as_str()) != Some(value))
+        .map(|(field, value)| (field.clone(), writable(field, value)))
+        .collect()
+}
+
+fn recreated(before: &Value) -> Value {
+    let Value::Object(before) = before else {
+        return before.clone();
+    };
+    Value::Object(
+        before
+            .iter()
+            .filter(|(field, _)| !READ_ONLY.contains(&field.as_str()))
+            .map(|(field, value)| (field.clone(), writable(field, value)))
+            .collect(),
+    )
+}
+
+// The changelog keeps tags by name, the API takes them as objects
+fn writable(field: &str, value: &Value) -> Value {
+    match (field, value) {
+        ("tags", Value::Array(tags)) => tags
+            .iter()
+            .map(|tag| match tag {
+                Value::String(name) => json!({ "name": name }),
+                other => other.clone(),
+            })
+            .collect(),
+        _ => value.clone(),
+    }
+}
+
+// Whether the object was saved after the run's last change to it. The
+// changelog keeps timestamps to the millisecond, the API to the microsecond.
+fn changed_since(current: &Value, last: &ObjectChange) -> bool {
+    let saved = |object: Option<&Value>| {
+        object
+            .and_then(|o| o["last_updated"].as_str())
+            .and_then(|t| DateTime::parse_from_rfc3339(t).ok())
+    };
+    match (saved(Some(current)), saved(last.postchange_data.as_ref())) {
+        (Some(now), Some(then)) => now - then >= Duration::milliseconds(1),
+        _ => false,
+    }
+}
+
+fn tag_slugs(object: &Value) -> Vec<String> {
+    object["tags"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|t| t["slug"].as_str().map(String::from))
+        .collect()
+}
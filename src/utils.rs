@@ -1,24 +1,3 @@
-pub async fn extract_vec<T, E1, E2>(res: Result<Result<Vec<T>, E1>, E2>) -> Vec<T>
-where
-    T: std::fmt::Debug,
-    E1: std::fmt::Display,
-    E2: std::fmt::Display,
-{
-    match res {
-        Ok(inner_result) => match inner_result {
-            Ok(items) => items,
-            Err(e) => {
-                eprintln!("Error fetching data: {}", e);
-                Vec::new()
-            }
-        },
-        Err(e) => {
-            eprintln!("Task panicked: {}", e);
-            Vec::new()
-        }
-    }
-}
-
 pub fn sanitize_slug(input: &str) -> String {
     // trim and lowercase input
     let s = input.trim().to_lowercase();
@@ -42,3 +21,15 @@ pub fn sanitize_slug(input: &str) -> String {
 
     slug.trim_matches('-').to_string()
 }
+
+// Flattens a std error and its sources into `outer: inner: root`
+pub fn error_chain(e: &dyn std::error::Error) -> String {
+    let mut out = e.to_string();
+    let mut cause = e.source();
+    while let Some(inner) = cause {
+        out.push_str(": ");
+        out.push_str(&inner.to_string());
+        cause = inner.source();
+    }
+    out
+}
@@ -0,0 +1,209 @@
+use crate::{
+    consolidate,
+    daemon::Worker,
+    ownership::Ownership,
+    report::{ObjectCounts, RunReport},
+    sync::{self, DevicePlan, SourceKind},
+    tags::TagPolicy,
+};
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::post,
+    Json, Router,
+};
+use dashmap::DashMap;
+use hmac::{digest::KeyInit, Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Sha256, Sha512};
+use std::{collections::BTreeMap, sync::Arc};
+use tracing::{debug, info, info_span, warn, Instrument};
+
+// The routes of the webhooks configured under `[webhooks]`
+pub(crate) fn router(worker: Arc<Worker>) -> Router {
+    let mut router = Router::new();
+    if worker.settings.webhooks.netbox.is_some() {
+        info!("accepting NetBox webhooks on /webhooks/netbox");
+        router = router.route("/webhooks/netbox", post(netbox));
+    }
+    if worker.settings.webhooks.events.is_some() {
+        info!("accepting sync events on /webhooks/sync");
+        router = router.route("/webhooks/sync", post(event));
+    }
+    router.with_state(worker)
+}
+
+// What NetBox posts for an object saved or deleted
+#[derive(Debug, Deserialize)]
+struct NetBoxEvent {
+    // created, updated or deleted
+    event: String,
+    // e.g. `device`
+    model: String,
+    data: Value,
+}
+
+// NetBox signs the body with HMAC-SHA512 in `X-Hook-Signature`
+async fn netbox(State(worker): State<Arc<Worker>>, headers: HeaderMap, body: Bytes) -> Response {
+    let secret = &worker.settings.webhooks.netbox.as_ref().unwrap().secret;
+    let signature = header(&headers, "X-Hook-Signature");
+    if !verify::<Hmac<Sha512>>(secret, &body, signature) {
+        warn!("rejected NetBox webhook with a bad signature");
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    let hook: NetBoxEvent = match serde_json::from_slice(&body) {
+        Ok(hook) => hook,
+        Err(e) => {
+            return (StatusCode::BAD_REQUEST, format!("invalid webhook: {}", e)).into_response()
+        }
+    };
+
+    let deleted = hook.event == "deleted";
+    match worker.local_cache.refresh(&hook.model, hook.data, deleted) {
+        Ok(true) => {
+            info!(model = %hook.model, event = %hook.event, "cache refreshed from NetBox");
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Ok(false) => {
+            debug!(model = %hook.model, "webhook for a model the cache doesn't hold");
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Err(e) => (StatusCode::UNPROCESSABLE_ENTITY, format!("{:#}", e)).into_response(),
+    }
+}
+
+// An inbound event naming one device, e.g. from a FortiGate automation stitch
+#[derive(Debug, Deserialize)]
+struct SyncEvent {
+    device: String,
+    // refetched before the sync, otherwise the records of the last cycle are used
+    #[serde(default)]
+    source: Option<SourceKind>,
+}
+
+#[derive(Debug, Serialize)]
+struct Synced {
+    run_id: String,
+    device: String,
+    objects: BTreeMap<String, ObjectCounts>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    failures: BTreeMap<String, Vec<String>>,
+    // what a dry run would have done instead of writing
+    #[serde(skip_serializing_if = "Option::is_none")]
+    plan: Option<DevicePlan>,
+}
+
+// Events are signed with HMAC-SHA256 in `X-Signature-256: sha256=<hex>`
+async fn event(State(worker): State<Arc<Worker>>, headers: HeaderMap, body: Bytes) -> Response {
+    let secret = &worker.settings.webhooks.events.as_ref().unwrap().secret;
+    let signature = header(&headers, "X-Signature-256").and_then(|s| s.strip_prefix("sha256="));
+    if !verify::<Hmac<Sha256>>(secret, &body, signature) {
+        warn!("rejected sync event with a bad signature");
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    let event: SyncEvent = match serde_json::from_slice(&body) {
+        Ok(event) => event,
+        Err(e) => {
+            return (StatusCode::BAD_REQUEST, format!("invalid event: {}", e)).into_response()
+        }
+    };
+    if let Some(kind) = event
+        .source
+        .filter(|k| !worker.settings.sources().contains(k))
+    {
+        return (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("source {} is not configured", kind.name()),
+        )
+            .into_response();
+    }
+
+    // keyed like the consolidated devices
+    let key = worker.normalizer.naming.key(&event.device);
+    let span = info_span!("event", device = %key);
+    match sync_one(&worker, &key, event.source).instrument(span).await {
+        Some(synced) => Json(synced).into_response(),
+        None => (
+            StatusCode::NOT_FOUND,
+            format!("no source reports a device `{}`", event.device),
+        )
+            .into_response(),
+    }
+}
+
+// Refetches `source`, if given, then consolidates and pushes the device `key`
+// alone as a run of its own, or only plans it in a dry run. None when no source
+// reports the device.
+async fn sync_one(worker: &Worker, key: &str, source: Option<SourceKind>) -> Option<Synced> {
+    let settings = &worker.settings;
+    let report = RunReport::shared();
+    let run_id = report.lock().unwrap().run_id.clone();
+    let mut data = worker.data.lock().await;
+
+    if let Some(kind) = source {
+        let runs = sync::fetch_sources(
+            &worker.registry,
+            &worker.snapshots,
+            &[kind],
+            &mut data,
+            &report,
+        )
+        .await;
+        worker.record(runs);
+    }
+    let devices = consolidate::devices(&data, &worker.registry, &worker.normalizer, &report);
+    let (key, device) = devices.remove(key)?;
+    let ownership = Ownership::new(&settings.ownership);
+    let tags = TagPolicy::new(&settings.tags, &worker.registry, &data.kinds(), &ownership);
+
+    if worker.dry_run {
+        let plan = sync::plan_device(&key, &device, &worker.local_cache, &ownership, &tags);
+        info!(%run_id, action = ?plan.action, "planned device for event, nothing written");
+        return Some(Synced {
+            run_id,
+            device: key,
+            objects: BTreeMap::new(),
+            failures: BTreeMap::new(),
+            plan: Some(plan),
+        });
+    }
+    info!(%run_id, "syncing device for event");
+    sync::push_devices(
+        DashMap::from_iter([(key.clone(), device)]),
+        Arc::new(worker.netbox_client.for_run(&run_id)),
+        worker.local_cache.clone(),
+        ownership,
+        tags,
+        report.clone(),
+        1,
+    )
+    .await;
+
+    let report = report.lock().unwrap();
+    Some(Synced {
+        run_id,
+        device: key,
+        objects: report.objects.clone(),
+        failures: report.failures.clone(),
+        plan: None,
+    })
+}
+
+fn header<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|v| v.to_str().ok())
+}
+
+// Whether `signature`, hex encoded, is the MAC of `body` under `secret`
+fn verify<M: Mac + KeyInit>(secret: &str, body: &[u8], signature: Option<&str>) -> bool {
+    let Some(signature) = signature.and_then(|s| hex::decode(s.trim()).ok()) else {
+        return false;
+    };
+    let Ok(mut mac) = <M as Mac>::new_from_slice(secret.as_bytes()) else {
+        return false;
+    };
+    mac.update(body);
+    mac.verify_slice(&signature).is_ok()
+}
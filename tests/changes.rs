@@ -0,0 +1,160 @@
+mod common;
+
+use common::{run, run_id, test_dir, Mock};
+use netbox_ingester::{netbox::models::Device, NetBoxModel};
+use serde_json::{json, Value};
+
+#[tokio::test]
+async fn every_write_names_the_run() {
+    let mock = Mock::start().await;
+    let dir = test_dir("changes_attributed");
+    let config = mock.config(&dir, "");
+
+    let output = run(&config, &["sync", "--source", "azure"]).await;
+
+    let run_id = run_id(&dir);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout).trim(),
+        format!("run {}", run_id)
+    );
+    let writes: Vec<_> = mock
+        .request_log()
+        .into_iter()
+        .filter(|r| r.method != "GET")
+        .collect();
+    assert!(!writes.is_empty());
+    for write in writes {
+        assert_eq!(write.request_id.as_deref(), Some(run_id.as_str()));
+        assert_eq!(
+            write.message,
+            Some(format!("netbox-ingester run {}", run_id)),
+            "{}",
+            write.line()
+        );
+    }
+}
+
+#[tokio::test]
+async fn changes_lists_only_the_given_run() {
+    let mock = Mock::start().await;
+    let dir = test_dir("changes_listed");
+    let config = mock.config(&dir, "");
+
+    run(&config, &["sync", "--source", "azure"]).await;
+    let first = run_id(&dir);
+    run(&config, &["sync", "--source", "azure"]).await;
+    let second = run_id(&dir);
+
+    let output = run(&config, &["--output", "json", "changes", &first]).await;
+    let changes: Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(changes["counts"]["dcim.device"]["created"], 2);
+    assert_eq!(changes["counts"]["extras.tag"]["created"], 2);
+    let names: Vec<&str> = changes["changes"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|c| c["changed_object_type"] == "dcim.device")
+        .map(|c| c["object_repr"].as_str().unwrap())
+        .collect();
+    assert_eq!(names.len(), 2);
+    assert!(names.contains(&"JAKOB-DESKTOP"), "{:?}", names);
+
+    // the second run found everything in place
+    let output = run(&config, &["changes", &second]).await;
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout).trim(),
+        format!("no changes recorded for run {}", second)
+    );
+}
+
+#[tokio::test]
+async fn changes_are_found_by_their_message_only() {
+    let mock = Mock::start().await;
+    let run_id = "20240101T000000Z-abcdef";
+    let change = |repr: &str, message: Option<String>| {
+        json!({
+            "time": "2024-01-01T00:00:00Z",
+            "action": { "value": "create" },
+            "changed_object_type": "dcim.device",
+            "changed_object_id": 1,
+            "object_repr": repr,
+            "message": message,
+            "postchange_data": { "name": repr },
+        })
+    };
+    mock.insert(
+        "core/object-changes",
+        change("pc01", Some(format!("netbox-ingester run {}", run_id))),
+    );
+    // names the run, but wasn't made by it
+    mock.insert(
+        "core/object-changes",
+        change(&format!("notes-{}", run_id), Some("by hand".into())),
+    );
+    mock.insert("core/object-changes", change("pc02", None));
+    let dir = test_dir("changes_by_message");
+    let config = mock.config(&dir, "");
+
+    let output = run(&config, &["--output", "json", "changes", run_id]).await;
+
+    let changes: Value = serde_json::from_slice(&output.stdout).unwrap();
+    let names: Vec<&Value> = changes["changes"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|c| &c["object_repr"])
+        .collect();
+    assert_eq!(names, vec![&json!("pc01")]);
+    let request = mock
+        .request_log()
+        .into_iter()
+        .find(|r| r.path == "/api/core/object-changes/")
+        .unwrap();
+    let filters: Vec<(String, String)> =
+        url::form_urlencoded::parse(request.query.unwrap_or_default().as_bytes())
+            .into_owned()
+            .filter(|(k, _)| !["limit", "offset", "ordering"].contains(&k.as_str()))
+            .collect();
+    assert_eq!(
+        filters,
+        vec![(
+            "message".to_string(),
+            format!("netbox-ingester run {}", run_id)
+        )]
+    );
+}
+
+#[tokio::test]
+async fn clients_without_a_run_send_no_message() {
+    let mock = Mock::start().await;
+    let id = mock.insert(
+        "dcim/devices",
+        json!({ "name": "pc01", "status": "active" }),
+    );
+    let api = mock.client(1);
+    let device = Device::get_by_id(&api, id as u32).await.unwrap().unwrap();
+
+    device.delete(&api).await.unwrap();
+    let other = mock.insert(
+        "dcim/devices",
+        json!({ "name": "pc02", "status": "active" }),
+    );
+    api.for_run("manual-cleanup")
+        .bulk_delete("dcim/devices", &[other])
+        .await
+        .unwrap();
+
+    let deletes: Vec<_> = mock
+        .request_log()
+        .into_iter()
+        .filter(|r| r.method == "DELETE")
+        .collect();
+    assert_eq!(deletes[0].message, None);
+    assert_eq!(deletes[0].request_id, None);
+    assert_eq!(deletes[0].body, None);
+    assert_eq!(
+        deletes[1].message.as_deref(),
+        Some("netbox-ingester run manual-cleanup")
+    );
+    assert_eq!(deletes[1].body, Some(json!([{ "id": other }])));
+}
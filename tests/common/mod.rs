@@ -0,0 +1,634 @@
+// Local stand-ins for NetBox, Microsoft Graph, FortiGate and Nagios XI, served
+// from one address under /netbox, /login, /graph, /fortigate and /nagios.
+// NetBox keeps what is posted to it, so a second run sees the first one's result.
+
+#![allow(dead_code)] // each test crate uses part of the harness
+
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::{HeaderMap, Method, StatusCode, Uri},
+    response::{IntoResponse, Response},
+    Json, Router,
+};
+use netbox_ingester::{config::NetBoxConfig, netbox::changes::OBJECT_TYPES, ApiClient};
+use serde_json::{json, Map, Value};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    process::Output,
+    sync::{Arc, Mutex},
+};
+use tokio::{net::TcpListener, process::Command};
+
+pub const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures");
+
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub method: String,
+    // below /netbox, e.g. /api/dcim/devices/
+    pub path: String,
+    pub query: Option<String>,
+    // without the changelog message, which is in `message`
+    pub body: Option<Value>,
+    pub message: Option<String>,
+    // the X-Request-ID header
+    pub request_id: Option<String>,
+}
+
+impl Request {
+    // "POST /api/dcim/devices/"
+    pub fn line(&self) -> String {
+        format!("{} {}", self.method, self.path)
+    }
+}
+
+#[derive(Debug, Default)]
+struct MockState {
+    url: String,
+    netbox_requests: Vec<Request>,
+    // endpoint -> objects as posted, with their id
+    objects: BTreeMap<String, Vec<Value>>,
+    next_id: u64,
+    // status FortiGate answers with instead of its fixture
+    fortigate_status: Option<StatusCode>,
+    // page size of lists requested without a limit, all objects when unset
+    page_size: Option<usize>,
+    // offset of the list page NetBox fails to serve
+    failing_offset: Option<usize>,
+    // method NetBox answers with 500 Internal Server Error
+    rejected_method: Option<Method>,
+    // endpoint -> objects created right after its first page was served
+    arriving: BTreeMap<String, Vec<Value>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Mock {
+    pub url: String,
+    state: Arc<Mutex<MockState>>,
+}
+
+impl Mock {
+    pub async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let state = Arc::new(Mutex::new(MockState {
+            url: url.clone(),
+            ..Default::default()
+        }));
+        let app = Router::new().fallback(handle).with_state(state.clone());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        Self { url, state }
+    }
+
+    pub fn fail_fortigate(&self, status: StatusCode) {
+        self.state.lock().unwrap().fortigate_status = Some(status);
+    }
+
+    // NetBox requests so far as sorted "METHOD path" lines, pushes run concurrently
+    pub fn netbox_requests(&self) -> Vec<String> {
+        let mut lines: Vec<String> = self
+            .state
+            .lock()
+            .unwrap()
+            .netbox_requests
+            .iter()
+            .map(Request::line)
+            .collect();
+        lines.sort();
+        lines
+    }
+
+    // Bodies sent to `method path`
+    pub fn bodies(&self, method: &str, path: &str) -> Vec<Value> {
+        self.state
+            .lock()
+            .unwrap()
+            .netbox_requests
+            .iter()
+            .filter(|r| r.method == method && r.path == path)
+            .filter_map(|r| r.body.clone())
+            .collect()
+    }
+
+    // Every NetBox request so far, in arrival order
+    pub fn request_log(&self) -> Vec<Request> {
+        self.state.lock().unwrap().netbox_requests.clone()
+    }
+
+    // Stores `object` in NetBox as if it had been created there
+    pub fn insert(&self, endpoint: &str, object: Value) -> u64 {
+        let object = insert(&mut self.state.lock().unwrap(), endpoint, object);
+        object["id"].as_u64().unwrap()
+    }
+
+    // Inserts the devices pc01 to pc<count>, see `device`, and returns their ids
+    pub fn insert_devices(&self, count: usize) -> Vec<u64> {
+        (1..=count)
+            .map(|i| self.insert("dcim/devices", device(i)))
+            .collect()
+    }
+
+    pub fn page_size(&self, size: usize) {
+        self.state.lock().unwrap().page_size = Some(size);
+    }
+
+    // Fails every NetBox request made with `method`
+    pub fn reject(&self, method: Method) {
+        self.state.lock().unwrap().rejected_method = Some(method);
+    }
+
+    // Answers list pages starting at `offset` with 503 Service Unavailable
+    pub fn fail_page(&self, offset: usize) {
+        self.state.lock().unwrap().failing_offset = Some(offset);
+    }
+
+    // Creates `object` while a client is paging through `endpoint`
+    pub fn insert_while_listing(&self, endpoint: &str, object: Value) {
+        self.state
+            .lock()
+            .unwrap()
+            .arriving
+            .entry(endpoint.to_string())
+            .or_default()
+            .push(object);
+    }
+
+    // What NetBox holds at `endpoint`, without related objects nested
+    pub fn objects(&self, endpoint: &str) -> Vec<Value> {
+        let state = self.state.lock().unwrap();
+        state.objects.get(endpoint).cloned().unwrap_or_default()
+    }
+
+    // Changes an object the way someone editing it in NetBox would, without a changelog entry
+    pub fn edit(&self, endpoint: &str, id: u64, changes: Value) {
+        let mut state = self.state.lock().unwrap();
+        let object = state
+            .objects
+            .get_mut(endpoint)
+            .and_then(|objects| objects.iter_mut().find(|o| o["id"] == json!(id)))
+            .and_then(Value::as_object_mut)
+            .unwrap();
+        object.extend(changes.as_object().cloned().unwrap_or_default());
+        object.insert("last_updated".into(), json!(now()));
+    }
+
+    // A library client for this NetBox, making up to `api_limit` requests at once
+    pub fn client(&self, api_limit: usize) -> ApiClient {
+        ApiClient::new(&NetBoxConfig {
+            api_url: format!("{}/netbox/api", self.url),
+            api_key: "netbox-token".into(),
+            api_limit,
+            ..Default::default()
+        })
+    }
+
+    pub fn clear_requests(&self) {
+        self.state.lock().unwrap().netbox_requests.clear();
+    }
+
+    // A config for the ingester pointing every service at this mock
+    pub fn config(&self, dir: &Path, extra: &str) -> PathBuf {
+        fs::create_dir_all(dir).unwrap();
+        let config = format!(
+            r#"
+[netbox]
+api_url = "{url}/netbox/api"
+api_key = "netbox-token"
+# one device at a time, devices sharing a new role or tag would race to create it
+api_limit = 1
+
+[azure]
+client_id = "client"
+client_secret = "secret"
+tenant_id = "tenant"
+url = "{url}/graph"
+login_url = "{url}/login"
+
+[fortigate]
+url = "{url}/fortigate"
+token = "fortigate-token"
+ca_cert = "{fixtures}/FortiGate.crt"
+
+[nagiosxi]
+url = "{url}/nagios"
+api_key = "nagios-key"
+
+[naming]
+strip_domains = ["example.com"]
+
+[report]
+json = "{dir}/report.json"
+
+[logging]
+level = "warn"
+
+{extra}
+"#,
+            url = self.url,
+            fixtures = FIXTURES,
+            dir = dir.display(),
+        );
+        let path = dir.join("config.toml");
+        fs::write(&path, config).unwrap();
+        path
+    }
+}
+
+// Runs the ingester binary, whether it succeeds or not
+pub async fn output(config: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_netbox_ingester"))
+        .arg("--config")
+        .arg(config)
+        .args(args)
+        .env_remove("RUST_LOG")
+        .output()
+        .await
+        .unwrap()
+}
+
+// Runs the ingester binary, panicking with its output when it fails
+pub async fn run(config: &Path, args: &[&str]) -> Output {
+    let output = output(config, args).await;
+    assert!(
+        output.status.success(),
+        "ingester failed:\n{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    output
+}
+
+// The report the last run wrote to `dir`, see `Mock::config`
+pub fn report(dir: &Path) -> Value {
+    serde_json::from_str(&fs::read_to_string(dir.join("report.json")).unwrap()).unwrap()
+}
+
+// Id of the last run writing its report to `dir`
+pub fn run_id(dir: &Path) -> String {
+    report(dir)["run_id"].as_str().unwrap().to_string()
+}
+
+// Device `pc<i>` with the serial `SN<i>`, e.g. pc01 and SN1
+pub fn device(i: usize) -> Value {
+    json!({ "name": format!("pc{:02}", i), "serial": format!("SN{}", i), "status": "active" })
+}
+
+// Scratch directory of one test, emptied first
+pub fn test_dir(name: &str) -> PathBuf {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join(name);
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+fn fixture(name: &str) -> Value {
+    let text = fs::read_to_string(Path::new(FIXTURES).join(name)).unwrap();
+    serde_json::from_str(&text).unwrap()
+}
+
+async fn handle(
+    State(state): State<Arc<Mutex<MockState>>>,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let path = uri.path();
+    let mut body: Option<Value> = serde_json::from_slice(&body).ok();
+    let message = take_message(&mut body);
+    let mut state = state.lock().unwrap();
+
+    if let Some(path) = path.strip_prefix("/netbox") {
+        state.netbox_requests.push(Request {
+            method: method.to_string(),
+            path: path.to_string(),
+            query: uri.query().map(String::from),
+            body: body.clone(),
+            message: message.clone(),
+            request_id: headers
+                .get("X-Request-ID")
+                .and_then(|v| v.to_str().ok())
+                .map(String::from),
+        });
+        if state.rejected_method.as_ref() == Some(&method) {
+            return (StatusCode::INTERNAL_SERVER_ERROR, "mock failure").into_response();
+        }
+        return netbox(&mut state, &method, path, uri.query(), body, message);
+    }
+
+    match (&method, path) {
+        (&Method::POST, "/login/tenant/oauth2/v2.0/token") => Json(json!({
+            "token_type": "Bearer",
+            "expires_in": 3599,
+            "access_token": "graph-token"
+        }))
+        .into_response(),
+        (&Method::GET, "/graph/deviceManagement/managedDevices") => {
+            Json(fixture("azure_devices.json")).into_response()
+        }
+        (&Method::GET, "/graph/users") => Json(fixture("azure_users.json")).into_response(),
+        (&Method::GET, "/fortigate/monitor/user/device/query") => match state.fortigate_status {
+            Some(status) => (status, "mock failure").into_response(),
+            None => Json(fixture("fortigate_devices.json")).into_response(),
+        },
+        (&Method::GET, "/nagios/objects/hoststatus") => {
+            Json(fixture("nagiosxi_hosts.json")).into_response()
+        }
+        _ => (
+            StatusCode::NOT_FOUND,
+            format!("no mock for {} {}", method, path),
+        )
+            .into_response(),
+    }
+}
+
+// Just enough of NetBox: list with exact match filters, `q` and limit/offset
+// paging, get, create, update, replace and (bulk) delete, with related objects
+// nested the way NetBox returns them. Writes land in `core/object-changes`.
+fn netbox(
+    state: &mut MockState,
+    method: &Method,
+    path: &str,
+    query: Option<&str>,
+    body: Option<Value>,
+    message: Option<String>,
+) -> Response {
+    let path = path.trim_start_matches("/api/").trim_end_matches('/');
+    let (endpoint, id) = match path.rsplit_once('/') {
+        Some((endpoint, id)) if id.parse::<u64>().is_ok() => (endpoint, id.parse::<u64>().ok()),
+        _ => (path, None),
+    };
+
+    match (method, id) {
+        (&Method::GET, None) => {
+            let params: Vec<(String, String)> =
+                url::form_urlencoded::parse(query.unwrap_or_default().as_bytes())
+                    .into_owned()
+                    .collect();
+            let param = |name: &str| {
+                params
+                    .iter()
+                    .find(|(k, _)| k == name)
+                    .and_then(|(_, v)| v.parse::<usize>().ok())
+            };
+            let matches = |object: &Value| {
+                params
+                    .iter()
+                    .filter(|(k, _)| {
+                        !["limit", "offset", "brief", "fields", "ordering"].contains(&k.as_str())
+                    })
+                    .all(|(k, v)| match &object[k] {
+                        _ if k == "q" => object.as_object().unwrap().values().any(|field| {
+                            field
+                                .as_str()
+                                .is_some_and(|s| s.to_lowercase().contains(&v.to_lowercase()))
+                        }),
+                        Value::String(s) => s == v,
+                        other => serde_json::from_str::<Value>(v).is_ok_and(|v| &v == other),
+                    })
+            };
+            let all: Vec<Value> = state
+                .objects
+                .get(endpoint)
+                .cloned()
+                .unwrap_or_default()
+                .into_iter()
+                .filter(|o| matches(o))
+                .collect();
+
+            let offset = param("offset").unwrap_or(0);
+            if state.failing_offset == Some(offset) {
+                return (StatusCode::SERVICE_UNAVAILABLE, "mock failure").into_response();
+            }
+            let limit = param("limit")
+                .or(state.page_size)
+                .unwrap_or(all.len())
+                .max(1);
+            let next = (offset + limit < all.len()).then(|| {
+                let mut next = url::form_urlencoded::Serializer::new(String::new());
+                for (k, v) in params.iter().filter(|(k, _)| k != "offset" && k != "limit") {
+                    next.append_pair(k, v);
+                }
+                next.append_pair("limit", &limit.to_string());
+                next.append_pair("offset", &(offset + limit).to_string());
+                format!("{}/netbox/api/{}/?{}", state.url, endpoint, next.finish())
+            });
+            let results: Vec<Value> = all
+                .iter()
+                .skip(offset)
+                .take(limit)
+                .map(|o| nest(state, endpoint, o))
+                .collect();
+            if offset == 0 {
+                for object in state.arriving.remove(endpoint).unwrap_or_default() {
+                    insert(state, endpoint, object);
+                }
+            }
+            Json(json!({ "count": all.len(), "next": next, "previous": null, "results": results }))
+                .into_response()
+        }
+        (&Method::POST, None) => {
+            let Some(Value::Object(mut object)) = body else {
+                return (StatusCode::BAD_REQUEST, "expected an object").into_response();
+            };
+            object.insert("last_updated".into(), json!(now()));
+            let object = insert(state, endpoint, Value::Object(object));
+            log_change(state, endpoint, "create", None, Some(&object), message);
+            (StatusCode::CREATED, Json(nest(state, endpoint, &object))).into_response()
+        }
+        (&Method::GET, Some(id)) => {
+            let objects = state.objects.get(endpoint).cloned().unwrap_or_default();
+            match objects.iter().find(|o| o["id"] == json!(id)) {
+                Some(object) => Json(nest(state, endpoint, object)).into_response(),
+                None => StatusCode::NOT_FOUND.into_response(),
+            }
+        }
+        (&Method::PUT, Some(id)) => {
+            let Some(Value::Object(mut replacement)) = body else {
+                return (StatusCode::BAD_REQUEST, "expected an object").into_response();
+            };
+            replacement.insert("id".into(), json!(id));
+            replacement.insert("last_updated".into(), json!(now()));
+            let objects = state.objects.entry(endpoint.to_string()).or_default();
+            let Some(object) = objects.iter_mut().find(|o| o["id"] == json!(id)) else {
+                return StatusCode::NOT_FOUND.into_response();
+            };
+            let before = std::mem::replace(object, Value::Object(replacement));
+            let object = object.clone();
+            log_change(
+                state,
+                endpoint,
+                "update",
+                Some(&before),
+                Some(&object),
+                message,
+            );
+            Json(nest(state, endpoint, &object)).into_response()
+        }
+        (&Method::DELETE, Some(id)) => match remove(state, endpoint, &[id], message) {
+            true => StatusCode::NO_CONTENT.into_response(),
+            false => StatusCode::NOT_FOUND.into_response(),
+        },
+        (&Method::DELETE, None) => {
+            let ids: Option<Vec<u64>> = body
+                .as_ref()
+                .and_then(Value::as_array)
+                .map(|items| items.iter().filter_map(|i| i["id"].as_u64()).collect());
+            match ids {
+                Some(ids) if remove(state, endpoint, &ids, message) => {
+                    StatusCode::NO_CONTENT.into_response()
+                }
+                Some(_) => StatusCode::NOT_FOUND.into_response(),
+                None => (StatusCode::BAD_REQUEST, "expected a list of ids").into_response(),
+            }
+        }
+        (&Method::PATCH, Some(id)) => {
+            let Some(Value::Object(changes)) = body else {
+                return (StatusCode::BAD_REQUEST, "expected an object").into_response();
+            };
+            let objects = state.objects.entry(endpoint.to_string()).or_default();
+            let Some(Value::Object(object)) = objects.iter_mut().find(|o| o["id"] == json!(id))
+            else {
+                return StatusCode::NOT_FOUND.into_response();
+            };
+            let before = Value::Object(object.clone());
+            object.extend(changes);
+            object.insert("last_updated".into(), json!(now()));
+            let object = Value::Object(object.clone());
+            log_change(
+                state,
+                endpoint,
+                "update",
+                Some(&before),
+                Some(&object),
+                message,
+            );
+            Json(nest(state, endpoint, &object)).into_response()
+        }
+        _ => StatusCode::METHOD_NOT_ALLOWED.into_response(),
+    }
+}
+
+// Removes the objects `ids`, all of them or, when one is missing, none
+fn remove(state: &mut MockState, endpoint: &str, ids: &[u64], message: Option<String>) -> bool {
+    let objects = state.objects.entry(endpoint.to_string()).or_default();
+    let (removed, kept): (Vec<Value>, Vec<Value>) = objects
+        .drain(..)
+        .partition(|o| ids.iter().any(|id| o["id"] == json!(id)));
+    if removed.len() != ids.len() {
+        objects.extend(removed);
+        objects.sort_by_key(|o| o["id"].as_u64());
+        return false;
+    }
+    *objects = kept;
+    for object in &removed {
+        log_change(
+            state,
+            endpoint,
+            "delete",
+            Some(object),
+            None,
+            message.clone(),
+        );
+    }
+    true
+}
+
+fn now() -> String {
+    chrono::Utc::now().to_rfc3339()
+}
+
+// The changelog message of a write, which NetBox doesn't store on the object
+fn take_message(body: &mut Option<Value>) -> Option<String> {
+    let objects: Vec<&mut Map<String, Value>> = match body {
+        Some(Value::Object(object)) => vec![object],
+        Some(Value::Array(items)) => items.iter_mut().filter_map(Value::as_object_mut).collect(),
+        _ => Vec::new(),
+    };
+    let messages: Vec<Value> = objects
+        .into_iter()
+        .filter_map(|o| o.remove("changelog_message"))
+        .collect();
+    messages.first().and_then(Value::as_str).map(String::from)
+}
+
+fn log_change(
+    state: &mut MockState,
+    endpoint: &str,
+    action: &str,
+    before: Option<&Value>,
+    after: Option<&Value>,
+    message: Option<String>,
+) {
+    let object = after.or(before).unwrap();
+    let object_type = OBJECT_TYPES
+        .iter()
+        .find(|(_, e)| *e == endpoint)
+        .map(|(t, _)| *t)
+        .unwrap_or(endpoint);
+    let repr = ["name", "slug", "address"]
+        .iter()
+        .find_map(|field| object[field].as_str())
+        .unwrap_or_default();
+    let changes = state
+        .objects
+        .entry("core/object-changes".to_string())
+        .or_default();
+    let id = changes.len() + 1;
+    changes.push(json!({
+        "id": id,
+        "time": now(),
+        "action": { "value": action },
+        "changed_object_type": object_type,
+        "changed_object_id": object["id"],
+        "object_repr": repr,
+        "message": message,
+        "prechange_data": before,
+        "postchange_data": after,
+    }));
+}
+
+// Stores `object` under the next id and returns it with that id
+fn insert(state: &mut MockState, endpoint: &str, mut object: Value) -> Value {
+    state.next_id += 1;
+    object["id"] = json!(state.next_id);
+    state
+        .objects
+        .entry(endpoint.to_string())
+        .or_default()
+        .push(object.clone());
+    object
+}
+
+fn nest(state: &MockState, endpoint: &str, object: &Value) -> Value {
+    if endpoint != "dcim/devices" {
+        return object.clone();
+    }
+    let lookup = |endpoint: &str, id: &Value| -> Value {
+        state
+            .objects
+            .get(endpoint)
+            .and_then(|objects| objects.iter().find(|o| &o["id"] == id))
+            .cloned()
+            .unwrap_or(Value::Null)
+    };
+    let mut nested: Map<String, Value> = object.as_object().cloned().unwrap_or_default();
+    for (field, related) in [
+        ("device_type", "dcim/device-types"),
+        ("role", "dcim/device-roles"),
+        ("site", "dcim/sites"),
+        ("platform", "dcim/platforms"),
+    ] {
+        if let Some(id) = object.get(field).filter(|v| v.is_number()) {
+            nested.insert(field.into(), lookup(related, id));
+        }
+    }
+    if let Some(tags) = object.get("tags").and_then(Value::as_array) {
+        let tags = tags.iter().map(|id| lookup("extras/tags", id)).collect();
+        nested.insert("tags".into(), Value::Array(tags));
+    }
+    if let Some(status) = object.get("status").and_then(Value::as_str) {
+        nested.insert("status".into(), json!({ "value": status }));
+    }
+    Value::Object(nested)
+}
@@ -0,0 +1,106 @@
+mod common;
+
+use common::Mock;
+use netbox_ingester::{netbox::models::Device, NetBoxModel};
+use serde_json::json;
+
+#[tokio::test]
+async fn get_by_id_returns_the_object_or_none() {
+    let mock = Mock::start().await;
+    let id = mock.insert_devices(1)[0] as u32;
+    let api = mock.client(1);
+
+    let device = Device::get_by_id(&api, id).await.unwrap().unwrap();
+    let missing = Device::get_by_id(&api, id + 1).await.unwrap();
+
+    assert_eq!(device.name, "pc01");
+    assert_eq!(device.serial.as_deref(), Some("SN1"));
+    assert!(missing.is_none());
+    assert_eq!(
+        mock.request_log()[0].path,
+        format!("/api/dcim/devices/{}/", id)
+    );
+}
+
+#[tokio::test]
+async fn update_sends_only_the_changes() {
+    let mock = Mock::start().await;
+    let id = mock.insert_devices(1)[0] as u32;
+    let api = mock.client(1);
+    let device = Device::get_by_id(&api, id).await.unwrap().unwrap();
+    mock.clear_requests();
+
+    let updated = device
+        .update(&api, &json!({ "serial": "SN2" }))
+        .await
+        .unwrap();
+
+    assert_eq!(updated.serial.as_deref(), Some("SN2"));
+    assert_eq!(updated.name, "pc01");
+    assert_eq!(
+        mock.bodies("PATCH", &format!("/api/dcim/devices/{}/", id)),
+        vec![json!({ "serial": "SN2" })]
+    );
+}
+
+#[tokio::test]
+async fn replace_puts_the_whole_object() {
+    let mock = Mock::start().await;
+    let id = mock.insert_devices(1)[0] as u32;
+    let api = mock.client(1);
+    let mut device = Device::get_by_id(&api, id).await.unwrap().unwrap();
+    device.name = "pc01-renamed".into();
+    device.serial = None;
+
+    let replaced = device.replace(&api).await.unwrap();
+
+    assert_eq!(replaced.name, "pc01-renamed");
+    assert_eq!(replaced.serial, None);
+    assert_eq!(
+        mock.netbox_requests().last().unwrap(),
+        &format!("PUT /api/dcim/devices/{}/", id)
+    );
+}
+
+#[tokio::test]
+async fn delete_removes_one_object_and_bulk_delete_several() {
+    let mock = Mock::start().await;
+    let ids = mock.insert_devices(3);
+    let (first, second, third) = (ids[0] as u32, ids[1] as u32, ids[2] as u32);
+    let api = mock.client(1);
+    let device = Device::get_by_id(&api, first).await.unwrap().unwrap();
+
+    device.delete(&api).await.unwrap();
+    api.bulk_delete(Device::get_endpoint(), &[second, third])
+        .await
+        .unwrap();
+
+    for id in [first, second, third] {
+        assert!(Device::get_by_id(&api, id).await.unwrap().is_none());
+    }
+    assert_eq!(
+        mock.bodies("DELETE", "/api/dcim/devices/"),
+        vec![json!([{ "id": second }, { "id": third }])]
+    );
+    let error = device.delete(&api).await.unwrap_err();
+    assert!(error.to_string().contains("404"), "{}", error);
+}
+
+#[tokio::test]
+async fn objects_without_id_are_not_sent() {
+    let mock = Mock::start().await;
+    let id = mock.insert_devices(1)[0] as u32;
+    let api = mock.client(1);
+    let mut device = Device::get_by_id(&api, id).await.unwrap().unwrap();
+    device.id = None;
+    mock.clear_requests();
+
+    let error = device
+        .update(&api, &json!({ "serial": "SN2" }))
+        .await
+        .unwrap_err();
+    assert!(device.delete(&api).await.is_err());
+
+    assert_eq!(error.to_string(), "dcim/devices `pc01` has no id");
+    assert!(mock.request_log().is_empty());
+}
@@ -0,0 +1,151 @@
+mod common;
+
+use common::{report, run, test_dir, Mock};
+use serde_json::{json, Value};
+
+// JAKOB-DESKTOP as someone set it up by hand before the ingester saw it:
+// their own site and role, an outdated serial and status, plus `tags`.
+// Returns the device's id and the tags' ids.
+fn seed(mock: &Mock, tags: &[(&str, &str)]) -> (u64, Vec<u64>) {
+    let site = mock.insert(
+        "dcim/sites",
+        json!({ "name": "Server room", "slug": "server-room" }),
+    );
+    let role = mock.insert(
+        "dcim/device-roles",
+        json!({ "name": "Workstation", "slug": "workstation" }),
+    );
+    let tags: Vec<u64> = tags
+        .iter()
+        .map(|(name, slug)| mock.insert("extras/tags", json!({ "name": name, "slug": slug })))
+        .collect();
+    let id = mock.insert(
+        "dcim/devices",
+        json!({
+            "name": "JAKOB-DESKTOP",
+            "serial": "OLD",
+            "status": "planned",
+            "site": site,
+            "role": role,
+            "tags": tags,
+        }),
+    );
+    (id, tags)
+}
+
+fn keys(body: &Value) -> Vec<&str> {
+    body.as_object()
+        .unwrap()
+        .keys()
+        .map(String::as_str)
+        .collect()
+}
+
+#[tokio::test]
+async fn curated_devices_only_get_their_curated_fields() {
+    let mock = Mock::start().await;
+    let (id, _) = seed(&mock, &[]);
+    let dir = test_dir("ownership_curated");
+    let config = mock.config(&dir, "");
+
+    run(&config, &["sync", "--source", "azure"]).await;
+
+    let patches = mock.bodies("PATCH", &format!("/api/dcim/devices/{}/", id));
+    assert_eq!(patches.len(), 1);
+    assert_eq!(keys(&patches[0]), vec!["serial", "status", "tags"]);
+    assert_eq!(patches[0]["serial"], "5CD1234XYZ");
+    // last seen by Intune long ago
+    assert_eq!(patches[0]["status"], "offline");
+}
+
+#[tokio::test]
+async fn curated_fields_are_configurable() {
+    let mock = Mock::start().await;
+    let (id, _) = seed(&mock, &[]);
+    let dir = test_dir("ownership_curated_fields");
+    let config = mock.config(&dir, "[ownership]\ncurated_fields = [\"serial\", \"site\"]");
+
+    run(&config, &["sync", "--source", "azure"]).await;
+
+    let patches = mock.bodies("PATCH", &format!("/api/dcim/devices/{}/", id));
+    assert_eq!(keys(&patches[0]), vec!["serial", "site"]);
+}
+
+#[tokio::test]
+async fn managed_devices_get_every_field() {
+    let mock = Mock::start().await;
+    let (id, tags) = seed(&mock, &[("managed-by-ingester", "managed-by-ingester")]);
+    let dir = test_dir("ownership_managed");
+    let config = mock.config(&dir, "");
+
+    run(&config, &["sync", "--source", "azure"]).await;
+
+    let patches = mock.bodies("PATCH", &format!("/api/dcim/devices/{}/", id));
+    assert_eq!(
+        keys(&patches[0]),
+        vec![
+            "device_type",
+            "name",
+            "platform",
+            "role",
+            "serial",
+            "site",
+            "status",
+            "tags"
+        ]
+    );
+    // the managed tag stays on
+    assert!(patches[0]["tags"]
+        .as_array()
+        .unwrap()
+        .contains(&json!(tags[0])));
+}
+
+#[tokio::test]
+async fn locked_devices_are_left_alone() {
+    let mock = Mock::start().await;
+    seed(
+        &mock,
+        &[
+            ("managed-by-ingester", "managed-by-ingester"),
+            ("Locked", "locked"),
+        ],
+    );
+    let dir = test_dir("ownership_locked");
+    let config = mock.config(&dir, "");
+
+    let plan = run(&config, &["--dry-run", "sync", "--source", "azure"]).await;
+    run(&config, &["sync", "--source", "azure"]).await;
+
+    let stdout = String::from_utf8_lossy(&plan.stdout);
+    assert!(stdout.contains("locked    jakob-desktop"), "{}", stdout);
+    assert!(mock
+        .netbox_requests()
+        .iter()
+        .all(|r| !r.starts_with("PATCH")));
+    assert_eq!(
+        report(&dir)["skipped"]["jakob-desktop"]["reason"],
+        "locked by tag `locked`"
+    );
+}
+
+#[tokio::test]
+async fn created_devices_carry_the_managed_tag() {
+    let mock = Mock::start().await;
+    let dir = test_dir("ownership_created");
+    let config = mock.config(&dir, "[ownership]\nmanaged_tag = \"Ingester\"");
+
+    run(&config, &["sync", "--source", "azure"]).await;
+
+    let tag = mock
+        .objects("extras/tags")
+        .into_iter()
+        .find(|t| t["slug"] == "ingester")
+        .unwrap();
+    let devices = mock.objects("dcim/devices");
+    assert_eq!(devices.len(), 2);
+    for device in devices {
+        let tags = device["tags"].as_array().unwrap();
+        assert!(tags.contains(&tag["id"]), "{}", device);
+    }
+}
@@ -0,0 +1,131 @@
+mod common;
+
+use common::{device, Mock};
+use netbox_ingester::netbox::models::Device;
+use serde_json::json;
+
+fn names(devices: &[Device]) -> Vec<String> {
+    devices.iter().map(|d| d.name.clone()).collect()
+}
+
+#[tokio::test]
+async fn get_fetches_the_remaining_pages_by_offset() {
+    let mock = Mock::start().await;
+    mock.page_size(2);
+    mock.insert_devices(7);
+
+    let devices: Vec<Device> = mock.client(4).get("dcim/devices/", None).await.unwrap();
+
+    // in NetBox's order, whichever page answered first
+    assert_eq!(
+        names(&devices),
+        vec!["pc01", "pc02", "pc03", "pc04", "pc05", "pc06", "pc07"]
+    );
+    let mut queries: Vec<String> = mock
+        .request_log()
+        .into_iter()
+        .filter_map(|r| r.query)
+        .collect();
+    queries.sort();
+    assert_eq!(
+        queries,
+        vec![
+            "ordering=id",
+            "ordering=id&limit=2&offset=2",
+            "ordering=id&limit=2&offset=4",
+            "ordering=id&limit=2&offset=6",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn get_keeps_the_filters_of_the_endpoint() {
+    let mock = Mock::start().await;
+    mock.page_size(2);
+    for (i, id) in mock.insert_devices(7).into_iter().enumerate() {
+        if (i + 1) % 3 == 0 {
+            mock.edit("dcim/devices", id, json!({ "status": "offline" }));
+        }
+    }
+
+    let devices: Vec<Device> = mock
+        .client(4)
+        .get("dcim/devices/?status=active", None)
+        .await
+        .unwrap();
+
+    assert_eq!(
+        names(&devices),
+        vec!["pc01", "pc02", "pc04", "pc05", "pc07"]
+    );
+    let mut queries: Vec<String> = mock
+        .request_log()
+        .into_iter()
+        .filter_map(|r| r.query)
+        .collect();
+    queries.sort();
+    assert_eq!(
+        queries,
+        vec![
+            "status=active&ordering=id",
+            "status=active&ordering=id&limit=2&offset=2",
+            "status=active&ordering=id&limit=2&offset=4",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn get_keeps_the_ordering_of_the_endpoint() {
+    let mock = Mock::start().await;
+    mock.insert_devices(3);
+
+    let devices: Vec<Device> = mock
+        .client(4)
+        .get("dcim/devices/?ordering=-name", None)
+        .await
+        .unwrap();
+
+    assert_eq!(devices.len(), 3);
+    let queries: Vec<Option<String>> = mock.request_log().into_iter().map(|r| r.query).collect();
+    assert_eq!(queries, vec![Some("ordering=-name".to_string())]);
+}
+
+#[tokio::test]
+async fn get_fails_when_a_page_fails() {
+    let mock = Mock::start().await;
+    mock.page_size(2);
+    mock.insert_devices(7);
+    mock.fail_page(2);
+
+    let result = mock.client(4).get::<Device>("dcim/devices/", None).await;
+
+    let error = result.unwrap_err().to_string();
+    assert!(error.contains("503"), "{}", error);
+}
+
+#[tokio::test]
+async fn get_reads_objects_created_while_paging() {
+    let mock = Mock::start().await;
+    mock.page_size(2);
+    mock.insert_devices(4);
+    mock.insert_while_listing("dcim/devices", device(5));
+
+    let devices: Vec<Device> = mock.client(4).get("dcim/devices/", None).await.unwrap();
+
+    assert_eq!(
+        names(&devices),
+        vec!["pc01", "pc02", "pc03", "pc04", "pc05"]
+    );
+    assert_eq!(mock.request_log().len(), 3);
+}
+
+#[tokio::test]
+async fn get_reads_a_single_page_once() {
+    let mock = Mock::start().await;
+    mock.insert_devices(3);
+
+    let devices: Vec<Device> = mock.client(4).get("dcim/devices/", None).await.unwrap();
+
+    assert_eq!(names(&devices), vec!["pc01", "pc02", "pc03"]);
+    assert_eq!(mock.request_log().len(), 1);
+}
@@ -0,0 +1,115 @@
+mod common;
+
+use chrono::{TimeZone, Utc};
+use common::Mock;
+use futures::StreamExt;
+use netbox_ingester::{
+    config::NetBoxConfig,
+    netbox::{models::Device, query::Query},
+    ApiClient,
+};
+
+#[test]
+fn query_url_carries_every_filter() {
+    let url = Query::<Device>::new()
+        .name("JAKOB DESKTOP")
+        .serial("5CD1234XYZ")
+        .mac_address("3C:22:FB:00:00:01")
+        .tag("aad")
+        .site_id(3)
+        .search("latitude")
+        .updated_since(Utc.with_ymd_and_hms(2024, 1, 15, 9, 30, 0).unwrap())
+        .order_by("-last_updated")
+        .brief()
+        .fields(&["id", "name"])
+        .page_size(100)
+        .page_size(50)
+        .url("https://netbox.example/api/")
+        .unwrap();
+
+    assert_eq!(
+        url.as_str(),
+        "https://netbox.example/api/dcim/devices/?name=JAKOB+DESKTOP&serial=5CD1234XYZ\
+         &mac_address=3C%3A22%3AFB%3A00%3A00%3A01&tag=aad&site_id=3&q=latitude\
+         &last_updated__gte=2024-01-15T09%3A30%3A00Z&ordering=-last_updated&brief=true\
+         &fields=id%2Cname&limit=50"
+    );
+}
+
+#[tokio::test]
+async fn query_returns_only_matching_objects() {
+    let mock = Mock::start().await;
+    mock.insert_devices(3);
+
+    let devices = mock
+        .client(1)
+        .query(&Query::<Device>::new().serial("SN2"))
+        .await
+        .unwrap();
+
+    assert_eq!(devices.len(), 1);
+    assert_eq!(devices[0].name, "pc02");
+    assert_eq!(devices[0].id, Some(2));
+    let log = mock.request_log();
+    assert_eq!(log.len(), 1);
+    assert_eq!(log[0].path, "/api/dcim/devices/");
+    assert_eq!(log[0].query.as_deref(), Some("serial=SN2"));
+}
+
+#[tokio::test]
+async fn query_pages_yields_each_page() {
+    let mock = Mock::start().await;
+    mock.insert_devices(5);
+    let api = mock.client(1);
+    let query = Query::<Device>::new().page_size(2);
+
+    let sizes: Vec<usize> = api
+        .query_pages(&query)
+        .map(|page| page.unwrap().len())
+        .collect()
+        .await;
+
+    assert_eq!(sizes, vec![2, 2, 1]);
+    let queries: Vec<Option<String>> = mock.request_log().into_iter().map(|r| r.query).collect();
+    assert_eq!(
+        queries,
+        vec![
+            Some("limit=2".to_string()),
+            Some("limit=2&offset=2".to_string()),
+            Some("limit=2&offset=4".to_string()),
+        ]
+    );
+    assert_eq!(api.query(&query).await.unwrap().len(), 5);
+}
+
+#[tokio::test]
+async fn first_requests_a_single_object() {
+    let mock = Mock::start().await;
+    mock.insert_devices(3);
+    let api = mock.client(1);
+
+    let device = api.first(&Query::<Device>::new()).await.unwrap();
+    let missing = api
+        .first(&Query::<Device>::new().name("nope"))
+        .await
+        .unwrap();
+
+    assert_eq!(device.map(|d| d.name), Some("pc01".to_string()));
+    assert!(missing.is_none());
+    let log = mock.request_log();
+    assert_eq!(log[0].query.as_deref(), Some("limit=1"));
+}
+
+#[tokio::test]
+async fn query_fails_on_error_status() {
+    let mock = Mock::start().await;
+    let api = ApiClient::new(&NetBoxConfig {
+        api_url: format!("{}/nowhere/api", mock.url),
+        api_key: "netbox-token".into(),
+        ..Default::default()
+    });
+
+    let result = api.query(&Query::<Device>::new()).await;
+
+    assert!(result.unwrap_err().to_string().contains("404"));
+}
@@ -0,0 +1,118 @@
+mod common;
+
+use common::{output, report, run, test_dir, Mock};
+use serde_json::json;
+
+// NetBox devices an earlier Azure sync created, none of them in the Azure fixture
+fn seed(mock: &Mock, names: &[(&str, bool)]) -> Vec<u64> {
+    let aad = mock.insert("extras/tags", json!({ "name": "AAD", "slug": "aad" }));
+    let protected = mock.insert(
+        "extras/tags",
+        json!({ "name": "Protected", "slug": "protected" }),
+    );
+    names
+        .iter()
+        .map(|(name, is_protected)| {
+            let tags = match is_protected {
+                true => json!([aad, protected]),
+                false => json!([aad]),
+            };
+            mock.insert(
+                "dcim/devices",
+                json!({ "name": name, "status": "active", "tags": tags }),
+            )
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn delete_removes_stale_devices_in_one_request() {
+    let mock = Mock::start().await;
+    let ids = seed(&mock, &[("old-laptop", false), ("old-tablet", false)]);
+    let dir = test_dir("reconcile_delete");
+    let config = mock.config(&dir, "");
+
+    run(&config, &["reconcile", "--source", "azure", "--delete"]).await;
+
+    assert_eq!(
+        mock.bodies("DELETE", "/api/dcim/devices/"),
+        vec![json!([{ "id": ids[0] }, { "id": ids[1] }])]
+    );
+    assert_eq!(report(&dir)["objects"]["dcim/devices"]["deleted"], 2);
+}
+
+#[tokio::test]
+async fn protected_devices_are_not_deleted() {
+    let mock = Mock::start().await;
+    let ids = seed(&mock, &[("old-laptop", false), ("old-server", true)]);
+    let dir = test_dir("reconcile_protected");
+    let config = mock.config(&dir, "[delete]\nprotect_tags = [\"Protected\"]");
+
+    let dry_run = run(
+        &config,
+        &["--dry-run", "reconcile", "--source", "azure", "--delete"],
+    )
+    .await;
+    run(&config, &["reconcile", "--source", "azure", "--delete"]).await;
+
+    let stdout = String::from_utf8_lossy(&dry_run.stdout);
+    assert!(stdout.contains("delete    old-laptop"), "{}", stdout);
+    assert!(stdout.contains("protected old-server"), "{}", stdout);
+    assert_eq!(
+        mock.bodies("DELETE", "/api/dcim/devices/"),
+        vec![json!([{ "id": ids[0] }])]
+    );
+    let report = report(&dir);
+    assert_eq!(report["objects"]["dcim/devices"]["deleted"], 1);
+    assert_eq!(
+        report["skipped"]["old-server"]["reason"],
+        "protected from deletion by tag"
+    );
+}
+
+#[tokio::test]
+async fn more_stale_devices_than_allowed_deletes_none() {
+    let mock = Mock::start().await;
+    seed(&mock, &[("old-laptop", false), ("old-tablet", false)]);
+    let dir = test_dir("reconcile_max_deletes");
+    let config = mock.config(&dir, "[delete]\nmax_per_run = 1");
+
+    let output = output(&config, &["reconcile", "--source", "azure", "--delete"]).await;
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("delete.max_per_run is 1"), "{}", stderr);
+    assert_eq!(
+        mock.netbox_requests()
+            .iter()
+            .filter(|l| l.starts_with("DELETE"))
+            .count(),
+        0
+    );
+}
+
+#[tokio::test]
+async fn locked_devices_are_never_stale() {
+    let mock = Mock::start().await;
+    let ids = seed(&mock, &[("old-laptop", false)]);
+    let aad = mock
+        .objects("extras/tags")
+        .into_iter()
+        .find(|t| t["slug"] == "aad")
+        .unwrap()["id"]
+        .clone();
+    let locked = mock.insert("extras/tags", json!({ "name": "Locked", "slug": "locked" }));
+    mock.insert(
+        "dcim/devices",
+        json!({ "name": "old-server", "status": "active", "tags": [aad, locked] }),
+    );
+    let dir = test_dir("reconcile_locked");
+    let config = mock.config(&dir, "");
+
+    run(&config, &["reconcile", "--source", "azure", "--delete"]).await;
+
+    assert_eq!(
+        mock.bodies("DELETE", "/api/dcim/devices/"),
+        vec![json!([{ "id": ids[0] }])]
+    );
+}
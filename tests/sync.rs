@@ -0,0 +1,309 @@
+mod common;
+
+use axum::http::{Method, StatusCode};
+use common::{output, report, run, test_dir, Mock};
+use serde_json::{json, Value};
+use std::fs;
+
+// The GETs every run starts with to fill the cache
+const PRELOAD: [&str; 9] = [
+    "GET /api/dcim/device-roles",
+    "GET /api/dcim/device-types",
+    "GET /api/dcim/devices/",
+    "GET /api/dcim/manufacturers",
+    "GET /api/dcim/platforms",
+    "GET /api/dcim/sites",
+    "GET /api/extras/tags",
+    "GET /api/tenancy/contacts",
+    "GET /api/virtualization/virtual-machines",
+];
+
+fn expected(writes: &[&str]) -> Vec<String> {
+    let mut lines: Vec<String> = PRELOAD
+        .iter()
+        .chain(writes)
+        .map(|s| s.to_string())
+        .collect();
+    lines.sort();
+    lines
+}
+
+#[tokio::test]
+async fn sync_creates_devices_and_their_components() {
+    let mock = Mock::start().await;
+    let dir = test_dir("sync_creates_devices");
+    let config = mock.config(&dir, "");
+
+    run(&config, &["sync", "--only", "devices,contacts"]).await;
+
+    assert_eq!(
+        mock.netbox_requests(),
+        expected(&[
+            "POST /api/dcim/device-roles/",
+            "POST /api/dcim/device-types/",
+            "POST /api/dcim/device-types/",
+            "POST /api/dcim/devices/",
+            "POST /api/dcim/devices/",
+            "POST /api/dcim/manufacturers/",
+            "POST /api/dcim/manufacturers/",
+            "POST /api/dcim/manufacturers/",
+            "POST /api/dcim/platforms/",
+            "POST /api/dcim/platforms/",
+            "POST /api/dcim/sites/",
+            "POST /api/extras/tags/",
+            "POST /api/extras/tags/",
+            "POST /api/extras/tags/",
+            "POST /api/extras/tags/",
+            "POST /api/tenancy/contacts/",
+        ])
+    );
+
+    let mut names: Vec<Value> = mock
+        .bodies("POST", "/api/dcim/devices/")
+        .iter()
+        .map(|d| d["name"].clone())
+        .collect();
+    names.sort_by_key(|n| n.to_string());
+    // trimmed and without the domain; the printer has no device type and is skipped
+    assert_eq!(names, vec![json!("JAKOB-DESKTOP"), json!("tos-vismacli08")]);
+
+    let mut manufacturers: Vec<Value> = mock
+        .bodies("POST", "/api/dcim/manufacturers/")
+        .iter()
+        .map(|m| m["name"].clone())
+        .collect();
+    manufacturers.sort_by_key(|n| n.to_string());
+    assert_eq!(
+        manufacturers,
+        vec![json!("Dell"), json!("Lenovo"), json!("Microsoft")]
+    );
+
+    let mut platforms: Vec<Value> = mock
+        .bodies("POST", "/api/dcim/platforms/")
+        .iter()
+        .map(|p| p["name"].clone())
+        .collect();
+    platforms.sort_by_key(|n| n.to_string());
+    assert_eq!(platforms, vec![json!("Windows 10"), json!("Windows 11")]);
+
+    let mut tags: Vec<Value> = mock
+        .bodies("POST", "/api/extras/tags/")
+        .iter()
+        .map(|t| t["name"].clone())
+        .collect();
+    tags.sort_by_key(|n| n.to_string());
+    assert_eq!(
+        tags,
+        vec![
+            json!("AAD"),
+            json!("FortiGate"),
+            json!("Reserved DHCP"),
+            json!("managed-by-ingester")
+        ]
+    );
+
+    let report = report(&dir);
+    assert_eq!(report["sources"]["azure"]["records"], 2);
+    assert_eq!(report["sources"]["fortigate"]["records"], 2);
+    assert_eq!(
+        report["sources"]["fortigate"]["filtered"]["locally_administered_mac"],
+        1
+    );
+    assert_eq!(report["sources"]["nagiosxi"]["records"], 2);
+    assert!(report["skipped"]["hp-001122334455"].is_object());
+}
+
+#[tokio::test]
+async fn second_sync_changes_nothing() {
+    let mock = Mock::start().await;
+    let dir = test_dir("second_sync_changes_nothing");
+    let config = mock.config(&dir, "");
+
+    run(&config, &["sync", "--only", "devices,contacts"]).await;
+    mock.clear_requests();
+    run(&config, &["sync", "--only", "devices,contacts"]).await;
+
+    assert_eq!(mock.netbox_requests(), expected(&[]));
+}
+
+#[tokio::test]
+async fn devices_named_before_domain_stripping_are_renamed() {
+    let mock = Mock::start().await;
+    let tag = mock.insert(
+        "extras/tags",
+        json!({ "name": "managed-by-ingester", "slug": "managed-by-ingester" }),
+    );
+    let id = mock.insert(
+        "dcim/devices",
+        json!({ "name": "tos-vismacli08.example.com", "status": "active", "tags": [tag] }),
+    );
+    let dir = test_dir("sync_fqdn_renamed");
+    let config = mock.config(&dir, "");
+
+    run(&config, &["sync", "--source", "azure"]).await;
+
+    let names: Vec<Value> = mock
+        .bodies("POST", "/api/dcim/devices/")
+        .iter()
+        .map(|d| d["name"].clone())
+        .collect();
+    assert_eq!(names, vec![json!("JAKOB-DESKTOP")]);
+    let patches = mock.bodies("PATCH", &format!("/api/dcim/devices/{}/", id));
+    assert_eq!(patches.len(), 1);
+    assert_eq!(patches[0]["name"], "tos-vismacli08");
+
+    // the renamed device is still reported, so it isn't stale
+    let output = run(&config, &["--dry-run", "reconcile", "--source", "azure"]).await;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("0 stale devices"), "{}", stdout);
+}
+
+#[tokio::test]
+async fn show_device_takes_the_name_a_source_reports() {
+    let mock = Mock::start().await;
+    let dir = test_dir("show_device_fqdn");
+    let config = mock.config(&dir, "");
+
+    let output = run(
+        &config,
+        &[
+            "--output",
+            "json",
+            "show-device",
+            "JAKOB-DESKTOP.example.com",
+        ],
+    )
+    .await;
+
+    let shown: Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(shown["consolidated"]["name"], "JAKOB-DESKTOP");
+    assert_eq!(shown["plan"]["action"], "create");
+    assert_eq!(mock.netbox_requests(), expected(&[]));
+}
+
+#[tokio::test]
+async fn dry_run_only_reads() {
+    let mock = Mock::start().await;
+    let dir = test_dir("dry_run_only_reads");
+    let config = mock.config(&dir, "");
+
+    let output = run(&config, &["--dry-run", "sync"]).await;
+
+    assert_eq!(mock.netbox_requests(), expected(&[]));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("create    jakob-desktop"), "{}", stdout);
+    assert!(stdout.contains("create    tos-vismacli08"), "{}", stdout);
+}
+
+#[tokio::test]
+async fn dry_run_refuses_contacts() {
+    let mock = Mock::start().await;
+    let dir = test_dir("dry_run_contacts");
+    let config = mock.config(&dir, "");
+
+    let output = output(
+        &config,
+        &["--dry-run", "sync", "--only", "devices,contacts"],
+    )
+    .await;
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("drop contacts from --only"), "{}", stderr);
+    assert!(mock.request_log().is_empty());
+}
+
+#[tokio::test]
+async fn failed_source_does_not_stop_the_run() {
+    let mock = Mock::start().await;
+    mock.fail_fortigate(StatusCode::INTERNAL_SERVER_ERROR);
+    let dir = test_dir("failed_source");
+    let config = mock.config(&dir, "");
+
+    run(&config, &["sync"]).await;
+
+    // AAD and the managed tag, no FortiGate or Reserved DHCP tag this time
+    assert_eq!(
+        mock.netbox_requests(),
+        expected(&[
+            "POST /api/dcim/device-roles/",
+            "POST /api/dcim/device-types/",
+            "POST /api/dcim/device-types/",
+            "POST /api/dcim/devices/",
+            "POST /api/dcim/devices/",
+            "POST /api/dcim/manufacturers/",
+            "POST /api/dcim/manufacturers/",
+            "POST /api/dcim/manufacturers/",
+            "POST /api/dcim/platforms/",
+            "POST /api/dcim/platforms/",
+            "POST /api/dcim/sites/",
+            "POST /api/extras/tags/",
+            "POST /api/extras/tags/",
+        ])
+    );
+    let report = report(&dir);
+    let error = report["sources"]["fortigate"]["error"].as_str().unwrap();
+    assert!(error.contains("500"), "{}", error);
+    assert_eq!(report["sources"]["azure"]["records"], 2);
+}
+
+#[tokio::test]
+async fn failed_run_keeps_the_last_success_in_the_textfile() {
+    let mock = Mock::start().await;
+    mock.fail_fortigate(StatusCode::INTERNAL_SERVER_ERROR);
+    let dir = test_dir("failed_run_textfile");
+    let textfile = dir.join("ingester.prom");
+    let config = mock.config(
+        &dir,
+        &format!("[metrics]\ntextfile = \"{}\"\n", textfile.display()),
+    );
+    fs::write(
+        &textfile,
+        "# TYPE ingester_last_success_timestamp_seconds gauge\n\
+         ingester_last_success_timestamp_seconds 1700000000\n",
+    )
+    .unwrap();
+
+    run(&config, &["sync"]).await;
+
+    let metrics = fs::read_to_string(&textfile).unwrap();
+    assert!(
+        metrics.contains("\ningester_last_success_timestamp_seconds 1700000000\n"),
+        "{}",
+        metrics
+    );
+}
+
+#[tokio::test]
+async fn run_with_failed_writes_keeps_the_last_success_in_the_textfile() {
+    let mock = Mock::start().await;
+    mock.reject(Method::POST);
+    let dir = test_dir("failed_writes_textfile");
+    let textfile = dir.join("ingester.prom");
+    let config = mock.config(
+        &dir,
+        &format!("[metrics]\ntextfile = \"{}\"\n", textfile.display()),
+    );
+    fs::write(
+        &textfile,
+        "# TYPE ingester_last_success_timestamp_seconds gauge\n\
+         ingester_last_success_timestamp_seconds 1700000000\n",
+    )
+    .unwrap();
+
+    run(&config, &["sync"]).await;
+
+    let report = report(&dir);
+    assert!(report["sources"]
+        .as_object()
+        .unwrap()
+        .values()
+        .all(|s| s["error"].is_null()));
+    assert!(!report["failures"].as_object().unwrap().is_empty());
+    let metrics = fs::read_to_string(&textfile).unwrap();
+    assert!(
+        metrics.contains("\ningester_last_success_timestamp_seconds 1700000000\n"),
+        "{}",
+        metrics
+    );
+}
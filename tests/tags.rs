@@ -0,0 +1,91 @@
+mod common;
+
+use common::{run, test_dir, Mock};
+use serde_json::json;
+
+const SOURCES: [&str; 5] = ["sync", "--source", "azure", "--source", "fortigate"];
+
+// tos-vismacli08 is only in Azure, so it no longer has a DHCP reservation
+fn seed(mock: &Mock) -> (u64, Vec<u64>) {
+    let tags: Vec<u64> = [
+        ("Printer room", "printer-room"),
+        ("Reserved DHCP", "reserved-dhcp"),
+        ("AAD", "aad"),
+        ("Nagios", "nagios"),
+    ]
+    .iter()
+    .map(|(name, slug)| mock.insert("extras/tags", json!({ "name": name, "slug": slug })))
+    .collect();
+    let id = mock.insert(
+        "dcim/devices",
+        json!({ "name": "tos-vismacli08", "tags": tags }),
+    );
+    (id, tags)
+}
+
+#[tokio::test]
+async fn derived_tags_are_dropped_and_others_kept() {
+    let mock = Mock::start().await;
+    let (id, tags) = seed(&mock);
+    let dir = test_dir("tags_reconciled");
+    let config = mock.config(&dir, "");
+
+    run(&config, &SOURCES).await;
+
+    let path = format!("/api/dcim/devices/{}/", id);
+    let patches = mock.bodies("PATCH", &path);
+    assert_eq!(patches.len(), 1);
+    let mut patched: Vec<u64> = patches[0]["tags"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|t| t.as_u64().unwrap())
+        .collect();
+    patched.sort_unstable();
+    // the manual tag, the tag derived again and one no fetched source decides on
+    assert_eq!(patched, vec![tags[0], tags[2], tags[3]]);
+
+    mock.clear_requests();
+    run(&config, &SOURCES).await;
+    assert!(mock.bodies("PATCH", &path).is_empty());
+}
+
+#[tokio::test]
+async fn new_tags_are_styled_from_config() {
+    let mock = Mock::start().await;
+    let dir = test_dir("tags_styled");
+    let config = mock.config(
+        &dir,
+        "[tags.\"Reserved DHCP\"]\ncolor = \"ff9800\"\ndescription = \"Has a DHCP reservation on the FortiGate\"",
+    );
+
+    run(&config, &SOURCES).await;
+
+    let tag = mock
+        .bodies("POST", "/api/extras/tags/")
+        .into_iter()
+        .find(|t| t["slug"] == "reserved-dhcp")
+        .unwrap();
+    assert_eq!(tag["color"], "ff9800");
+    assert_eq!(
+        tag["description"],
+        "Has a DHCP reservation on the FortiGate"
+    );
+}
+
+#[tokio::test]
+async fn tag_colors_are_validated() {
+    let mock = Mock::start().await;
+    let dir = test_dir("tags_invalid");
+    let config = mock.config(&dir, "[tags.AAD]\ncolor = \"orange\"");
+
+    let output = common::output(&config, &["validate-config"]).await;
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("tags.AAD.color: `orange` is not six hex digits"),
+        "{}",
+        stderr
+    );
+}
@@ -0,0 +1,178 @@
+mod common;
+
+use common::{output, run, run_id, test_dir, Mock};
+use serde_json::{json, Value};
+use std::path::Path;
+
+// JAKOB-DESKTOP as it was before the ingester took it over
+fn seed(mock: &Mock) -> u64 {
+    let tag = mock.insert(
+        "extras/tags",
+        json!({ "name": "managed-by-ingester", "slug": "managed-by-ingester" }),
+    );
+    mock.insert(
+        "dcim/devices",
+        json!({ "name": "JAKOB-DESKTOP", "serial": "OLD", "status": "planned", "tags": [tag] }),
+    )
+}
+
+// Syncs Azure once and returns the run's id
+async fn sync(mock: &Mock, dir: &Path, extra: &str) -> (std::path::PathBuf, String) {
+    let config = mock.config(dir, extra);
+    run(&config, &["sync", "--source", "azure"]).await;
+    let run_id = run_id(dir);
+    mock.clear_requests();
+    (config, run_id)
+}
+
+fn device(mock: &Mock, name: &str) -> Option<Value> {
+    mock.objects("dcim/devices")
+        .into_iter()
+        .find(|d| d["name"] == name)
+}
+
+#[tokio::test]
+async fn undo_only_lists_the_steps_by_default() {
+    let mock = Mock::start().await;
+    seed(&mock);
+    let dir = test_dir("undo_listed");
+    let (config, run_id) = sync(&mock, &dir, "").await;
+
+    let output = run(&config, &["undo", &run_id]).await;
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let line = |prefix: &str| stdout.lines().find(|l| l.starts_with(prefix)).unwrap();
+    assert!(
+        line("delete   dcim.device ").contains("tos-vismacli08"),
+        "{}",
+        stdout
+    );
+    let restore = line("restore  dcim.device ");
+    assert!(restore.contains("JAKOB-DESKTOP"), "{}", stdout);
+    assert!(restore.ends_with("serial,status,tags"), "{}", stdout);
+    assert!(stdout.contains(&format!("pass --apply to revert run {}", run_id)));
+    assert!(mock.request_log().iter().all(|r| r.method == "GET"));
+}
+
+#[tokio::test]
+async fn undo_apply_reverts_the_run() {
+    let mock = Mock::start().await;
+    let id = seed(&mock);
+    let dir = test_dir("undo_applied");
+    let (config, undone) = sync(&mock, &dir, "").await;
+
+    run(&config, &["undo", &undone, "--apply"]).await;
+
+    let devices = mock.objects("dcim/devices");
+    assert_eq!(devices.len(), 1);
+    assert_eq!(devices[0]["id"], id);
+    assert_eq!(devices[0]["serial"], "OLD");
+    assert_eq!(devices[0]["status"], "planned");
+    let tags: Vec<Value> = mock
+        .objects("extras/tags")
+        .iter()
+        .map(|t| t["slug"].clone())
+        .collect();
+    assert_eq!(tags, vec![json!("managed-by-ingester")]);
+    assert!(mock.objects("dcim/manufacturers").is_empty());
+    assert!(mock.objects("dcim/device-types").is_empty());
+
+    // the undo is a run of its own
+    let undo_id = run_id(&dir);
+    assert_ne!(undo_id, undone);
+    for write in mock.request_log().iter().filter(|r| r.method != "GET") {
+        assert_eq!(write.request_id.as_deref(), Some(undo_id.as_str()));
+    }
+}
+
+#[tokio::test]
+async fn objects_changed_since_the_run_are_left_alone() {
+    let mock = Mock::start().await;
+    seed(&mock);
+    let dir = test_dir("undo_changed_since");
+    let (config, run_id) = sync(&mock, &dir, "").await;
+    let created = device(&mock, "tos-vismacli08").unwrap();
+    mock.edit(
+        "dcim/devices",
+        created["id"].as_u64().unwrap(),
+        json!({ "comments": "moved to the lab" }),
+    );
+
+    let output = run(&config, &["undo", &run_id, "--apply"]).await;
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.lines().any(|l| l.starts_with("skip")
+            && l.contains("tos-vismacli08")
+            && l.ends_with("(changed since the run)")),
+        "{}",
+        stdout
+    );
+    assert!(device(&mock, "tos-vismacli08").is_some());
+    assert_eq!(device(&mock, "JAKOB-DESKTOP").unwrap()["serial"], "OLD");
+}
+
+#[tokio::test]
+async fn undo_within_the_deletion_limit_only() {
+    let mock = Mock::start().await;
+    seed(&mock);
+    let dir = test_dir("undo_limited");
+    let (config, run_id) = sync(&mock, &dir, "[delete]\nmax_per_run = 1").await;
+
+    let output = output(&config, &["undo", &run_id, "--apply"]).await;
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Refusing to delete"), "{}", stderr);
+    assert!(mock.request_log().iter().all(|r| r.method == "GET"));
+}
+
+#[tokio::test]
+async fn deletions_without_pre_change_data_are_not_recreated() {
+    let mock = Mock::start().await;
+    let run_id = "20240101T000000Z-abcdef";
+    mock.insert(
+        "core/object-changes",
+        json!({
+            "time": "2024-01-01T00:00:00Z",
+            "action": { "value": "delete" },
+            "changed_object_type": "dcim.device",
+            "changed_object_id": 41,
+            "object_repr": "pc01",
+            "message": format!("netbox-ingester run {}", run_id),
+            "prechange_data": null,
+            "postchange_data": null,
+        }),
+    );
+    let dir = test_dir("undo_no_prechange");
+    let config = mock.config(&dir, "");
+
+    let output = run(&config, &["undo", run_id, "--apply"]).await;
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.lines().any(|l| l.starts_with("skip")
+            && l.contains("pc01")
+            && l.ends_with("(no pre-change data to recreate from)")),
+        "{}",
+        stdout
+    );
+    assert!(mock.request_log().iter().all(|r| r.method == "GET"));
+}
+
+#[tokio::test]
+async fn undo_of_an_unknown_run_fails() {
+    let mock = Mock::start().await;
+    let dir = test_dir("undo_unknown");
+    let config = mock.config(&dir, "");
+
+    let output = output(&config, &["undo", "20240101T000000Z-000000"]).await;
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("No changes recorded for run 20240101T000000Z-000000"),
+        "{}",
+        stderr
+    );
+}
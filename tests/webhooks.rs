@@ -0,0 +1,260 @@
+mod common;
+
+use common::{test_dir, Mock};
+use hmac::{Hmac, Mac};
+use serde_json::{json, Value};
+use sha2::{Sha256, Sha512};
+use std::{path::Path, process::Stdio, time::Duration};
+use tokio::process::{Child, Command};
+
+const NETBOX_SECRET: &str = "netbox-hook-secret";
+const EVENTS_SECRET: &str = "events-secret";
+
+struct Daemon {
+    url: String,
+    _child: Child,
+}
+
+// Starts the daemon and waits until its first cycle wrote the report, or
+// fetched every source in a dry run
+async fn daemon(mock: &Mock, dir: &Path, dry_run: bool) -> Daemon {
+    let addr = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let config = mock.config(
+        dir,
+        &format!(
+            r#"
+[daemon]
+listen = "{addr}"
+
+[webhooks.netbox]
+secret = "{NETBOX_SECRET}"
+
+[webhooks.events]
+secret = "{EVENTS_SECRET}"
+"#
+        ),
+    );
+    let child = Command::new(env!("CARGO_BIN_EXE_netbox_ingester"))
+        .arg("--config")
+        .arg(&config)
+        .args(dry_run.then_some("--dry-run"))
+        .arg("daemon")
+        .env_remove("RUST_LOG")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+        .unwrap();
+    let url = format!("http://{}", addr);
+    for _ in 0..100 {
+        let ready = if dry_run {
+            fetched_sources(&url).await == 3
+        } else {
+            dir.join("report.json").exists()
+        };
+        if ready {
+            return Daemon { url, _child: child };
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("the daemon never finished its first cycle");
+}
+
+async fn fetched_sources(url: &str) -> usize {
+    let Ok(response) = reqwest::get(format!("{}/health", url)).await else {
+        return 0;
+    };
+    let health: Value = response.json().await.unwrap_or_default();
+    health.as_object().map_or(0, |sources| sources.len())
+}
+
+fn sign<M: Mac + hmac::digest::KeyInit>(secret: &str, body: &[u8]) -> String {
+    let mut mac = <M as Mac>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(body);
+    hex::encode(mac.finalize().into_bytes())
+}
+
+async fn netbox_hook(daemon: &Daemon, payload: &Value) -> reqwest::Response {
+    let body = serde_json::to_vec(payload).unwrap();
+    reqwest::Client::new()
+        .post(format!("{}/webhooks/netbox", daemon.url))
+        .header(
+            "X-Hook-Signature",
+            sign::<Hmac<Sha512>>(NETBOX_SECRET, &body),
+        )
+        .body(body)
+        .send()
+        .await
+        .unwrap()
+}
+
+async fn event(daemon: &Daemon, payload: &Value) -> reqwest::Response {
+    let body = serde_json::to_vec(payload).unwrap();
+    reqwest::Client::new()
+        .post(format!("{}/webhooks/sync", daemon.url))
+        .header(
+            "X-Signature-256",
+            format!("sha256={}", sign::<Hmac<Sha256>>(EVENTS_SECRET, &body)),
+        )
+        .body(body)
+        .send()
+        .await
+        .unwrap()
+}
+
+// The device as NetBox serializes it in a webhook
+async fn netbox_device(mock: &Mock, name: &str) -> Value {
+    let id = mock
+        .objects("dcim/devices")
+        .into_iter()
+        .find(|d| d["name"] == name)
+        .unwrap()["id"]
+        .clone();
+    reqwest::Client::new()
+        .get(format!("{}/netbox/api/dcim/devices/{}/", mock.url, id))
+        .header("Authorization", "Token netbox-token")
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn unsigned_payloads_are_rejected() {
+    let mock = Mock::start().await;
+    let dir = test_dir("webhooks_unsigned");
+    let daemon = daemon(&mock, &dir, false).await;
+    mock.clear_requests();
+
+    let client = reqwest::Client::new();
+    let unsigned = client
+        .post(format!("{}/webhooks/sync", daemon.url))
+        .body(r#"{"device":"JAKOB-DESKTOP"}"#)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(unsigned.status(), 401);
+    let wrong_key = client
+        .post(format!("{}/webhooks/netbox", daemon.url))
+        .header("X-Hook-Signature", sign::<Hmac<Sha512>>("guess", b"{}"))
+        .body("{}")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(wrong_key.status(), 401);
+    assert!(mock.request_log().iter().all(|r| r.method == "GET"));
+}
+
+#[tokio::test]
+async fn a_netbox_edit_is_reverted_by_the_next_event() {
+    let mock = Mock::start().await;
+    let dir = test_dir("webhooks_edit_reverted");
+    let daemon = daemon(&mock, &dir, false).await;
+    let device = netbox_device(&mock, "JAKOB-DESKTOP").await;
+    let (id, serial) = (device["id"].as_u64().unwrap(), device["serial"].clone());
+
+    mock.edit("dcim/devices", id, json!({ "serial": "EDITED" }));
+    let edited = netbox_device(&mock, "JAKOB-DESKTOP").await;
+    let hook = netbox_hook(
+        &daemon,
+        &json!({ "event": "updated", "model": "device", "data": edited }),
+    )
+    .await;
+    assert_eq!(hook.status(), 204);
+    mock.clear_requests();
+
+    let synced = event(&daemon, &json!({ "device": "jakob-desktop " })).await;
+
+    assert_eq!(synced.status(), 200);
+    let synced: Value = synced.json().await.unwrap();
+    assert_eq!(synced["device"], "jakob-desktop");
+    assert_eq!(synced["objects"]["dcim/devices"]["updated"], 1);
+    let writes: Vec<_> = mock
+        .request_log()
+        .into_iter()
+        .filter(|r| r.method != "GET")
+        .collect();
+    assert_eq!(writes.len(), 1, "{:?}", writes);
+    assert_eq!(writes[0].method, "PATCH");
+    assert_eq!(
+        writes[0].request_id.as_ref(),
+        synced["run_id"].as_str().map(String::from).as_ref()
+    );
+    let device = netbox_device(&mock, "JAKOB-DESKTOP").await;
+    assert_eq!(device["serial"], serial);
+}
+
+#[tokio::test]
+async fn virtual_machine_hooks_refresh_the_cache() {
+    let mock = Mock::start().await;
+    let dir = test_dir("webhooks_virtual_machine");
+    let daemon = daemon(&mock, &dir, false).await;
+    // as NetBox serializes a virtual machine, related objects nested
+    let vm = json!({
+        "id": 7,
+        "name": "vm01",
+        "status": { "value": "active", "label": "Active" },
+        "site": { "id": 1, "name": "Server room", "slug": "server-room" },
+        "role": null,
+        "platform": { "id": 2, "name": "Linux", "slug": "linux" },
+        "vcpus": 2.0,
+        "memory": 4096,
+        "disk": 40,
+        "tags": [],
+    });
+
+    for event in ["created", "updated", "deleted"] {
+        let hook = netbox_hook(
+            &daemon,
+            &json!({ "event": event, "model": "virtualmachine", "data": vm }),
+        )
+        .await;
+        assert_eq!(
+            hook.status(),
+            204,
+            "{}: {}",
+            event,
+            hook.text().await.unwrap()
+        );
+    }
+}
+
+#[tokio::test]
+async fn an_event_for_an_unknown_device_is_not_found() {
+    let mock = Mock::start().await;
+    let dir = test_dir("webhooks_unknown_device");
+    let daemon = daemon(&mock, &dir, false).await;
+    mock.clear_requests();
+
+    let response = event(
+        &daemon,
+        &json!({ "device": "nowhere", "source": "fortigate" }),
+    )
+    .await;
+
+    assert_eq!(response.status(), 404);
+    assert!(mock.request_log().iter().all(|r| r.method == "GET"));
+}
+
+#[tokio::test]
+async fn a_dry_run_daemon_only_plans() {
+    let mock = Mock::start().await;
+    let dir = test_dir("webhooks_dry_run");
+    let daemon = daemon(&mock, &dir, true).await;
+
+    // named like the source's FQDN, the policy strips example.com
+    let planned = event(&daemon, &json!({ "device": "JAKOB-DESKTOP.Example.com" })).await;
+
+    assert_eq!(planned.status(), 200);
+    let planned: Value = planned.json().await.unwrap();
+    assert_eq!(planned["device"], "jakob-desktop");
+    assert_eq!(planned["plan"]["action"], "create");
+    assert!(mock.request_log().iter().all(|r| r.method == "GET"));
+    assert!(mock.objects("dcim/devices").is_empty());
+    assert!(!dir.join("report.json").exists());
+}